@@ -0,0 +1,594 @@
+//! Chapter markers from ID3v2 `CHAP` frames and from MP4 files, either a
+//! Nero `chpl` atom or a QuickTime chapter text track. lofty reads neither,
+//! so the relevant structures are parsed here.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on the `moov` atom and ID3v2 tag read into memory.
+const MAX_HEADER_BYTES: u64 = 64 * 1024 * 1024;
+const MAX_TITLE_BYTES: u64 = 1024;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub title: String,
+    /// Seconds from the start of the track.
+    pub start: f64,
+    /// `None` when the chapter runs to the end of a track of unknown length.
+    pub end: Option<f64>,
+}
+
+/// Chapters of an audio file in start order, or an empty list when it has
+/// none or they cannot be read.
+pub fn read(path: &Path, duration: Option<f64>) -> Vec<Chapter> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let starts = match ext.as_deref() {
+        Some("m4a" | "m4b" | "mp4" | "aac" | "alac") => read_mp4(path),
+        Some("mp3") => read_id3(path),
+        _ => return Vec::new(),
+    };
+    match starts {
+        Ok(starts) => finish(starts, duration),
+        Err(err) => {
+            tracing::debug!(?err, path = %path.display(), "failed to read chapters");
+            Vec::new()
+        }
+    }
+}
+
+/// Sorts chapter starts and lets each one end where the next begins.
+fn finish(mut starts: Vec<(f64, Option<f64>, String)>, duration: Option<f64>) -> Vec<Chapter> {
+    starts.retain(|(start, _, _)| start.is_finite() && *start >= 0.0);
+    starts.sort_by(|a, b| a.0.total_cmp(&b.0));
+    starts.dedup_by(|b, a| a.0 == b.0);
+    let next: Vec<Option<f64>> = starts
+        .iter()
+        .skip(1)
+        .map(|(start, _, _)| Some(*start))
+        .chain(std::iter::once(duration))
+        .collect();
+    starts
+        .into_iter()
+        .zip(next)
+        .enumerate()
+        .map(|(i, ((start, end, title), next))| Chapter {
+            title: if title.is_empty() {
+                format!("Chapter {}", i + 1)
+            } else {
+                title
+            },
+            start,
+            end: end.filter(|e| *e > start).or(next),
+        })
+        .collect()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn synchsafe(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0, |acc, b| (acc << 7) | u32::from(b & 0x7f))
+}
+
+/// `(start, end, title)` of every `CHAP` frame in the ID3v2 tag at the
+/// start of the file.
+fn read_id3(path: &Path) -> io::Result<Vec<(f64, Option<f64>, String)>> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header)?;
+    if &header[..3] != b"ID3" {
+        return Ok(Vec::new());
+    }
+    let version = header[3];
+    if !(3..=4).contains(&version) {
+        return Ok(Vec::new());
+    }
+    let size = u64::from(synchsafe(&header[6..10]));
+    if size > MAX_HEADER_BYTES {
+        return Err(invalid("ID3v2 tag too large"));
+    }
+    let mut tag = vec![0u8; size as usize];
+    file.read_exact(&mut tag)?;
+    let flags = header[5];
+    if flags & 0x80 != 0 {
+        tag = resynchronise(&tag);
+    }
+    let mut pos = 0;
+    if flags & 0x40 != 0 {
+        let ext = be_u32(&tag, 0).ok_or_else(|| invalid("truncated extended header"))?;
+        pos = if version == 4 {
+            synchsafe(&tag[..4]) as usize
+        } else {
+            ext as usize + 4
+        };
+    }
+    let mut chapters = Vec::new();
+    for (id, body) in id3_frames(&tag[pos.min(tag.len())..], version) {
+        if &id != b"CHAP" {
+            continue;
+        }
+        let Some(nul) = body.iter().position(|b| *b == 0) else {
+            continue;
+        };
+        let times = &body[nul + 1..];
+        let (Some(start), Some(end)) = (be_u32(times, 0), be_u32(times, 4)) else {
+            continue;
+        };
+        let title = times
+            .get(16..)
+            .map(|sub| {
+                id3_frames(sub, version)
+                    .find(|(id, _)| id == b"TIT2")
+                    .map(|(_, text)| id3_text(text))
+                    .unwrap_or_default()
+            })
+            .unwrap_or_default();
+        let end = (end != u32::MAX).then(|| f64::from(end) / 1000.0);
+        chapters.push((f64::from(start) / 1000.0, end, title));
+    }
+    Ok(chapters)
+}
+
+/// Undoes ID3v2 unsynchronisation, which inserts a zero after every 0xFF.
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = 0u8;
+    for &b in data {
+        if !(prev == 0xff && b == 0) {
+            out.push(b);
+        }
+        prev = b;
+    }
+    out
+}
+
+/// Frame IDs and bodies in a run of ID3v2.3/2.4 frames.
+fn id3_frames(data: &[u8], version: u8) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 10)?;
+        if header[0] == 0 {
+            return None;
+        }
+        let id: [u8; 4] = header[..4].try_into().ok()?;
+        let size = if version == 4 {
+            synchsafe(&header[4..8])
+        } else {
+            be_u32(header, 4)?
+        } as usize;
+        let body = data.get(pos + 10..pos + 10 + size)?;
+        pos += 10 + size;
+        Some((id, body))
+    })
+}
+
+/// An ID3v2 text frame body: an encoding byte followed by the text.
+fn id3_text(body: &[u8]) -> String {
+    let Some((&encoding, text)) = body.split_first() else {
+        return String::new();
+    };
+    let text = match encoding {
+        0 => text.iter().map(|&b| char::from(b)).collect(),
+        1 | 2 => decode_utf16(text, encoding == 2),
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+    text.trim_end_matches('\0').trim().to_string()
+}
+
+/// UTF-16 text, honouring a byte order mark when there is one.
+fn decode_utf16(data: &[u8], mut big_endian: bool) -> String {
+    let mut data = data;
+    match data {
+        [0xfe, 0xff, rest @ ..] => {
+            big_endian = true;
+            data = rest;
+        }
+        [0xff, 0xfe, rest @ ..] => {
+            big_endian = false;
+            data = rest;
+        }
+        _ => {}
+    }
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| {
+            if big_endian {
+                u16::from_be_bytes([c[0], c[1]])
+            } else {
+                u16::from_le_bytes([c[0], c[1]])
+            }
+        })
+        .take_while(|u| *u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Child atoms of an in-memory MP4 atom body, as `(type, body)`.
+fn atoms(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let size = be_u32(data, pos)? as usize;
+        let kind: [u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, data.len() - pos),
+            1 => (16, usize::try_from(be_u64(data, pos + 8)?).ok()?),
+            n => (8, n),
+        };
+        if size < header {
+            return None;
+        }
+        let body = data.get(pos + header..pos + size)?;
+        pos += size;
+        Some((kind, body))
+    })
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    atoms(data).find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+/// Reads the `moov` atom of an MP4 file into memory.
+fn read_moov(file: &mut File) -> io::Result<Vec<u8>> {
+    let len = file.metadata()?.len();
+    let mut pos = 0u64;
+    while pos + 8 <= len {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8])?;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (8, len - pos),
+            1 => {
+                file.read_exact(&mut header[8..])?;
+                (16, u64::from_be_bytes(header[8..].try_into().unwrap()))
+            }
+            n => (8, u64::from(n)),
+        };
+        if size < header_len {
+            break;
+        }
+        if &kind == b"moov" {
+            if size > MAX_HEADER_BYTES {
+                return Err(invalid("moov atom too large"));
+            }
+            let mut body = vec![0u8; (size - header_len) as usize];
+            file.read_exact(&mut body)?;
+            return Ok(body);
+        }
+        pos += size;
+    }
+    Err(invalid("no moov atom"))
+}
+
+fn read_mp4(path: &Path) -> io::Result<Vec<(f64, Option<f64>, String)>> {
+    let mut file = File::open(path)?;
+    let moov = read_moov(&mut file)?;
+    let nero = child(&moov, b"udta")
+        .and_then(|udta| child(udta, b"chpl"))
+        .map(nero_chapters)
+        .unwrap_or_default();
+    if !nero.is_empty() {
+        return Ok(nero);
+    }
+    quicktime_chapters(&moov, &mut file)
+}
+
+/// A Nero `chpl` atom: a count followed by 100 ns start times and titles.
+fn nero_chapters(body: &[u8]) -> Vec<(f64, Option<f64>, String)> {
+    let Some(&version) = body.first() else {
+        return Vec::new();
+    };
+    let mut pos = if version == 0 { 4 } else { 8 };
+    let Some(&count) = body.get(pos) else {
+        return Vec::new();
+    };
+    pos += 1;
+    let mut chapters = Vec::new();
+    for _ in 0..count {
+        let (Some(start), Some(&len)) = (be_u64(body, pos), body.get(pos + 8)) else {
+            break;
+        };
+        let Some(title) = body.get(pos + 9..pos + 9 + usize::from(len)) else {
+            break;
+        };
+        chapters.push((
+            start as f64 / 10_000_000.0,
+            None,
+            String::from_utf8_lossy(title).trim().to_string(),
+        ));
+        pos += 9 + usize::from(len);
+    }
+    chapters
+}
+
+/// Follows `tref/chap` from an audio track to its text track and reads one
+/// title per sample.
+fn quicktime_chapters(moov: &[u8], file: &mut File) -> io::Result<Vec<(f64, Option<f64>, String)>> {
+    let traks: Vec<&[u8]> = atoms(moov)
+        .filter(|(k, _)| k == b"trak")
+        .map(|(_, body)| body)
+        .collect();
+    let Some(chapter_id) = traks.iter().find_map(|trak| {
+        let chap = child(child(trak, b"tref")?, b"chap")?;
+        be_u32(chap, 0)
+    }) else {
+        return Ok(Vec::new());
+    };
+    let Some(trak) = traks.iter().find(|trak| track_id(trak) == Some(chapter_id)) else {
+        return Ok(Vec::new());
+    };
+    let mdia = child(trak, b"mdia").ok_or_else(|| invalid("missing mdia"))?;
+    let timescale = child(mdia, b"mdhd")
+        .and_then(|mdhd| {
+            let at = if mdhd.first() == Some(&1) { 20 } else { 12 };
+            be_u32(mdhd, at)
+        })
+        .filter(|t| *t > 0)
+        .ok_or_else(|| invalid("missing timescale"))?;
+    let stbl = child(mdia, b"minf")
+        .and_then(|minf| child(minf, b"stbl"))
+        .ok_or_else(|| invalid("missing stbl"))?;
+    let starts = sample_times(stbl).ok_or_else(|| invalid("bad stts"))?;
+    let offsets = sample_offsets(stbl).ok_or_else(|| invalid("bad sample tables"))?;
+    let mut chapters = Vec::new();
+    for (start, offset) in starts.into_iter().zip(offsets) {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut len = [0u8; 2];
+        file.read_exact(&mut len)?;
+        let len = u64::from(u16::from_be_bytes(len)).min(MAX_TITLE_BYTES);
+        let mut text = vec![0u8; len as usize];
+        file.read_exact(&mut text)?;
+        let title = if text.starts_with(&[0xfe, 0xff]) || text.starts_with(&[0xff, 0xfe]) {
+            decode_utf16(&text, true)
+        } else {
+            String::from_utf8_lossy(&text).into_owned()
+        };
+        chapters.push((
+            start as f64 / f64::from(timescale),
+            None,
+            title.trim().to_string(),
+        ));
+    }
+    Ok(chapters)
+}
+
+fn track_id(trak: &[u8]) -> Option<u32> {
+    let tkhd = child(trak, b"tkhd")?;
+    let at = if tkhd.first() == Some(&1) { 20 } else { 12 };
+    be_u32(tkhd, at)
+}
+
+/// Start of every sample in media time units, from `stts`.
+fn sample_times(stbl: &[u8]) -> Option<Vec<u64>> {
+    let stts = child(stbl, b"stts")?;
+    let count = be_u32(stts, 4)? as usize;
+    let mut times = Vec::new();
+    let mut now = 0u64;
+    for i in 0..count {
+        let samples = be_u32(stts, 8 + i * 8)?;
+        let delta = be_u32(stts, 12 + i * 8)?;
+        for _ in 0..samples.min(10_000) {
+            times.push(now);
+            now += u64::from(delta);
+        }
+    }
+    Some(times)
+}
+
+/// File offset of every sample, from `stsc`, `stco`/`co64` and `stsz`.
+fn sample_offsets(stbl: &[u8]) -> Option<Vec<u64>> {
+    let chunks: Vec<u64> = if let Some(stco) = child(stbl, b"stco") {
+        let count = be_u32(stco, 4)? as usize;
+        (0..count)
+            .map(|i| be_u32(stco, 8 + i * 4).map(u64::from))
+            .collect::<Option<_>>()?
+    } else {
+        let co64 = child(stbl, b"co64")?;
+        let count = be_u32(co64, 4)? as usize;
+        (0..count)
+            .map(|i| be_u64(co64, 8 + i * 8))
+            .collect::<Option<_>>()?
+    };
+    let stsz = child(stbl, b"stsz")?;
+    let fixed = be_u32(stsz, 4)?;
+    let sample_count = be_u32(stsz, 8)? as usize;
+    let size = |i: usize| {
+        if fixed != 0 {
+            Some(u64::from(fixed))
+        } else {
+            be_u32(stsz, 12 + i * 4).map(u64::from)
+        }
+    };
+    let stsc = child(stbl, b"stsc")?;
+    let runs = be_u32(stsc, 4)? as usize;
+    let mut offsets = Vec::new();
+    let mut sample = 0;
+    for run in 0..runs {
+        let first = be_u32(stsc, 8 + run * 12)? as usize;
+        let per_chunk = be_u32(stsc, 12 + run * 12)? as usize;
+        let last = match be_u32(stsc, 20 + run * 12) {
+            Some(next) if run + 1 < runs => next as usize,
+            _ => chunks.len() + 1,
+        };
+        for chunk in first.max(1)..last {
+            let mut offset = *chunks.get(chunk - 1)?;
+            for _ in 0..per_chunk {
+                if sample >= sample_count {
+                    return Some(offsets);
+                }
+                offsets.push(offset);
+                offset += size(sample)?;
+                sample += 1;
+            }
+        }
+    }
+    Some(offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::atom;
+    use std::io::Write;
+
+    fn frame(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn chap(id: &str, start: u32, end: u32, title: &str) -> Vec<u8> {
+        let mut body = id.as_bytes().to_vec();
+        body.push(0);
+        for v in [start, end, u32::MAX, u32::MAX] {
+            body.extend_from_slice(&v.to_be_bytes());
+        }
+        let mut text = vec![3];
+        text.extend_from_slice(title.as_bytes());
+        body.extend(frame(b"TIT2", &text));
+        frame(b"CHAP", &body)
+    }
+
+    #[test]
+    fn reads_id3_chap_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.mp3");
+        let mut frames = chap("ch1", 65_000, 130_000, "Two");
+        frames.extend(chap("ch0", 0, 65_000, "One"));
+        frames.extend(frame(b"TIT2", b"\x03Book"));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"ID3\x03\x00\x00").unwrap();
+        let size = frames.len() as u32;
+        let safe: Vec<u8> = (0..4)
+            .rev()
+            .map(|i| ((size >> (7 * i)) & 0x7f) as u8)
+            .collect();
+        file.write_all(&safe).unwrap();
+        file.write_all(&frames).unwrap();
+        drop(file);
+
+        let chapters = read(&path, Some(200.0));
+        assert_eq!(
+            chapters,
+            vec![
+                Chapter {
+                    title: "One".into(),
+                    start: 0.0,
+                    end: Some(65.0)
+                },
+                Chapter {
+                    title: "Two".into(),
+                    start: 65.0,
+                    end: Some(130.0)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_nero_chapters() {
+        let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, 2];
+        for (start, title) in [(0u64, "Intro"), (300_000_000, "")] {
+            chpl.extend_from_slice(&start.to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        let moov = atom(b"moov", &atom(b"udta", &atom(b"chpl", &chpl)));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mix.m4b");
+        let mut data = atom(b"ftyp", b"M4B \0\0\0\0");
+        data.extend(moov);
+        std::fs::write(&path, data).unwrap();
+
+        let chapters = read(&path, None);
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title, "Intro");
+        assert_eq!(chapters[0].end, Some(30.0));
+        assert_eq!(chapters[1].title, "Chapter 2");
+        assert_eq!(chapters[1].start, 30.0);
+        assert_eq!(chapters[1].end, None);
+    }
+
+    #[test]
+    fn reads_quicktime_chapter_tracks() {
+        fn full(body: &[u8]) -> Vec<u8> {
+            let mut out = vec![0, 0, 0, 0];
+            out.extend_from_slice(body);
+            out
+        }
+        fn words(values: &[u32]) -> Vec<u8> {
+            values.iter().flat_map(|v| v.to_be_bytes()).collect()
+        }
+        let titles = [&b"\x00\x05Start"[..], &b"\x00\x03End"[..]];
+        let mut mdat = Vec::new();
+        for t in titles {
+            mdat.extend_from_slice(t);
+        }
+        let ftyp = atom(b"ftyp", b"M4A \0\0\0\0");
+        let mdat_atom = atom(b"mdat", &mdat);
+        let text_start = (ftyp.len() + 8) as u32;
+
+        let audio = atom(
+            b"trak",
+            &[
+                atom(b"tkhd", &full(&words(&[0, 0, 1]))),
+                atom(b"tref", &atom(b"chap", &words(&[2]))),
+            ]
+            .concat(),
+        );
+        let stbl = [
+            atom(b"stts", &full(&words(&[2, 1, 5000, 1, 1000]))),
+            atom(b"stsc", &full(&words(&[1, 1, 2, 1]))),
+            atom(b"stsz", &full(&words(&[0, 2, 7, 5]))),
+            atom(b"stco", &full(&words(&[1, text_start]))),
+        ]
+        .concat();
+        let text = atom(
+            b"trak",
+            &[
+                atom(b"tkhd", &full(&words(&[0, 0, 2]))),
+                atom(
+                    b"mdia",
+                    &[
+                        atom(b"mdhd", &full(&words(&[0, 0, 1000, 6000]))),
+                        atom(b"minf", &atom(b"stbl", &stbl)),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        );
+        let moov = atom(b"moov", &[audio, text].concat());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.m4a");
+        std::fs::write(&path, [ftyp, mdat_atom, moov].concat()).unwrap();
+
+        let chapters = read(&path, Some(6.0));
+        let got: Vec<_> = chapters
+            .iter()
+            .map(|c| (c.title.as_str(), c.start, c.end))
+            .collect();
+        assert_eq!(
+            got,
+            vec![("Start", 0.0, Some(5.0)), ("End", 5.0, Some(6.0))]
+        );
+    }
+}
@@ -0,0 +1,589 @@
+//! CUE sheets: parsing them, and cutting one track out of a single-file
+//! album rip without decoding it.
+//!
+//! WAV rips are cut to the exact sample. FLAC rips are cut at the frame
+//! boundaries around the requested range (a few milliseconds at most) and
+//! get a fresh STREAMINFO header, so players see a standalone stream.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// CD frames per second, the unit of `INDEX` times.
+const CD_FRAMES_PER_SEC: f64 = 75.0;
+/// How far to look for the next FLAC frame header before giving up.
+const MAX_FRAME_SEARCH: u64 = 1024 * 1024;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub files: Vec<CueFile>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueFile {
+    /// As written in the sheet, relative to the sheet's folder.
+    pub name: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Seconds from the start of the file (`INDEX 01`).
+    pub start: f64,
+}
+
+/// Decodes a sheet as UTF-8, falling back to Latin-1 which older rippers
+/// write.
+pub fn decode(data: &[u8]) -> String {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    match std::str::from_utf8(data) {
+        Ok(text) => text.to_string(),
+        Err(_) => data.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+pub fn parse(text: &str) -> CueSheet {
+    let mut sheet = CueSheet::default();
+    for line in text.lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let file = sheet.files.last_mut();
+        let track = file.and_then(|f| f.tracks.last_mut());
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => sheet.files.push(CueFile {
+                name: file_name(rest),
+                tracks: Vec::new(),
+            }),
+            "TRACK" => {
+                let number = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(0);
+                let is_audio = rest.to_ascii_uppercase().ends_with("AUDIO");
+                if let Some(file) = sheet.files.last_mut()
+                    && is_audio
+                {
+                    file.tracks.push(CueTrack {
+                        number,
+                        start: f64::NAN,
+                        ..CueTrack::default()
+                    });
+                }
+            }
+            "TITLE" => match track {
+                Some(track) => track.title = text_value(rest),
+                None => sheet.title = text_value(rest),
+            },
+            "PERFORMER" => match track {
+                Some(track) => track.performer = text_value(rest),
+                None => sheet.performer = text_value(rest),
+            },
+            "INDEX" => {
+                let mut parts = rest.split_whitespace();
+                if parts.next().and_then(|n| n.parse::<u32>().ok()) == Some(1)
+                    && let Some(time) = parts.next().and_then(parse_time)
+                    && let Some(track) = track
+                {
+                    track.start = time;
+                }
+            }
+            "REM" => {
+                let (key, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                match key.to_ascii_uppercase().as_str() {
+                    "GENRE" => sheet.genre = text_value(value),
+                    "DATE" => {
+                        sheet.year = value
+                            .trim()
+                            .get(..4)
+                            .and_then(|y| y.parse().ok())
+                            .filter(|y| *y > 0)
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    // Tracks without an INDEX 01 cannot be placed.
+    for file in &mut sheet.files {
+        file.tracks.retain(|t| t.start.is_finite());
+    }
+    sheet
+}
+
+/// Strips the quotes and the trailing file type from a `FILE` argument.
+fn file_name(rest: &str) -> String {
+    if let Some(quoted) = rest.strip_prefix('"')
+        && let Some((name, _)) = quoted.split_once('"')
+    {
+        return name.to_string();
+    }
+    match rest.rsplit_once(char::is_whitespace) {
+        Some((name, _)) => name.trim().to_string(),
+        None => rest.to_string(),
+    }
+}
+
+fn text_value(rest: &str) -> Option<String> {
+    let value = rest.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// `mm:ss:ff` with 75 frames per second.
+fn parse_time(value: &str) -> Option<f64> {
+    let mut parts = value.split(':').map(|p| p.parse::<u32>().ok());
+    let (m, s, f) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || s >= 60 || f >= 75 {
+        return None;
+    }
+    Some(f64::from(m) * 60.0 + f64::from(s) + f64::from(f) / CD_FRAMES_PER_SEC)
+}
+
+/// Whether tracks can be cut out of this kind of file.
+pub fn can_slice(path: &Path) -> bool {
+    slice_format(path).is_some()
+}
+
+#[derive(Clone, Copy)]
+enum SliceFormat {
+    Flac,
+    Wav,
+}
+
+fn slice_format(path: &Path) -> Option<SliceFormat> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "flac" => Some(SliceFormat::Flac),
+        "wav" => Some(SliceFormat::Wav),
+        _ => None,
+    }
+}
+
+/// A track cut from a rip: a new header followed by `start..end` of the
+/// original file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slice {
+    pub header: Vec<u8>,
+    pub start: u64,
+    pub end: u64,
+    pub content_type: &'static str,
+}
+
+impl Slice {
+    pub fn size(&self) -> u64 {
+        self.header.len() as u64 + (self.end - self.start)
+    }
+}
+
+/// Works out how to serve `start..end` seconds of a rip.
+pub fn plan_slice(path: &Path, start: f64, end: Option<f64>) -> io::Result<Slice> {
+    let mut file = File::open(path)?;
+    match slice_format(path) {
+        Some(SliceFormat::Flac) => flac_slice(&mut file, start, end),
+        Some(SliceFormat::Wav) => wav_slice(&mut file, start, end),
+        None => Err(invalid("unsupported format")),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn to_samples(seconds: f64, rate: u32) -> u64 {
+    (seconds.max(0.0) * f64::from(rate)).round() as u64
+}
+
+fn wav_slice(file: &mut File, start: f64, end: Option<f64>) -> io::Result<Slice> {
+    let file_len = file.metadata()?.len();
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff)?;
+    if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+    let mut fmt = None;
+    let mut pos = 12u64;
+    let (data_start, data_len) = loop {
+        let mut chunk = [0u8; 8];
+        file.read_exact(&mut chunk)?;
+        let size = u64::from(u32::from_le_bytes(chunk[4..].try_into().unwrap()));
+        pos += 8;
+        match &chunk[..4] {
+            b"fmt " if size <= 1024 => {
+                let mut data = vec![0u8; size as usize];
+                file.read_exact(&mut data)?;
+                fmt = Some(data);
+            }
+            b"data" => break (pos, size.min(file_len.saturating_sub(pos))),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        pos += size + (size & 1);
+        file.seek(SeekFrom::Start(pos))?;
+    };
+    let fmt = fmt
+        .filter(|f| f.len() >= 16)
+        .ok_or_else(|| invalid("missing fmt chunk"))?;
+    let rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+    let align = u64::from(u16::from_le_bytes(fmt[12..14].try_into().unwrap()));
+    if rate == 0 || align == 0 {
+        return Err(invalid("bad fmt chunk"));
+    }
+    let frames = data_len / align;
+    let first = to_samples(start, rate).min(frames);
+    let last = end
+        .map_or(frames, |e| to_samples(e, rate).min(frames))
+        .max(first);
+    let len = (last - first) * align;
+
+    let mut header = Vec::with_capacity(28 + fmt.len());
+    header.extend_from_slice(b"RIFF");
+    let riff_len = 4 + 8 + fmt.len() as u64 + (fmt.len() as u64 & 1) + 8 + len;
+    header.extend_from_slice(&(riff_len.min(u64::from(u32::MAX)) as u32).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+    header.extend_from_slice(&fmt);
+    if fmt.len() & 1 == 1 {
+        header.push(0);
+    }
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&(len.min(u64::from(u32::MAX)) as u32).to_le_bytes());
+    Ok(Slice {
+        header,
+        start: data_start + first * align,
+        end: data_start + last * align,
+        content_type: "audio/wav",
+    })
+}
+
+struct FlacInfo {
+    streaminfo: [u8; 34],
+    channels: u8,
+    sample_rate: u32,
+    /// Samples per frame for fixed-blocksize streams.
+    block_size: u64,
+    total_samples: u64,
+    audio_start: u64,
+    file_len: u64,
+}
+
+fn flac_info(file: &mut File) -> io::Result<FlacInfo> {
+    let file_len = file.metadata()?.len();
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)?;
+    if &magic != b"fLaC" {
+        return Err(invalid("not a FLAC file"));
+    }
+    let mut streaminfo = None;
+    let mut pos = 4u64;
+    loop {
+        let mut block = [0u8; 4];
+        file.read_exact(&mut block)?;
+        let len = u64::from(u32::from_be_bytes([0, block[1], block[2], block[3]]));
+        pos += 4;
+        if block[0] & 0x7f == 0 && len == 34 {
+            let mut data = [0u8; 34];
+            file.read_exact(&mut data)?;
+            streaminfo = Some(data);
+        }
+        pos += len;
+        file.seek(SeekFrom::Start(pos))?;
+        if block[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    let streaminfo = streaminfo.ok_or_else(|| invalid("missing STREAMINFO"))?;
+    let packed = u64::from_be_bytes(streaminfo[10..18].try_into().unwrap());
+    Ok(FlacInfo {
+        streaminfo,
+        channels: ((packed >> 41) & 7) as u8 + 1,
+        sample_rate: (packed >> 44) as u32,
+        block_size: u64::from(u16::from_be_bytes([streaminfo[0], streaminfo[1]])),
+        total_samples: packed & 0xF_FFFF_FFFF,
+        audio_start: pos,
+        file_len,
+    })
+}
+
+fn flac_slice(file: &mut File, start: f64, end: Option<f64>) -> io::Result<Slice> {
+    let info = flac_info(file)?;
+    if info.sample_rate == 0 {
+        return Err(invalid("bad STREAMINFO"));
+    }
+    let first_sample = to_samples(start, info.sample_rate);
+    let (start_off, start_sample) = frame_at(file, &info, first_sample)?;
+    let (end_off, end_sample) = match end.map(|e| to_samples(e, info.sample_rate)) {
+        Some(last) if info.total_samples == 0 || last < info.total_samples => {
+            let (off, sample) = frame_at(file, &info, last)?;
+            if sample >= last || off <= start_off {
+                (off.max(start_off), sample.max(start_sample))
+            } else {
+                next_frame(file, &info, off + 1)?.unwrap_or((info.file_len, info.total_samples))
+            }
+        }
+        _ => (info.file_len, info.total_samples),
+    };
+
+    let mut streaminfo = info.streaminfo;
+    let total = end_sample.saturating_sub(start_sample) & 0xF_FFFF_FFFF;
+    let packed = u64::from_be_bytes(streaminfo[10..18].try_into().unwrap());
+    let packed = (packed & !0xF_FFFF_FFFF) | total;
+    streaminfo[10..18].copy_from_slice(&packed.to_be_bytes());
+    // The checksum covered the whole rip.
+    streaminfo[18..].fill(0);
+    let mut header = b"fLaC".to_vec();
+    header.extend_from_slice(&[0x80, 0, 0, 34]);
+    header.extend_from_slice(&streaminfo);
+    Ok(Slice {
+        header,
+        start: start_off,
+        end: end_off,
+        content_type: "audio/flac",
+    })
+}
+
+/// The last frame starting at or before `sample`, found by bisecting the
+/// file on byte offsets.
+fn frame_at(file: &mut File, info: &FlacInfo, sample: u64) -> io::Result<(u64, u64)> {
+    let first = next_frame(file, info, info.audio_start)?.ok_or_else(|| invalid("no frames"))?;
+    let (mut lo, mut hi) = (first.0, info.file_len);
+    let mut best = first;
+    while lo + 1 < hi {
+        let mid = lo + (hi - lo) / 2;
+        match next_frame(file, info, mid)? {
+            Some((off, s)) if s <= sample => {
+                best = (off, s);
+                lo = mid.max(off);
+            }
+            _ => hi = mid,
+        }
+    }
+    Ok(best)
+}
+
+/// Offset and first sample of the first frame at or after `pos`.
+fn next_frame(file: &mut File, info: &FlacInfo, pos: u64) -> io::Result<Option<(u64, u64)>> {
+    const WINDOW: usize = 64 * 1024;
+    let mut buf = vec![0u8; WINDOW + 16];
+    let mut base = pos;
+    while base < info.file_len && base - pos < MAX_FRAME_SEARCH {
+        file.seek(SeekFrom::Start(base))?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match file.read(&mut buf[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        let data = &buf[..filled];
+        for i in 0..data.len().min(WINDOW) {
+            if data[i] == 0xFF
+                && let Some(sample) = frame_sample(&data[i..], info)
+            {
+                return Ok(Some((base + i as u64, sample)));
+            }
+        }
+        base += WINDOW as u64;
+    }
+    Ok(None)
+}
+
+/// Validates a frame header (including its CRC-8) and returns the number of
+/// its first sample.
+fn frame_sample(b: &[u8], info: &FlacInfo) -> Option<u64> {
+    if b.len() < 6 || b[0] != 0xFF || b[1] & 0xFE != 0xF8 {
+        return None;
+    }
+    let variable = b[1] & 1 == 1;
+    let block_code = b[2] >> 4;
+    let rate_code = b[2] & 0x0F;
+    let channel_code = b[3] >> 4;
+    let size_code = (b[3] >> 1) & 7;
+    if block_code == 0 || rate_code == 15 || channel_code > 10 || size_code == 3 || b[3] & 1 != 0 {
+        return None;
+    }
+    let channels = if channel_code < 8 {
+        channel_code + 1
+    } else {
+        2
+    };
+    if channels != info.channels {
+        return None;
+    }
+    let (number, used) = coded_number(&b[4..])?;
+    let mut len = 4 + used;
+    len += match block_code {
+        6 => 1,
+        7 => 2,
+        _ => 0,
+    };
+    len += match rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+    if b.len() <= len || crc8(&b[..len]) != b[len] {
+        return None;
+    }
+    Some(if variable {
+        number
+    } else {
+        number * info.block_size
+    })
+}
+
+/// The UTF-8-like variable-length frame or sample number.
+fn coded_number(b: &[u8]) -> Option<(u64, usize)> {
+    let first = *b.first()?;
+    let extra = match first.leading_ones() {
+        0 => return Some((u64::from(first), 1)),
+        n @ 2..=7 => n as usize - 1,
+        _ => return None,
+    };
+    let mut value = u64::from(first & (0x7F >> (extra + 1)));
+    for &byte in b.get(1..=extra)? {
+        if byte & 0xC0 != 0x80 {
+            return None;
+        }
+        value = (value << 6) | u64::from(byte & 0x3F);
+    }
+    Some((value, extra + 1))
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn parses_sheet() {
+        let text = "REM GENRE Classical\r\nREM DATE 1998\r\nPERFORMER \"Orchestra\"\r\nTITLE \"Symphonies\"\r\nFILE \"Symphonies.wav\" WAVE\r\n  TRACK 01 AUDIO\r\n    TITLE \"Allegro\"\r\n    INDEX 01 00:00:00\r\n  TRACK 02 AUDIO\r\n    TITLE \"Adagio\"\r\n    PERFORMER \"Soloist\"\r\n    INDEX 00 07:58:00\r\n    INDEX 01 08:00:37\r\n  TRACK 03 AUDIO\r\n";
+        let sheet = parse(text);
+        assert_eq!(sheet.title.as_deref(), Some("Symphonies"));
+        assert_eq!(sheet.performer.as_deref(), Some("Orchestra"));
+        assert_eq!(sheet.genre.as_deref(), Some("Classical"));
+        assert_eq!(sheet.year, Some(1998));
+        assert_eq!(sheet.files.len(), 1);
+        let file = &sheet.files[0];
+        assert_eq!(file.name, "Symphonies.wav");
+        assert_eq!(file.tracks.len(), 2);
+        assert_eq!(file.tracks[1].number, 2);
+        assert_eq!(file.tracks[1].title.as_deref(), Some("Adagio"));
+        assert_eq!(file.tracks[1].performer.as_deref(), Some("Soloist"));
+        assert!((file.tracks[1].start - (480.0 + 37.0 / 75.0)).abs() < 1e-9);
+        assert_eq!(decode(b"TITLE \"Caf\xe9\""), "TITLE \"Café\"");
+    }
+
+    fn write_wav(path: &Path, rate: u32, frames: u32) {
+        let mut file = File::create(path).unwrap();
+        let data_len = frames * 4;
+        file.write_all(b"RIFF").unwrap();
+        file.write_all(&(36 + data_len).to_le_bytes()).unwrap();
+        file.write_all(b"WAVEfmt ").unwrap();
+        file.write_all(&16u32.to_le_bytes()).unwrap();
+        file.write_all(&1u16.to_le_bytes()).unwrap();
+        file.write_all(&2u16.to_le_bytes()).unwrap();
+        file.write_all(&rate.to_le_bytes()).unwrap();
+        file.write_all(&(rate * 4).to_le_bytes()).unwrap();
+        file.write_all(&4u16.to_le_bytes()).unwrap();
+        file.write_all(&16u16.to_le_bytes()).unwrap();
+        file.write_all(b"data").unwrap();
+        file.write_all(&data_len.to_le_bytes()).unwrap();
+        file.write_all(&vec![0u8; data_len as usize]).unwrap();
+    }
+
+    #[test]
+    fn slices_wav_to_the_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rip.wav");
+        write_wav(&path, 1000, 5000);
+        let slice = plan_slice(&path, 1.5, Some(3.25)).unwrap();
+        assert_eq!(slice.start, 44 + 1500 * 4);
+        assert_eq!(slice.end, 44 + 3250 * 4);
+        assert_eq!(slice.header.len(), 44);
+        assert_eq!(&slice.header[40..44], &(1750u32 * 4).to_le_bytes());
+        assert_eq!(slice.size(), 44 + 1750 * 4);
+        let tail = plan_slice(&path, 4.0, None).unwrap();
+        assert_eq!(tail.end, 44 + 5000 * 4);
+    }
+
+    /// A fixed-blocksize FLAC stream with `frames` frames of `block`
+    /// samples. Frame bodies are filler; only the headers matter here.
+    fn write_flac(path: &Path, block: u16, frames: u32) -> Vec<u64> {
+        let mut data = b"fLaC".to_vec();
+        data.extend([0x80, 0, 0, 34]);
+        data.extend(block.to_be_bytes());
+        data.extend(block.to_be_bytes());
+        data.extend([0; 6]);
+        let total = u64::from(block) * u64::from(frames);
+        let packed: u64 = (1000 << 44) | (1 << 41) | (15 << 36) | total;
+        data.extend(packed.to_be_bytes());
+        data.extend([0xAA; 16]);
+        let mut offsets = Vec::new();
+        for n in 0..frames {
+            offsets.push(data.len() as u64);
+            // Block size from the 16-bit field, sample rate from STREAMINFO,
+            // stereo, 16 bits per sample.
+            let mut header = vec![0xFF, 0xF8, 0x70, 0x18];
+            let mut buf = [0u8; 4];
+            let number = char::from_u32(n).unwrap().encode_utf8(&mut buf);
+            header.extend_from_slice(number.as_bytes());
+            header.extend((block - 1).to_be_bytes());
+            header.push(crc8(&header));
+            data.extend(&header);
+            data.extend(vec![0x11; 300 + (n as usize % 7) * 13]);
+        }
+        std::fs::write(path, data).unwrap();
+        offsets
+    }
+
+    #[test]
+    fn slices_flac_at_frame_boundaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rip.flac");
+        let offsets = write_flac(&path, 100, 400);
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        // 2.55 s is sample 2550, inside frame 25; 10 s starts frame 100.
+        let slice = plan_slice(&path, 2.55, Some(10.0)).unwrap();
+        assert_eq!(slice.start, offsets[25]);
+        assert_eq!(slice.end, offsets[100]);
+        let packed = u64::from_be_bytes(slice.header[18..26].try_into().unwrap());
+        assert_eq!(packed & 0xF_FFFF_FFFF, 7500);
+        assert_eq!(&slice.header[26..], &[0; 16]);
+
+        let last = plan_slice(&path, 39.0, None).unwrap();
+        assert_eq!(last.start, offsets[390]);
+        assert_eq!(last.end, len);
+        let first = plan_slice(&path, 0.0, Some(0.95)).unwrap();
+        assert_eq!((first.start, first.end), (offsets[0], offsets[10]));
+    }
+}
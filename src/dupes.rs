@@ -0,0 +1,455 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::library::Track;
+
+/// How far apart two durations may be for tracks to still count as the
+/// same recording.
+pub const DEFAULT_TOLERANCE_SECS: f64 = 2.0;
+
+/// A track to compare, with the path to report and the file to read.
+pub struct Candidate {
+    pub path: String,
+    pub file: PathBuf,
+    pub track: Arc<Track>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DupeKind {
+    /// Byte-for-byte identical files.
+    Exact,
+    /// The same encoded audio with different tags.
+    Audio,
+    /// Same artist and title with nearly the same duration, such as one
+    /// album ripped in different formats.
+    Metadata,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DupeTrack {
+    pub path: String,
+    pub size: Option<u64>,
+    pub format: Option<String>,
+    pub duration: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DupeGroup {
+    pub kind: DupeKind,
+    pub tracks: Vec<DupeTrack>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DupeReport {
+    pub groups: Vec<DupeGroup>,
+    /// Files read to compare their contents; a file can be read twice.
+    pub files_hashed: usize,
+}
+
+/// Groups likely duplicates. Only files that share a size (for exact
+/// matches) or a duration (for audio matches) are read, so a library
+/// without duplicates costs little more than a walk over the metadata.
+pub fn find(candidates: &[Candidate], tolerance: f64) -> DupeReport {
+    let mut report = DupeReport::default();
+    // Virtual CUE tracks share their rip, so only real files are hashed.
+    let files: Vec<usize> = (0..candidates.len())
+        .filter(|&i| candidates[i].track.cue.is_none())
+        .collect();
+
+    let mut file_hashes: HashMap<usize, [u8; 32]> = HashMap::new();
+    let by_size = bucket(&files, |i| candidates[i].track.size);
+    for i in by_size.into_values().filter(|b| b.len() > 1).flatten() {
+        report.files_hashed += 1;
+        match hash_ranges(&candidates[i].file, None) {
+            Ok(hash) => {
+                file_hashes.insert(i, *hash.as_bytes());
+            }
+            Err(err) => {
+                tracing::debug!(?err, path = %candidates[i].file.display(), "failed to hash file")
+            }
+        }
+    }
+    let exact = bucket(&files, |i| file_hashes.get(&i).copied());
+    let mut groups: Vec<(DupeKind, Vec<usize>)> = exact
+        .into_values()
+        .filter(|g| g.len() > 1)
+        .map(|g| (DupeKind::Exact, g))
+        .collect();
+
+    let mut audio_hashes: HashMap<usize, [u8; 32]> = HashMap::new();
+    let by_duration = bucket(&files, |i| {
+        candidates[i].track.metadata.duration.map(f64::to_bits)
+    });
+    for i in by_duration.into_values().filter(|b| b.len() > 1).flatten() {
+        let file = &candidates[i].file;
+        let ranges = match audio_ranges(file) {
+            Ok(Some(ranges)) => ranges,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::debug!(?err, path = %file.display(), "failed to locate audio data");
+                continue;
+            }
+        };
+        report.files_hashed += 1;
+        if let Ok(hash) = hash_ranges(file, Some(&ranges)) {
+            audio_hashes.insert(i, *hash.as_bytes());
+        }
+    }
+    for group in bucket(&files, |i| audio_hashes.get(&i).copied()).into_values() {
+        // Identical files are already reported as exact matches.
+        let distinct: HashSet<_> = group.iter().map(|i| file_hashes.get(i)).collect();
+        if group.len() > 1 && (distinct.len() > 1 || distinct.contains(&None)) {
+            groups.push((DupeKind::Audio, group));
+        }
+    }
+
+    let all: Vec<usize> = (0..candidates.len()).collect();
+    let by_name = bucket(&all, |i| {
+        let meta = &candidates[i].track.metadata;
+        let artist = normalize(meta.artist.as_deref().or(meta.album_artist.as_deref())?);
+        let title = normalize(meta.title.as_deref()?);
+        meta.duration?;
+        (!artist.is_empty() && !title.is_empty()).then(|| format!("{artist}\u{0}{title}"))
+    });
+    let duration = |i: &usize| candidates[*i].track.metadata.duration.unwrap_or(0.0);
+    for mut group in by_name.into_values().filter(|g| g.len() > 1) {
+        group.sort_by(|a, b| duration(a).total_cmp(&duration(b)));
+        let mut cluster = vec![group[0]];
+        for &i in &group[1..] {
+            if duration(&i) - duration(cluster.last().unwrap()) > tolerance {
+                push_metadata_group(&mut groups, std::mem::take(&mut cluster));
+            }
+            cluster.push(i);
+        }
+        push_metadata_group(&mut groups, cluster);
+    }
+
+    groups.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then_with(|| candidates[a.1[0]].path.cmp(&candidates[b.1[0]].path))
+    });
+    report.groups = groups
+        .into_iter()
+        .map(|(kind, members)| DupeGroup {
+            kind,
+            tracks: members
+                .into_iter()
+                .map(|i| {
+                    let c = &candidates[i];
+                    DupeTrack {
+                        path: c.path.clone(),
+                        size: c.track.size,
+                        format: c
+                            .track
+                            .path
+                            .extension()
+                            .map(|e| e.to_string_lossy().to_ascii_lowercase()),
+                        duration: c.track.metadata.duration,
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+    report
+}
+
+/// Metadata matches only add something when they aren't already one
+/// exact or audio group.
+fn push_metadata_group(groups: &mut Vec<(DupeKind, Vec<usize>)>, cluster: Vec<usize>) {
+    if cluster.len() < 2 {
+        return;
+    }
+    let covered = groups
+        .iter()
+        .any(|(_, g)| cluster.iter().all(|i| g.contains(i)));
+    if !covered {
+        groups.push((DupeKind::Metadata, cluster));
+    }
+}
+
+/// Groups indices by a key, keeping each group in index order. Indices
+/// without a key are left out.
+fn bucket<K: Ord>(indices: &[usize], key: impl Fn(usize) -> Option<K>) -> BTreeMap<K, Vec<usize>> {
+    let mut out: BTreeMap<K, Vec<usize>> = BTreeMap::new();
+    for &i in indices {
+        if let Some(k) = key(i) {
+            out.entry(k).or_default().push(i);
+        }
+    }
+    out
+}
+
+/// Lowercases and keeps only letters and digits, so punctuation and
+/// spacing differences between taggers don't matter.
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Hashes the encoded audio of a file, leaving out its tags where they can
+/// be told apart, so retagging a file keeps its hash.
+pub fn audio_hash(path: &Path) -> io::Result<blake3::Hash> {
+    let ranges = audio_ranges(path).ok().flatten();
+    hash_ranges(path, ranges.as_deref())
+}
+
+/// Hashes a whole file, or only the given `(offset, length)` ranges.
+fn hash_ranges(path: &Path, ranges: Option<&[(u64, u64)]>) -> io::Result<blake3::Hash> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    match ranges {
+        None => {
+            io::copy(&mut file, &mut hasher)?;
+        }
+        Some(ranges) => {
+            for &(start, len) in ranges {
+                file.seek(SeekFrom::Start(start))?;
+                io::copy(&mut (&mut file).take(len), &mut hasher)?;
+            }
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_at(file: &mut File, at: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(at))?;
+    file.read_exact(buf)
+}
+
+/// Where the encoded audio sits in a file, leaving out tags. Formats whose
+/// tags can't be separated from the audio (such as Ogg) return `None`.
+fn audio_ranges(path: &Path) -> io::Result<Option<Vec<(u64, u64)>>> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let ranges = match ext.as_str() {
+        "mp3" | "aac" => {
+            let start = skip_id3v2(&mut file, len)?;
+            let end = trim_trailing_tags(&mut file, start, len)?;
+            vec![(start, end - start)]
+        }
+        "flac" => {
+            let start = skip_id3v2(&mut file, len)?;
+            let start = skip_flac_metadata(&mut file, start)?;
+            let end = trim_trailing_tags(&mut file, start, len)?;
+            vec![(start, end - start)]
+        }
+        "wav" => riff_chunks(&mut file, len, false, b"data")?,
+        "aif" | "aiff" => riff_chunks(&mut file, len, true, b"SSND")?,
+        "m4a" | "m4b" | "mp4" | "alac" => mp4_mdat(&mut file, len)?,
+        _ => return Ok(None),
+    };
+    Ok((!ranges.is_empty()).then_some(ranges))
+}
+
+/// Skips any ID3v2 tags at the start of the file.
+fn skip_id3v2(file: &mut File, len: u64) -> io::Result<u64> {
+    let mut pos = 0;
+    let mut header = [0u8; 10];
+    while pos + 10 <= len {
+        read_at(file, pos, &mut header)?;
+        if &header[..3] != b"ID3" {
+            break;
+        }
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 7) | u64::from(b & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        pos = (pos + 10 + size + footer).min(len);
+    }
+    Ok(pos)
+}
+
+/// Leaves out an ID3v1 tag and an APEv2 tag at the end of the file.
+fn trim_trailing_tags(file: &mut File, start: u64, mut end: u64) -> io::Result<u64> {
+    let mut id3v1 = [0u8; 3];
+    if end >= start + 128 {
+        read_at(file, end - 128, &mut id3v1)?;
+        if &id3v1 == b"TAG" {
+            end -= 128;
+        }
+    }
+    let mut footer = [0u8; 32];
+    if end >= start + 32 {
+        read_at(file, end - 32, &mut footer)?;
+        if &footer[..8] == b"APETAGEX" {
+            let size = u64::from(u32::from_le_bytes(footer[12..16].try_into().unwrap()));
+            let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+            let header = if flags & 0x8000_0000 != 0 { 32 } else { 0 };
+            end = end.saturating_sub(size + header).max(start);
+        }
+    }
+    Ok(end)
+}
+
+/// Skips the `fLaC` marker and every metadata block after it.
+fn skip_flac_metadata(file: &mut File, start: u64) -> io::Result<u64> {
+    let mut marker = [0u8; 4];
+    read_at(file, start, &mut marker)?;
+    if &marker != b"fLaC" {
+        return Err(invalid("not a FLAC stream"));
+    }
+    let mut pos = start + 4;
+    let mut header = [0u8; 4];
+    loop {
+        read_at(file, pos, &mut header)?;
+        let size = u64::from(u32::from_be_bytes([0, header[1], header[2], header[3]]));
+        pos += 4 + size;
+        if header[0] & 0x80 != 0 {
+            return Ok(pos);
+        }
+    }
+}
+
+/// Finds the audio chunk in a RIFF (little-endian, WAV) or IFF
+/// (big-endian, AIFF) file.
+fn riff_chunks(
+    file: &mut File,
+    len: u64,
+    big_endian: bool,
+    kind: &[u8; 4],
+) -> io::Result<Vec<(u64, u64)>> {
+    let mut pos = 12;
+    let mut header = [0u8; 8];
+    while pos + 8 <= len {
+        read_at(file, pos, &mut header)?;
+        let size = [header[4], header[5], header[6], header[7]];
+        let size = u64::from(if big_endian {
+            u32::from_be_bytes(size)
+        } else {
+            u32::from_le_bytes(size)
+        });
+        if &header[..4] == kind {
+            return Ok(vec![(pos + 8, size.min(len - pos - 8))]);
+        }
+        // Chunks are padded to an even length.
+        pos += 8 + size + (size & 1);
+    }
+    Ok(Vec::new())
+}
+
+/// The payload of every top-level `mdat` atom in an MP4 file.
+fn mp4_mdat(file: &mut File, len: u64) -> io::Result<Vec<(u64, u64)>> {
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+    let mut header = [0u8; 16];
+    while pos + 8 <= len {
+        read_at(file, pos, &mut header[..8])?;
+        let mut size = u64::from(u32::from_be_bytes(header[..4].try_into().unwrap()));
+        let mut head = 8;
+        if size == 1 {
+            read_at(file, pos + 8, &mut header[8..])?;
+            size = u64::from_be_bytes(header[8..].try_into().unwrap());
+            head = 16;
+        } else if size == 0 {
+            size = len - pos;
+        }
+        if size < head {
+            return Err(invalid("bad MP4 atom size"));
+        }
+        if &header[4..8] == b"mdat" {
+            ranges.push((pos + head, (size - head).min(len - pos - head)));
+        }
+        pos += size;
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::TrackMetadata;
+
+    fn candidate(dir: &Path, name: &str, data: &[u8], meta: TrackMetadata) -> Candidate {
+        let file = dir.join(name);
+        std::fs::write(&file, data).unwrap();
+        Candidate {
+            path: name.to_string(),
+            file,
+            track: Arc::new(Track {
+                path: PathBuf::from(name),
+                size: Some(data.len() as u64),
+                modified: None,
+                metadata: meta,
+                cue: None,
+                first_seen: None,
+                loudness: None,
+                album_loudness: None,
+            }),
+        }
+    }
+
+    fn id3(payload: &[u8]) -> Vec<u8> {
+        let mut data = b"ID3\x03\x00\x00\x00\x00\x00".to_vec();
+        data.push(payload.len() as u8);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn groups_exact_audio_and_metadata_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let meta = |title: &str, duration: f64| TrackMetadata {
+            artist: Some("The Band".into()),
+            title: Some(title.into()),
+            duration: Some(duration),
+            ..TrackMetadata::default()
+        };
+        let frames = b"\xff\xfbaudio frames";
+        let tagged = [id3(b"old tags"), frames.to_vec()].concat();
+        let retagged = [id3(b"new tags, longer"), frames.to_vec()].concat();
+        let candidates = vec![
+            candidate(dir.path(), "a.mp3", &tagged, meta("Song", 200.0)),
+            candidate(dir.path(), "b.mp3", &tagged, meta("Song", 200.0)),
+            candidate(dir.path(), "c.mp3", &retagged, meta("Song!", 200.0)),
+            candidate(dir.path(), "d.flac", b"fLaC", meta("song", 201.5)),
+            candidate(dir.path(), "e.flac", b"fLaCx", meta("Other", 200.0)),
+            candidate(dir.path(), "f.ogg", b"x", meta("Song", 230.0)),
+        ];
+        let report = find(&candidates, DEFAULT_TOLERANCE_SECS);
+        let groups: Vec<(DupeKind, Vec<&str>)> = report
+            .groups
+            .iter()
+            .map(|g| (g.kind, g.tracks.iter().map(|t| t.path.as_str()).collect()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (DupeKind::Exact, vec!["a.mp3", "b.mp3"]),
+                (DupeKind::Audio, vec!["a.mp3", "b.mp3", "c.mp3"]),
+                (
+                    DupeKind::Metadata,
+                    vec!["a.mp3", "b.mp3", "c.mp3", "d.flac"]
+                ),
+            ]
+        );
+        assert_eq!(report.groups[2].tracks[3].format.as_deref(), Some("flac"));
+    }
+
+    #[test]
+    fn strips_trailing_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.mp3");
+        let mut data = id3(b"tag");
+        data.extend_from_slice(b"frames");
+        data.extend_from_slice(b"TAG");
+        data.resize(data.len() + 125, 0);
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(audio_ranges(&path).unwrap(), Some(vec![(13, 6)]));
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// One finished play of a track, keyed by its public relative path.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayEvent {
+    pub path: String,
+    /// Unix seconds when playback started.
+    pub at: u64,
+    /// Seconds actually listened, when the client reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub played: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlayCount {
+    pub plays: u64,
+    pub last_played: u64,
+}
+
+/// Listening history kept as an append-only JSON Lines file in the data
+/// directory, with every event also held in memory ordered by time.
+pub struct History {
+    path: PathBuf,
+    events: Mutex<Vec<PlayEvent>>,
+}
+
+impl History {
+    pub fn open(dir: &Path) -> Self {
+        let path = dir.join(HISTORY_FILE);
+        let mut events = Vec::new();
+        if let Ok(data) = fs::read_to_string(&path) {
+            for (n, line) in data.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<PlayEvent>(line) {
+                    Ok(event) => events.push(event),
+                    // A crash mid-append leaves a torn last line; skip it
+                    // rather than losing the rest of the history.
+                    Err(err) => {
+                        tracing::warn!(?err, line = n + 1, path = %path.display(), "skipping bad history line")
+                    }
+                }
+            }
+        }
+        events.sort_by_key(|e| e.at);
+        History {
+            path,
+            events: Mutex::new(events),
+        }
+    }
+
+    pub fn record(&self, event: PlayEvent) -> io::Result<()> {
+        let mut events = self.events.lock().unwrap();
+        let mut line = serde_json::to_string(&event)?;
+        line.push('\n');
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        // Late scrobbles from offline clients still land in time order.
+        let index = events.partition_point(|e| e.at <= event.at);
+        events.insert(index, event);
+        Ok(())
+    }
+
+    /// Newest events first, optionally only those before `before`.
+    pub fn recent(&self, limit: usize, before: Option<u64>) -> Vec<PlayEvent> {
+        let events = self.events.lock().unwrap();
+        let end = match before {
+            Some(before) => events.partition_point(|e| e.at < before),
+            None => events.len(),
+        };
+        events[..end].iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Plays per track within `[since, until)`.
+    pub fn play_counts(&self, since: u64, until: u64) -> HashMap<String, PlayCount> {
+        let events = self.events.lock().unwrap();
+        let start = events.partition_point(|e| e.at < since);
+        let end = events.partition_point(|e| e.at < until);
+        let mut counts: HashMap<String, PlayCount> = HashMap::new();
+        for event in &events[start..end.max(start)] {
+            let count = counts.entry(event.path.clone()).or_default();
+            count.plays += 1;
+            count.last_played = count.last_played.max(event.at);
+        }
+        counts
+    }
+
+    /// All-time plays of one track.
+    pub fn play_count(&self, path: &str) -> u64 {
+        let events = self.events.lock().unwrap();
+        events.iter().filter(|e| e.path == path).count() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(path: &str, at: u64) -> PlayEvent {
+        PlayEvent {
+            path: path.into(),
+            at,
+            played: None,
+            client: None,
+        }
+    }
+
+    #[test]
+    fn records_and_reloads_in_time_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::open(dir.path());
+        history.record(play("a.mp3", 100)).unwrap();
+        history.record(play("b.mp3", 300)).unwrap();
+        history.record(play("a.mp3", 200)).unwrap();
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(HISTORY_FILE))
+            .unwrap();
+        file.write_all(b"{\"path\":\"torn").unwrap();
+
+        let history = History::open(dir.path());
+        let recent: Vec<u64> = history.recent(10, None).iter().map(|e| e.at).collect();
+        assert_eq!(recent, vec![300, 200, 100]);
+        assert_eq!(history.recent(1, Some(300))[0].at, 200);
+
+        let counts = history.play_counts(150, 400);
+        assert_eq!(
+            counts["a.mp3"],
+            PlayCount {
+                plays: 1,
+                last_played: 200
+            }
+        );
+        assert_eq!(counts["b.mp3"].plays, 1);
+        assert_eq!(history.play_counts(0, u64::MAX)["a.mp3"].plays, 2);
+        assert_eq!(history.play_count("a.mp3"), 2);
+    }
+}
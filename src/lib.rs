@@ -1,4 +1,22 @@
+pub mod chapters;
+pub mod cue;
+pub mod decode;
+pub mod dupes;
+pub mod history;
 pub mod library;
+pub mod loudness;
+pub mod lyrics;
 pub mod path_utils;
 pub mod playlist;
+pub mod playlist_store;
+pub mod ratings;
+pub mod resume;
+pub mod scan_report;
 pub mod server;
+pub mod smart_playlist;
+pub mod tag_writer;
+#[cfg(test)]
+#[path = "../tests/common/fixtures.rs"]
+mod test_fixtures;
+pub mod time;
+pub mod waveform;
@@ -3,9 +3,16 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use crate::chapters::{self, Chapter};
+use crate::cue;
+use crate::loudness::{self, Loudness, ReplayGain};
+use crate::lyrics::{self, LyricsKind};
 use crate::path_utils;
+use crate::playlist::{self, PlaylistFormat};
+use crate::scan_report::{ScanIssue, ScanIssueKind, ScanReport};
+use crate::time;
 
-use lofty::{Accessor, AudioFile, TaggedFileExt};
+use lofty::{Accessor, AudioFile, ItemKey, TaggedFileExt};
 use walkdir::{DirEntry, WalkDir};
 
 use blake3::Hasher;
@@ -20,14 +27,108 @@ pub struct TrackMetadata {
     pub album: Option<String>,
     pub duration: Option<f64>,
     pub artwork_id: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    #[serde(default)]
+    pub album_artist: Option<String>,
+    #[serde(default)]
+    pub track_number: Option<u32>,
+    #[serde(default)]
+    pub disc_number: Option<u32>,
+    /// Lyrics embedded in the tags.
+    #[serde(default)]
+    pub lyrics: Option<LyricsKind>,
+    /// Name of a `.lrc` or `.txt` lyrics file next to the track.
+    #[serde(default)]
+    pub lyrics_file: Option<String>,
+    /// What that file holds, judged from its content.
+    #[serde(default)]
+    pub lyrics_file_kind: Option<LyricsKind>,
+    /// Chapter markers from `CHAP` frames or MP4 chapter atoms.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Chapter>,
+    /// ReplayGain or R128 values from the tags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_gain: Option<ReplayGain>,
+}
+
+impl TrackMetadata {
+    /// Identifies the album a track belongs to from its tags: album artist
+    /// (or artist) plus album title, case-insensitive. `None` without an
+    /// album tag.
+    pub fn album_key(&self) -> Option<String> {
+        let album = self
+            .album
+            .as_deref()
+            .map(str::trim)
+            .filter(|a| !a.is_empty())?;
+        let artist = self
+            .album_artist
+            .as_deref()
+            .or(self.artist.as_deref())
+            .map(str::trim)
+            .unwrap_or("");
+        Some(format!(
+            "{}/{}",
+            artist.to_lowercase(),
+            album.to_lowercase()
+        ))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Track {
     pub path: PathBuf,
-    #[allow(dead_code)]
     pub size: Option<u64>,
+    /// File modification time, in seconds since the Unix epoch.
+    pub modified: Option<u64>,
     pub metadata: TrackMetadata,
+    /// Set for virtual tracks cut from a single-file rip by a CUE sheet.
+    pub cue: Option<CueSpan>,
+    /// When a scan first found the track, in seconds since the Unix epoch.
+    pub first_seen: Option<u64>,
+    /// Measured by the loudness analysis job; kept while the file is
+    /// unchanged.
+    pub loudness: Option<Loudness>,
+    /// The album's combined loudness, once every track of it is measured.
+    pub album_loudness: Option<Loudness>,
+}
+
+/// Where a virtual track lives inside its album rip.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CueSpan {
+    /// The single-file rip, relative to the root.
+    pub source: PathBuf,
+    /// The CUE sheet describing it, relative to the root.
+    pub sheet: PathBuf,
+    /// Seconds into the rip.
+    pub start: f64,
+    /// `None` runs to the end of the rip.
+    pub end: Option<f64>,
+}
+
+/// A track together with its public path, which includes the mount prefix.
+#[derive(Clone, Debug)]
+pub struct TrackRef {
+    pub path: String,
+    pub track: Arc<Track>,
+}
+
+impl TrackRef {
+    /// Public path of another file under the same root as this track.
+    pub fn sibling_path(&self, rel: &Path) -> String {
+        let own = self.track.path.to_string_lossy().replace('\\', "/");
+        let prefix = self.path.strip_suffix(own.as_str()).unwrap_or("");
+        format!("{prefix}{}", rel.to_string_lossy().replace('\\', "/"))
+    }
+}
+
+/// Running counters reported while a scan walks the tree.
+#[derive(Clone, Debug, Default)]
+pub struct ScanProgress {
+    pub files_seen: u64,
+    pub files_parsed: u64,
+    pub current_folder: String,
 }
 
 #[derive(Debug)]
@@ -37,6 +138,15 @@ pub struct Library {
     tracks: Vec<Arc<Track>>,
     folders: HashMap<String, FolderEntry>,
     artworks: HashMap<String, Artwork>,
+    playlists: Vec<PlaylistFile>,
+}
+
+/// A playlist file found in the tree, with its entries as written.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PlaylistFile {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub entries: Vec<String>,
 }
 
 #[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
@@ -56,13 +166,25 @@ struct LibrarySnapshot {
     tracks: Vec<TrackSnapshot>,
     folders: HashMap<String, FolderEntry>,
     artworks: HashMap<String, ArtworkSnapshot>,
+    #[serde(default)]
+    playlists: Vec<PlaylistFile>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 struct TrackSnapshot {
     path: PathBuf,
     size: Option<u64>,
+    #[serde(default)]
+    modified: Option<u64>,
     metadata: TrackMetadata,
+    #[serde(default)]
+    cue: Option<CueSpan>,
+    #[serde(default)]
+    first_seen: Option<u64>,
+    #[serde(default)]
+    loudness: Option<Loudness>,
+    #[serde(default)]
+    album_loudness: Option<Loudness>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -72,45 +194,127 @@ struct ArtworkSnapshot {
 }
 
 impl Library {
+    #[allow(dead_code)]
     pub fn scan(root: PathBuf) -> Self {
-        let mut tracks: Vec<Arc<Track>> = Vec::new();
+        Self::scan_with_progress(root, &mut |_| {}).0
+    }
+
+    pub fn scan_with_progress(
+        root: PathBuf,
+        progress: &mut dyn FnMut(&ScanProgress),
+    ) -> (Self, ScanReport) {
+        Library::empty(root).rescan_folder("", progress)
+    }
+
+    /// Rescans one folder (relative to the root, empty for everything) and
+    /// returns a new library with that subtree replaced. Tracks elsewhere
+    /// are carried over as they are.
+    pub fn rescan_folder(
+        &self,
+        folder: &str,
+        progress: &mut dyn FnMut(&ScanProgress),
+    ) -> (Self, ScanReport) {
+        let started = std::time::Instant::now();
+        let started_at = time::unix_now();
+        let mut issues: Vec<ScanIssue> = Vec::new();
+        let mut counters = ScanProgress::default();
+        let scope = Path::new(folder);
+        let mut tracks: Vec<Arc<Track>> = self
+            .tracks
+            .iter()
+            .filter(|t| !folder.is_empty() && !t.path.starts_with(scope))
+            .cloned()
+            .collect();
         let mut artworks: HashMap<String, Artwork> = HashMap::new();
-        let iter = WalkDir::new(root.clone())
+        let mut playlists: Vec<PlaylistFile> = self
+            .playlists
+            .iter()
+            .filter(|p| !folder.is_empty() && !p.path.starts_with(scope))
+            .cloned()
+            .collect();
+        let mut cue_sheets: Vec<PathBuf> = Vec::new();
+        let start_dir = self.root.join(folder);
+        let iter = WalkDir::new(&start_dir)
             .follow_links(false)
             .into_iter()
             .filter_entry(|e| !is_hidden_entry(e));
         for entry in iter.filter_map(|e| e.ok()) {
             let p = entry.path();
-            if p.is_file()
-                && let Some(ext) = p
-                    .extension()
-                    .and_then(|e| e.to_str())
-                    .map(|s| s.to_ascii_lowercase())
-                && matches!(
-                    ext.as_str(),
-                    "mp3"
-                        | "flac"
-                        | "wav"
-                        | "aac"
-                        | "m4a"
-                        | "ogg"
-                        | "opus"
-                        | "wma"
-                        | "aif"
-                        | "aiff"
-                        | "alac"
-                        | "pcm"
-                        | "mp2"
-                        | "mpga"
-                        | "ape"
-                )
-            {
+            if p.is_file() {
+                counters.files_seen += 1;
+                if let Some(format) = PlaylistFormat::from_path(p)
+                    && !is_hidden_path(p)
+                {
+                    let rel = p.strip_prefix(&self.root).unwrap_or(p).to_path_buf();
+                    match fs::read(p) {
+                        Ok(data) => {
+                            let (title, entries) = playlist::parse_playlist(format, &data);
+                            playlists.push(PlaylistFile {
+                                path: rel,
+                                title,
+                                entries,
+                            });
+                        }
+                        Err(err) => issues.push(ScanIssue::new(
+                            &rel,
+                            ScanIssueKind::Unreadable,
+                            err.to_string(),
+                        )),
+                    }
+                    continue;
+                }
+                if is_cue_path(p) && !is_hidden_path(p) {
+                    cue_sheets.push(p.to_path_buf());
+                    continue;
+                }
+            }
+            if p.is_file() && is_audio_path(p) {
                 if is_hidden_path(p) {
                     continue;
                 }
-                let rel = p.strip_prefix(&root).unwrap_or(p).to_path_buf();
-                let size = fs::metadata(p).ok().map(|m| m.len());
-                let (metadata, artwork_blob) = read_metadata(p);
+                let rel = p.strip_prefix(&self.root).unwrap_or(p).to_path_buf();
+                let (size, modified) = match fs::metadata(p) {
+                    Ok(m) => (Some(m.len()), m.modified().ok().and_then(unix_secs)),
+                    Err(err) => {
+                        issues.push(ScanIssue::new(
+                            &rel,
+                            ScanIssueKind::Unreadable,
+                            err.to_string(),
+                        ));
+                        (None, None)
+                    }
+                };
+                let (metadata, artwork_blob) = match read_metadata(p) {
+                    Ok(read) => {
+                        if read.0.duration.is_none() {
+                            issues.push(ScanIssue::new(
+                                &rel,
+                                ScanIssueKind::ZeroDuration,
+                                "file reports no playable duration",
+                            ));
+                        }
+                        read
+                    }
+                    Err(err) => {
+                        // Unreadable files were already reported above.
+                        if size.is_some() {
+                            issues.push(ScanIssue::from_lofty(&rel, &err));
+                        }
+                        (TrackMetadata::default(), None)
+                    }
+                };
+                let sidecar = lyrics::sidecar(p);
+                let metadata = TrackMetadata {
+                    lyrics_file: sidecar.as_ref().map(|(name, _)| name.clone()),
+                    lyrics_file_kind: sidecar.map(|(_, kind)| kind),
+                    ..metadata
+                };
+                counters.files_parsed += 1;
+                counters.current_folder = rel
+                    .parent()
+                    .map(|d| d.to_string_lossy().replace('\\', "/"))
+                    .unwrap_or_default();
+                progress(&counters);
                 if let Some(blob) = artwork_blob {
                     artworks.entry(blob.id.clone()).or_insert_with(|| Artwork {
                         mime: blob.mime,
@@ -120,56 +324,179 @@ impl Library {
                 tracks.push(Arc::new(Track {
                     path: rel,
                     size,
+                    modified,
                     metadata,
+                    cue: None,
+                    first_seen: None,
+                    loudness: None,
+                    album_loudness: None,
                 }));
             }
         }
+        apply_cue_sheets(&self.root, &mut tracks, &cue_sheets, &mut issues);
+        // Tracks keep the time they first appeared across rescans. The very
+        // first scan of a root, and caches from before this was recorded,
+        // fall back to the file's modification time.
+        let first_scan = self.tracks.is_empty();
+        for track in tracks.iter_mut().filter(|t| t.first_seen.is_none()) {
+            let known = self.track_at(&track.path);
+            let first_seen = match known {
+                Some(old) => old.first_seen.or(track.modified),
+                None if first_scan => track.modified,
+                None => None,
+            };
+            // Measured loudness stays valid while the file is unchanged.
+            let unchanged =
+                known.filter(|old| old.size == track.size && old.modified == track.modified);
+            let track = Arc::make_mut(track);
+            track.first_seen = first_seen.or(Some(started_at));
+            if let Some(old) = unchanged {
+                track.loudness = old.loudness;
+                track.album_loudness = old.album_loudness;
+            }
+        }
 
         tracks.sort_by(|a, b| a.path.cmp(&b.path));
-
-        let mut folders: HashMap<String, FolderEntry> = HashMap::new();
-        folders.entry(String::new()).or_default();
-        for (idx, t) in tracks.iter().enumerate() {
-            // Folder tree population
-            match t.path.parent() {
-                None => {
-                    folders.entry(String::new()).or_default().tracks.push(idx);
-                }
-                Some(parent) => {
-                    let rel_parent = parent.to_string_lossy().to_string();
-                    folders
-                        .entry(rel_parent.clone())
-                        .or_default()
-                        .tracks
-                        .push(idx);
-
-                    // Build chain of subfolder links from root to this parent
-                    let parts: Vec<String> = parent
-                        .components()
-                        .map(|c| c.as_os_str().to_string_lossy().to_string())
-                        .collect();
-                    let mut prev = String::new();
-                    for i in 0..parts.len() {
-                        let current = parts[0..=i].join("/");
-                        folders.entry(current.clone()).or_default();
-                        // link prev -> current
-                        folders
-                            .entry(prev.clone())
-                            .or_default()
-                            .subfolders
-                            .insert(current.clone());
-                        prev = current;
-                    }
-                }
+        playlists.sort_by(|a, b| a.path.cmp(&b.path));
+        for t in &tracks {
+            if let Some(id) = &t.metadata.artwork_id
+                && !artworks.contains_key(id)
+                && let Some(art) = self.artworks.get(id)
+            {
+                artworks.insert(id.clone(), art.clone());
             }
         }
+        let folders = build_folders(&tracks);
 
-        Library {
-            root,
+        let report = ScanReport {
+            started_at,
+            finished_at: time::unix_now(),
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            files_seen: counters.files_seen,
+            files_parsed: counters.files_parsed,
+            tracks: tracks.len(),
+            folders: folders.len(),
+            artworks: artworks.len(),
+            issues,
+            error: None,
+            scope: (!folder.is_empty()).then(|| folder.to_string()),
+        };
+        let lib = Library {
+            root: self.root.clone(),
             tracks,
             folders,
             artworks,
+            playlists,
+        };
+        (lib, report)
+    }
+
+    /// Re-reads known tracks after their files changed, without walking the
+    /// tree. Paths that are not in the library are ignored.
+    pub fn reread_tracks(&self, rels: &[PathBuf]) -> Self {
+        let mut tracks = self.tracks.clone();
+        let mut artworks = self.artworks.clone();
+        for rel in rels {
+            // Virtual CUE tracks have no file of their own to re-read.
+            let Some(index) = tracks
+                .iter()
+                .position(|t| &t.path == rel && t.cue.is_none())
+            else {
+                continue;
+            };
+            let abs = self.root.join(rel);
+            let (size, modified) = match fs::metadata(&abs) {
+                Ok(m) => (Some(m.len()), m.modified().ok().and_then(unix_secs)),
+                Err(_) => (None, None),
+            };
+            let (mut metadata, artwork_blob) = read_metadata(&abs).unwrap_or_default();
+            let sidecar = lyrics::sidecar(&abs);
+            metadata.lyrics_file = sidecar.as_ref().map(|(name, _)| name.clone());
+            metadata.lyrics_file_kind = sidecar.map(|(_, kind)| kind);
+            if let Some(blob) = artwork_blob {
+                artworks.entry(blob.id.clone()).or_insert_with(|| Artwork {
+                    mime: blob.mime,
+                    data: blob.data.into(),
+                });
+            }
+            tracks[index] = Arc::new(Track {
+                path: rel.clone(),
+                size,
+                modified,
+                metadata,
+                cue: None,
+                first_seen: tracks[index].first_seen,
+                // Tag edits leave the audio as it was.
+                loudness: tracks[index].loudness,
+                album_loudness: tracks[index].album_loudness,
+            });
+        }
+        // Drop covers no track embeds any more.
+        artworks.retain(|id, _| {
+            tracks
+                .iter()
+                .any(|t| t.metadata.artwork_id.as_deref() == Some(id.as_str()))
+        });
+        Library {
+            root: self.root.clone(),
+            folders: build_folders(&tracks),
+            tracks,
+            artworks,
+            playlists: self.playlists.clone(),
+        }
+    }
+
+    /// Stores measured loudness for tracks and recomputes every album's
+    /// combined loudness. Measurements of tracks whose file changed since
+    /// are dropped. Returns `None` when nothing changed.
+    pub fn with_loudness(&self, measured: &[(Arc<Track>, Loudness)]) -> Option<Self> {
+        let by_path: HashMap<&Path, &(Arc<Track>, Loudness)> =
+            measured.iter().map(|m| (m.0.path.as_path(), m)).collect();
+        let mut tracks = self.tracks.clone();
+        let mut changed = false;
+        for track in tracks.iter_mut() {
+            if let Some((old, loudness)) = by_path.get(track.path.as_path())
+                && old.size == track.size
+                && old.modified == track.modified
+                && track.loudness != Some(*loudness)
+            {
+                Arc::make_mut(track).loudness = Some(*loudness);
+                changed = true;
+            }
+        }
+        let mut albums: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, track) in tracks.iter().enumerate() {
+            if let Some(key) = track.metadata.album_key() {
+                albums.entry(key).or_default().push(i);
+            }
+        }
+        let mut album_loudness: Vec<Option<Loudness>> = vec![None; tracks.len()];
+        for members in albums.values() {
+            let combined = members
+                .iter()
+                .map(|&i| {
+                    let t = &tracks[i];
+                    Some((t.loudness?, t.metadata.duration.unwrap_or(0.0)))
+                })
+                .collect::<Option<Vec<_>>>()
+                .and_then(loudness::album);
+            for &i in members {
+                album_loudness[i] = combined;
+            }
+        }
+        for (track, album) in tracks.iter_mut().zip(album_loudness) {
+            if track.album_loudness != album {
+                Arc::make_mut(track).album_loudness = album;
+                changed = true;
+            }
         }
+        changed.then(|| Library {
+            root: self.root.clone(),
+            tracks,
+            folders: self.folders.clone(),
+            artworks: self.artworks.clone(),
+            playlists: self.playlists.clone(),
+        })
     }
 
     pub fn empty(root: PathBuf) -> Self {
@@ -178,6 +505,7 @@ impl Library {
             tracks: Vec::new(),
             folders: HashMap::new(),
             artworks: HashMap::new(),
+            playlists: Vec::new(),
         }
     }
 
@@ -206,6 +534,18 @@ impl Library {
         &self.tracks
     }
 
+    /// Looks up a track by its path relative to the root.
+    pub fn track(&self, rel: &str) -> Option<Arc<Track>> {
+        self.track_at(Path::new(rel)).cloned()
+    }
+
+    fn track_at(&self, rel: &Path) -> Option<&Arc<Track>> {
+        self.tracks
+            .binary_search_by(|t| t.path.as_path().cmp(rel))
+            .ok()
+            .map(|idx| &self.tracks[idx])
+    }
+
     pub fn folder(&self, rel: &str) -> Option<&FolderEntry> {
         self.folders.get(rel)
     }
@@ -227,6 +567,19 @@ impl Library {
         }
     }
 
+    pub fn folder_count(&self) -> usize {
+        self.folders.len()
+    }
+
+    pub fn artwork_count(&self) -> usize {
+        self.artworks.len()
+    }
+
+    /// Playlist files found in the tree, sorted by path.
+    pub fn playlists(&self) -> &[PlaylistFile] {
+        &self.playlists
+    }
+
     pub fn artwork(&self, id: &str) -> Option<Artwork> {
         self.artworks.get(id).cloned()
     }
@@ -239,7 +592,12 @@ impl Library {
                 Arc::new(Track {
                     path: track.path,
                     size: track.size,
+                    modified: track.modified,
                     metadata: track.metadata,
+                    cue: track.cue,
+                    first_seen: track.first_seen,
+                    loudness: track.loudness,
+                    album_loudness: track.album_loudness,
                 })
             })
             .collect();
@@ -261,6 +619,7 @@ impl Library {
             tracks,
             folders: snapshot.folders,
             artworks,
+            playlists: snapshot.playlists,
         }
     }
 
@@ -271,7 +630,12 @@ impl Library {
             .map(|track| TrackSnapshot {
                 path: track.path.clone(),
                 size: track.size,
+                modified: track.modified,
                 metadata: track.metadata.clone(),
+                cue: track.cue.clone(),
+                first_seen: track.first_seen,
+                loudness: track.loudness,
+                album_loudness: track.album_loudness,
             })
             .collect();
         let artworks = self
@@ -291,11 +655,52 @@ impl Library {
             tracks,
             folders: self.folders.clone(),
             artworks,
+            playlists: self.playlists.clone(),
+        }
+    }
+}
+
+fn build_folders(tracks: &[Arc<Track>]) -> HashMap<String, FolderEntry> {
+    let mut folders: HashMap<String, FolderEntry> = HashMap::new();
+    folders.entry(String::new()).or_default();
+    for (idx, t) in tracks.iter().enumerate() {
+        // Folder tree population
+        match t.path.parent() {
+            None => {
+                folders.entry(String::new()).or_default().tracks.push(idx);
+            }
+            Some(parent) => {
+                let rel_parent = parent.to_string_lossy().to_string();
+                folders
+                    .entry(rel_parent.clone())
+                    .or_default()
+                    .tracks
+                    .push(idx);
+
+                // Build chain of subfolder links from root to this parent
+                let parts: Vec<String> = parent
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect();
+                let mut prev = String::new();
+                for i in 0..parts.len() {
+                    let current = parts[0..=i].join("/");
+                    folders.entry(current.clone()).or_default();
+                    // link prev -> current
+                    folders
+                        .entry(prev.clone())
+                        .or_default()
+                        .subfolders
+                        .insert(current.clone());
+                    prev = current;
+                }
+            }
         }
     }
+    folders
 }
 
-fn cache_dir(root: &Path) -> PathBuf {
+pub(crate) fn cache_dir(root: &Path) -> PathBuf {
     root.join(CACHE_DIR)
 }
 
@@ -315,39 +720,231 @@ fn is_hidden_path(p: &Path) -> bool {
         .any(|c| path_utils::is_hidden_component(c.as_os_str()))
 }
 
-fn read_metadata(path: &Path) -> (TrackMetadata, Option<ArtworkBlob>) {
-    let mut metadata = TrackMetadata::default();
-    let mut artwork_blob = None;
-    if let Ok(tagged) = lofty::read_from_path(path) {
-        if let Some(tag) = tagged.primary_tag().or_else(|| tagged.first_tag()) {
-            if let Some(title) = tag.title() {
-                metadata.title = Some(title.to_string());
+fn is_cue_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
+}
+
+/// Replaces single-file rips described by a CUE sheet with one virtual
+/// track per sheet entry. Rips in formats that cannot be cut stay whole.
+fn apply_cue_sheets(
+    root: &Path,
+    tracks: &mut Vec<Arc<Track>>,
+    sheets: &[PathBuf],
+    issues: &mut Vec<ScanIssue>,
+) {
+    for sheet_path in sheets {
+        let rel_sheet = sheet_path
+            .strip_prefix(root)
+            .unwrap_or(sheet_path)
+            .to_path_buf();
+        let sheet = match fs::read(sheet_path) {
+            Ok(data) => cue::parse(&cue::decode(&data)),
+            Err(err) => {
+                issues.push(ScanIssue::new(
+                    &rel_sheet,
+                    ScanIssueKind::Unreadable,
+                    err.to_string(),
+                ));
+                continue;
             }
-            if let Some(artist) = tag.artist() {
-                metadata.artist = Some(artist.to_string());
+        };
+        let dir = rel_sheet.parent().unwrap_or(Path::new(""));
+        for file in &sheet.files {
+            if file.tracks.len() < 2 {
+                continue;
             }
-            if let Some(album) = tag.album() {
-                metadata.album = Some(album.to_string());
+            let Some(index) = find_rip(tracks, dir, &file.name) else {
+                let missing = dir.join(file.name.replace('\\', "/"));
+                issues.push(ScanIssue::new(
+                    &rel_sheet,
+                    ScanIssueKind::CueMissingSource,
+                    format!("refers to missing file {}", missing.display()),
+                ));
+                continue;
+            };
+            if !cue::can_slice(&tracks[index].path) {
+                continue;
             }
-            if let Some(picture) = tag.pictures().first() {
-                let mime = picture
-                    .mime_type()
-                    .map(|m| m.to_string())
-                    .unwrap_or_else(|| "image/jpeg".to_string());
-                let data = picture.data().to_vec();
-                let mut hasher = Hasher::new();
-                hasher.update(&data);
-                let id = hasher.finalize().to_hex().to_string();
-                metadata.artwork_id = Some(id.clone());
-                artwork_blob = Some(ArtworkBlob { id, mime, data });
+            let cut = cue_tracks(root, &sheet, file, &tracks[index], &rel_sheet);
+            if cut.iter().any(|v| tracks.iter().any(|t| t.path == v.path)) {
+                continue;
             }
+            tracks.splice(index..=index, cut);
+        }
+    }
+}
+
+/// The track a sheet's `FILE` line names. Sheets often still name the WAV
+/// a rip was made from after it was compressed, so a file with the same
+/// stem and another audio extension also matches.
+fn find_rip(tracks: &[Arc<Track>], dir: &Path, name: &str) -> Option<usize> {
+    let name = name.replace('\\', "/");
+    let wanted = dir.join(&name);
+    if wanted
+        .components()
+        .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        return None;
+    }
+    let whole = |t: &Arc<Track>| t.cue.is_none();
+    tracks
+        .iter()
+        .position(|t| whole(t) && t.path == wanted)
+        .or_else(|| {
+            let stem = wanted.file_stem()?;
+            tracks.iter().position(|t| {
+                whole(t) && t.path.parent() == wanted.parent() && t.path.file_stem() == Some(stem)
+            })
+        })
+}
+
+fn cue_tracks(
+    root: &Path,
+    sheet: &cue::CueSheet,
+    file: &cue::CueFile,
+    rip: &Track,
+    rel_sheet: &Path,
+) -> Vec<Arc<Track>> {
+    let stem = rip
+        .path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = rip
+        .path
+        .extension()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let base = &rip.metadata;
+    file.tracks
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let end = file.tracks.get(i + 1).map(|next| next.start);
+            let duration = end
+                .or(base.duration)
+                .map(|end| end - entry.start)
+                .filter(|d| *d > 0.0);
+            let metadata = TrackMetadata {
+                title: entry.title.clone(),
+                artist: entry
+                    .performer
+                    .clone()
+                    .or_else(|| sheet.performer.clone())
+                    .or_else(|| base.artist.clone()),
+                album: sheet.title.clone().or_else(|| base.album.clone()),
+                album_artist: sheet
+                    .performer
+                    .clone()
+                    .or_else(|| base.album_artist.clone()),
+                genre: sheet.genre.clone().or_else(|| base.genre.clone()),
+                year: sheet.year.or(base.year),
+                duration,
+                artwork_id: base.artwork_id.clone(),
+                track_number: Some(entry.number),
+                disc_number: base.disc_number,
+                lyrics: None,
+                lyrics_file: None,
+                lyrics_file_kind: None,
+                chapters: Vec::new(),
+                // Track values from the rip's tags measure the whole album.
+                replay_gain: base
+                    .replay_gain
+                    .filter(|g| g.album_gain.is_some())
+                    .map(|g| ReplayGain {
+                        track_gain: None,
+                        track_peak: None,
+                        ..g
+                    }),
+            };
+            Arc::new(Track {
+                path: rip
+                    .path
+                    .with_file_name(format!("{stem}.{:02}.{ext}", entry.number)),
+                // What the cut stream is served as, header included.
+                size: cue::plan_slice(&root.join(&rip.path), entry.start, end)
+                    .ok()
+                    .map(|slice| slice.size()),
+                modified: rip.modified,
+                metadata,
+                cue: Some(CueSpan {
+                    source: rip.path.clone(),
+                    sheet: rel_sheet.to_path_buf(),
+                    start: entry.start,
+                    end,
+                }),
+                first_seen: None,
+                loudness: None,
+                album_loudness: None,
+            })
+        })
+        .collect()
+}
+
+/// File extensions the scanner picks up as tracks.
+pub const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "wav", "aac", "m4a", "m4b", "ogg", "opus", "wma", "aif", "aiff", "alac", "pcm",
+    "mp2", "mpga", "ape",
+];
+
+pub fn is_audio_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.iter().any(|a| a.eq_ignore_ascii_case(ext)))
+}
+
+fn unix_secs(time: std::time::SystemTime) -> Option<u64> {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+fn read_metadata(path: &Path) -> lofty::Result<(TrackMetadata, Option<ArtworkBlob>)> {
+    let mut metadata = TrackMetadata::default();
+    let mut artwork_blob = None;
+    let tagged = lofty::read_from_path(path)?;
+    if let Some(tag) = tagged.primary_tag().or_else(|| tagged.first_tag()) {
+        if let Some(title) = tag.title() {
+            metadata.title = Some(title.to_string());
+        }
+        if let Some(artist) = tag.artist() {
+            metadata.artist = Some(artist.to_string());
+        }
+        if let Some(album) = tag.album() {
+            metadata.album = Some(album.to_string());
         }
-        let duration = tagged.properties().duration().as_secs_f64();
-        if duration.is_finite() && duration > 0.0 {
-            metadata.duration = Some(duration);
+        if let Some(genre) = tag.genre() {
+            metadata.genre = Some(genre.to_string());
+        }
+        if let Some(album_artist) = tag.get_string(&ItemKey::AlbumArtist) {
+            metadata.album_artist = Some(album_artist.to_string());
+        }
+        metadata.year = tag.year().filter(|y| *y > 0);
+        metadata.track_number = tag.track().filter(|n| *n > 0);
+        metadata.disc_number = tag.disk().filter(|n| *n > 0);
+        metadata.lyrics = lyrics::embedded_kind(tag);
+        metadata.replay_gain = loudness::from_tag(tag);
+        if let Some(picture) = tag.pictures().first() {
+            let mime = picture
+                .mime_type()
+                .map(|m| m.to_string())
+                .unwrap_or_else(|| "image/jpeg".to_string());
+            let data = picture.data().to_vec();
+            let mut hasher = Hasher::new();
+            hasher.update(&data);
+            let id = hasher.finalize().to_hex().to_string();
+            metadata.artwork_id = Some(id.clone());
+            artwork_blob = Some(ArtworkBlob { id, mime, data });
         }
     }
-    (metadata, artwork_blob)
+    let duration = tagged.properties().duration().as_secs_f64();
+    if duration.is_finite() && duration > 0.0 {
+        metadata.duration = Some(duration);
+    }
+    metadata.chapters = chapters::read(path, metadata.duration);
+    Ok((metadata, artwork_blob))
 }
 
 #[derive(Debug)]
@@ -356,3 +953,131 @@ struct ArtworkBlob {
     mime: String,
     data: Vec<u8>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"").unwrap();
+    }
+
+    #[test]
+    fn rescan_folder_merges_subtree() {
+        let tmp = tempfile::Builder::new().prefix("musrv").tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        touch(&root.join("A/one.mp3"));
+        touch(&root.join("C/three.mp3"));
+        let lib = Library::scan(root.clone());
+        assert_eq!(lib.tracks().len(), 2);
+
+        touch(&root.join("B/Disc 1/two.mp3"));
+        fs::remove_file(root.join("C/three.mp3")).unwrap();
+        let (lib, report) = lib.rescan_folder("B", &mut |_| {});
+        assert_eq!(report.scope.as_deref(), Some("B"));
+        let paths: Vec<_> = lib.tracks().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("A/one.mp3"),
+                PathBuf::from("B/Disc 1/two.mp3"),
+                PathBuf::from("C/three.mp3"),
+            ]
+        );
+        assert_eq!(lib.folder("B/Disc 1").unwrap().tracks, vec![1]);
+        assert_eq!(lib.folder("C").unwrap().tracks, vec![2]);
+        assert!(lib.folder("").unwrap().subfolders.contains("B"));
+
+        let (lib, _) = lib.rescan_folder("C", &mut |_| {});
+        assert_eq!(lib.tracks().len(), 2);
+        assert!(lib.folder("C").is_none());
+        assert!(!lib.folder("").unwrap().subfolders.contains("C"));
+    }
+
+    #[test]
+    fn measured_loudness_combines_into_albums() {
+        let track = |path: &str, album: Option<&str>| {
+            Arc::new(Track {
+                path: PathBuf::from(path),
+                size: Some(1),
+                modified: Some(1),
+                metadata: TrackMetadata {
+                    artist: Some("X".into()),
+                    album: album.map(str::to_string),
+                    duration: Some(100.0),
+                    ..TrackMetadata::default()
+                },
+                cue: None,
+                first_seen: None,
+                loudness: None,
+                album_loudness: None,
+            })
+        };
+        let tracks = vec![
+            track("A/1.mp3", Some("A")),
+            track("A/2.mp3", Some("A")),
+            track("B/1.mp3", None),
+        ];
+        let lib = Library {
+            root: PathBuf::from("/music"),
+            folders: build_folders(&tracks),
+            tracks: tracks.clone(),
+            artworks: HashMap::new(),
+            playlists: Vec::new(),
+        };
+        let level = |integrated| Loudness {
+            integrated,
+            peak: 0.5,
+        };
+
+        let lib = lib
+            .with_loudness(&[
+                (tracks[0].clone(), level(-10.0)),
+                (tracks[2].clone(), level(-20.0)),
+            ])
+            .unwrap();
+        assert_eq!(lib.tracks()[0].loudness, Some(level(-10.0)));
+        assert_eq!(lib.tracks()[0].album_loudness, None);
+        assert_eq!(lib.tracks()[2].album_loudness, None);
+        assert!(lib.with_loudness(&[]).is_none());
+
+        // A measurement of an older version of the file is dropped.
+        let mut stale = (*tracks[1]).clone();
+        stale.size = Some(2);
+        assert!(
+            lib.with_loudness(&[(Arc::new(stale), level(-10.0))])
+                .is_none()
+        );
+
+        let lib = lib
+            .with_loudness(&[(tracks[1].clone(), level(-10.0))])
+            .unwrap();
+        let album = lib.tracks()[1].album_loudness.unwrap();
+        assert!((album.integrated + 10.0).abs() < 1e-9);
+        assert_eq!(lib.tracks()[0].album_loudness, Some(album));
+        assert_eq!(lib.tracks()[2].album_loudness, None);
+    }
+
+    #[test]
+    fn first_seen_survives_rescans() {
+        let tmp = tempfile::Builder::new().prefix("musrv").tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        touch(&root.join("A/one.mp3"));
+        let old = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        fs::File::options()
+            .write(true)
+            .open(root.join("A/one.mp3"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        let lib = Library::scan(root.clone());
+        assert_eq!(lib.tracks()[0].first_seen, Some(1_600_000_000));
+
+        let before = time::unix_now();
+        touch(&root.join("B/two.mp3"));
+        let (lib, _) = lib.rescan_folder("", &mut |_| {});
+        assert_eq!(lib.tracks()[0].first_seen, Some(1_600_000_000));
+        assert!(lib.tracks()[1].first_seen.unwrap() >= before);
+    }
+}
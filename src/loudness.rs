@@ -0,0 +1,294 @@
+use std::f64::consts::PI;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use anyhow::Context;
+use lofty::{ItemKey, Tag};
+use serde::{Deserialize, Serialize};
+
+use crate::decode;
+
+/// ReplayGain 2.0 plays everything at this loudness.
+pub const REFERENCE_LUFS: f64 = -18.0;
+/// R128 gain tags are relative to -23 LUFS rather than the ReplayGain
+/// reference.
+const R128_OFFSET_DB: f64 = REFERENCE_LUFS - -23.0;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Gain and peak values read from ReplayGain or R128 tags. Gains are in dB
+/// against the ReplayGain reference, peaks are linear (1.0 is full scale).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGain {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_gain: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_peak: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_gain: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_peak: Option<f64>,
+}
+
+/// Measured EBU R128 integrated loudness and sample peak.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// LUFS.
+    pub integrated: f64,
+    /// Linear, 1.0 is full scale.
+    pub peak: f64,
+}
+
+impl Loudness {
+    /// The ReplayGain 2.0 gain that brings this to the reference level.
+    pub fn gain(&self) -> f64 {
+        REFERENCE_LUFS - self.integrated
+    }
+}
+
+/// Reads ReplayGain tags, or R128 gain tags as written for Opus files.
+pub fn from_tag(tag: &Tag) -> Option<ReplayGain> {
+    let value = |key: ItemKey| tag.get_string(&key).and_then(parse_db);
+    let r128 = |name: &str| {
+        tag.items()
+            .find(|item| matches!(item.key(), ItemKey::Unknown(k) if k.eq_ignore_ascii_case(name)))
+            .and_then(|item| item.value().text())
+            .and_then(|v| v.trim().parse::<i32>().ok())
+            .map(|q| f64::from(q) / 256.0 + R128_OFFSET_DB)
+    };
+    let gain = ReplayGain {
+        track_gain: value(ItemKey::ReplayGainTrackGain).or_else(|| r128("R128_TRACK_GAIN")),
+        track_peak: value(ItemKey::ReplayGainTrackPeak),
+        album_gain: value(ItemKey::ReplayGainAlbumGain).or_else(|| r128("R128_ALBUM_GAIN")),
+        album_peak: value(ItemKey::ReplayGainAlbumPeak),
+    };
+    (gain != ReplayGain::default()).then_some(gain)
+}
+
+/// Parses values such as `-6.54 dB` or `0.988547`.
+fn parse_db(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = value
+        .len()
+        .checked_sub(2)
+        .filter(|&n| value.is_char_boundary(n) && value[n..].eq_ignore_ascii_case("db"))
+        .map_or(value, |n| &value[..n]);
+    number.trim().parse().ok().filter(|v: &f64| v.is_finite())
+}
+
+/// Combines the loudness of an album's tracks, weighted by duration. This
+/// averages the tracks' energy rather than gating the whole album at once,
+/// which is close enough to judge one album against another.
+pub fn album(tracks: impl IntoIterator<Item = (Loudness, f64)>) -> Option<Loudness> {
+    let mut energy = 0.0;
+    let mut total = 0.0;
+    let mut peak: f64 = 0.0;
+    for (loudness, duration) in tracks {
+        let duration = duration.max(0.0);
+        energy += duration * 10f64.powf(loudness.integrated / 10.0);
+        total += duration;
+        peak = peak.max(loudness.peak);
+    }
+    (total > 0.0).then(|| Loudness {
+        integrated: 10.0 * (energy / total).log10(),
+        peak,
+    })
+}
+
+/// Decodes a file and measures it. Takes about as long as reading the
+/// whole file plus decoding it.
+pub fn analyze(path: &Path) -> anyhow::Result<Loudness> {
+    let mut meter: Option<Meter> = None;
+    decode::decode(path, |spec, samples| {
+        meter
+            .get_or_insert_with(|| Meter::new(spec.rate, spec.channels))
+            .feed(samples);
+        ControlFlow::Continue(())
+    })?;
+    meter.context("no audio decoded")?.finish()
+}
+
+/// One second-order IIR section.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn run(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The ITU-R BS.1770 K-weighting filter (a high shelf followed by a high
+/// pass), designed for any sample rate.
+fn k_weighting(rate: f64) -> [Biquad; 2] {
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+/// Integrated loudness per EBU R128: K-weighted energy in 400 ms blocks
+/// overlapping by 75%, gated at -70 LUFS and then 10 LU below the
+/// ungated mean.
+struct Meter {
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Samples per channel in each 100 ms step.
+    step_len: usize,
+    step_fill: usize,
+    step_sum: Vec<f64>,
+    /// Mean square per 100 ms step, already weighted and summed over channels.
+    steps: Vec<f64>,
+    peak: f64,
+}
+
+impl Meter {
+    fn new(rate: u32, channels: usize) -> Self {
+        // 5.1 has its low-frequency channel left out and the surrounds
+        // boosted; everything else counts each channel once.
+        let weights = if channels == 6 {
+            vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+        } else {
+            vec![1.0; channels]
+        };
+        Meter {
+            filters: vec![k_weighting(f64::from(rate)); channels],
+            weights,
+            step_len: (rate as usize / 10).max(1),
+            step_fill: 0,
+            step_sum: vec![0.0; channels],
+            steps: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Takes interleaved samples.
+    fn feed(&mut self, samples: &[f32]) {
+        let channels = self.filters.len();
+        for frame in samples.chunks_exact(channels) {
+            for (c, &sample) in frame.iter().enumerate() {
+                let x = f64::from(sample);
+                self.peak = self.peak.max(x.abs());
+                let [shelf, high_pass] = &mut self.filters[c];
+                let y = high_pass.run(shelf.run(x));
+                self.step_sum[c] += y * y;
+            }
+            self.step_fill += 1;
+            if self.step_fill == self.step_len {
+                let energy = self
+                    .step_sum
+                    .iter()
+                    .zip(&self.weights)
+                    .map(|(sum, w)| w * sum / self.step_len as f64)
+                    .sum();
+                self.steps.push(energy);
+                self.step_sum.iter_mut().for_each(|s| *s = 0.0);
+                self.step_fill = 0;
+            }
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<Loudness> {
+        let lufs = |energy: f64| -0.691 + 10.0 * energy.log10();
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(4)
+            .map(|w| w.iter().sum::<f64>() / 4.0)
+            .filter(|&e| lufs(e) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        anyhow::ensure!(!blocks.is_empty(), "too short or silent to measure");
+        let mean = |blocks: &mut dyn Iterator<Item = f64>| {
+            let (sum, n) = blocks.fold((0.0, 0usize), |(s, n), e| (s + e, n + 1));
+            sum / n as f64
+        };
+        let gate = lufs(mean(&mut blocks.iter().copied())) + RELATIVE_GATE_LU;
+        let gated = mean(&mut blocks.iter().copied().filter(|&e| lufs(e) > gate));
+        Ok(Loudness {
+            integrated: lufs(gated),
+            peak: self.peak,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, seconds: u32, amplitude: f32) -> Vec<f32> {
+        (0..rate * seconds)
+            .flat_map(|n| {
+                let s =
+                    amplitude * (2.0 * std::f32::consts::PI * 997.0 * n as f32 / rate as f32).sin();
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn measures_a_reference_tone() {
+        // EBU Tech 3341: a 1 kHz stereo sine reads its level in dBFS as
+        // LUFS, so one at -20 dBFS reads -20.
+        for rate in [44_100, 48_000] {
+            let mut meter = Meter::new(rate, 2);
+            meter.feed(&sine(rate, 5, 0.1));
+            let loudness = meter.finish().unwrap();
+            assert!(
+                (loudness.integrated + 20.0).abs() < 0.05,
+                "{rate}: {}",
+                loudness.integrated
+            );
+            assert!((loudness.peak - 0.1).abs() < 1e-3);
+            assert!((loudness.gain() - 2.0).abs() < 0.05);
+        }
+        let mut meter = Meter::new(48_000, 2);
+        meter.feed(&[0.0; 48_000 * 2]);
+        assert!(meter.finish().is_err());
+    }
+
+    #[test]
+    fn parses_gain_values_and_combines_albums() {
+        assert_eq!(parse_db("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_db("+1.5dB"), Some(1.5));
+        assert_eq!(parse_db("0.988547"), Some(0.988547));
+        assert_eq!(parse_db("loud"), None);
+
+        let track = |integrated| Loudness {
+            integrated,
+            peak: 0.5,
+        };
+        let same = album([(track(-10.0), 100.0), (track(-10.0), 50.0)]).unwrap();
+        assert!((same.integrated + 10.0).abs() < 1e-9);
+        let mixed = album([(track(-10.0), 100.0), (track(-20.0), 100.0)]).unwrap();
+        assert!(mixed.integrated < -10.0 && mixed.integrated > -13.0);
+        assert_eq!(album([]), None);
+    }
+}
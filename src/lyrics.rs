@@ -0,0 +1,201 @@
+//! Lyrics from embedded tags (`USLT`/`SYLT`, `LYRICS`, `©lyr`) and from
+//! `<track>.lrc` or `<track>.txt` files next to the track.
+
+use std::fs;
+use std::path::Path;
+
+use lofty::id3::v2::{SynchronizedText, TimestampFormat};
+use lofty::{ItemKey, ItemValue, TaggedFileExt};
+use serde::{Deserialize, Serialize};
+
+/// Sidecar extensions, in order of preference.
+const SIDECAR_EXTENSIONS: &[&str] = &["lrc", "txt"];
+
+/// Ordered so that synchronized lyrics rank above plain ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsKind {
+    /// Plain text without timing.
+    Plain,
+    /// Lines with timestamps.
+    Synced,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LyricLine {
+    /// Seconds from the start of the track.
+    pub time: f64,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lyrics {
+    Plain(String),
+    Synced(Vec<LyricLine>),
+}
+
+impl Lyrics {
+    /// Treats text as LRC when it has timed lines and as plain text
+    /// otherwise.
+    pub fn from_text(text: &str) -> Option<Lyrics> {
+        let text = text.trim_start_matches('\u{feff}');
+        if let Some(lines) = parse_lrc(text) {
+            return Some(Lyrics::Synced(lines));
+        }
+        let text = text.trim();
+        (!text.is_empty()).then(|| Lyrics::Plain(text.replace("\r\n", "\n")))
+    }
+
+    pub fn kind(&self) -> LyricsKind {
+        match self {
+            Lyrics::Plain(_) => LyricsKind::Plain,
+            Lyrics::Synced(_) => LyricsKind::Synced,
+        }
+    }
+}
+
+/// Parses LRC text into lines sorted by time. Lines may carry several
+/// timestamps, and an `[offset:±ms]` tag shifts them all. Returns `None`
+/// when no line has a timestamp.
+pub fn parse_lrc(text: &str) -> Option<Vec<LyricLine>> {
+    let mut offset = 0.0;
+    let mut lines = Vec::new();
+    for raw in text.lines() {
+        let mut rest = raw.trim();
+        let mut times = Vec::new();
+        while let Some(tail) = rest.strip_prefix('[') {
+            let Some((tag, after)) = tail.split_once(']') else {
+                break;
+            };
+            if let Some(time) = parse_timestamp(tag) {
+                times.push(time);
+            } else if let Some(value) = tag.strip_prefix("offset:") {
+                // Positive offsets make lyrics appear sooner.
+                offset = value.trim().parse::<f64>().unwrap_or(0.0) / 1000.0;
+            }
+            rest = after;
+        }
+        let text = rest.trim();
+        for time in times {
+            lines.push(LyricLine {
+                time,
+                text: text.to_string(),
+            });
+        }
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    for line in &mut lines {
+        line.time = (line.time - offset).max(0.0);
+    }
+    lines.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Some(lines)
+}
+
+/// `mm:ss`, `mm:ss.xx` or `mm:ss:xx`.
+fn parse_timestamp(tag: &str) -> Option<f64> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes: u32 = minutes.trim().parse().ok()?;
+    let seconds = seconds.trim().replacen(':', ".", 1);
+    if !seconds.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let seconds: f64 = seconds.parse().ok()?;
+    (seconds < 60.0).then(|| f64::from(minutes) * 60.0 + seconds)
+}
+
+/// The sidecar lyrics file next to a track, if there is one with lyrics in
+/// it, and whether those are synchronized. LRC files without timestamps
+/// count as plain text, as that is how they are served.
+pub fn sidecar(track: &Path) -> Option<(String, LyricsKind)> {
+    let stem = track.file_stem()?.to_str()?;
+    let name = SIDECAR_EXTENSIONS.iter().find_map(|ext| {
+        let name = format!("{stem}.{ext}");
+        track.with_file_name(&name).is_file().then_some(name)
+    })?;
+    let kind = read_sidecar(&track.with_file_name(&name))?.kind();
+    Some((name, kind))
+}
+
+pub fn read_sidecar(path: &Path) -> Option<Lyrics> {
+    let data = fs::read(path).ok()?;
+    Lyrics::from_text(&String::from_utf8_lossy(&data))
+}
+
+/// Which kind of lyrics a tag embeds, preferring synchronized ones.
+pub fn embedded_kind(tag: &lofty::Tag) -> Option<LyricsKind> {
+    if synced_frame(tag).is_some() {
+        return Some(LyricsKind::Synced);
+    }
+    tag.get_string(&ItemKey::Lyrics)
+        .and_then(Lyrics::from_text)
+        .map(|l| l.kind())
+}
+
+pub fn read_embedded(path: &Path) -> Option<Lyrics> {
+    let tagged = lofty::read_from_path(path).ok()?;
+    let tag = tagged.primary_tag().or_else(|| tagged.first_tag())?;
+    if let Some(lines) = synced_frame(tag) {
+        return Some(Lyrics::Synced(lines));
+    }
+    tag.get_string(&ItemKey::Lyrics).and_then(Lyrics::from_text)
+}
+
+/// Lines of an ID3v2 `SYLT` frame with millisecond timestamps. lofty keeps
+/// these frames as raw bytes.
+fn synced_frame(tag: &lofty::Tag) -> Option<Vec<LyricLine>> {
+    tag.items().find_map(|item| {
+        let ItemKey::Unknown(key) = item.key() else {
+            return None;
+        };
+        let ItemValue::Binary(data) = item.value() else {
+            return None;
+        };
+        if key != "SYLT" {
+            return None;
+        }
+        let frame = SynchronizedText::parse(data).ok()?;
+        if frame.timestamp_format != TimestampFormat::MS || frame.content.is_empty() {
+            return None;
+        }
+        let lines = frame
+            .content
+            .into_iter()
+            .map(|(ms, text)| LyricLine {
+                time: f64::from(ms) / 1000.0,
+                text: text.trim().to_string(),
+            })
+            .collect();
+        Some(lines)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lrc_with_offsets_and_repeats() {
+        let text = "\u{feff}[ar:Someone]\n[offset:+500]\n[00:12.50][01:02.00]Chorus\n[00:05.25] Verse\nno timing\n";
+        let Some(Lyrics::Synced(lines)) = Lyrics::from_text(text) else {
+            panic!("expected synced lyrics");
+        };
+        let parsed: Vec<(f64, &str)> = lines.iter().map(|l| (l.time, l.text.as_str())).collect();
+        assert_eq!(
+            parsed,
+            vec![(4.75, "Verse"), (12.0, "Chorus"), (61.5, "Chorus")]
+        );
+    }
+
+    #[test]
+    fn plain_text_without_timestamps() {
+        assert_eq!(
+            Lyrics::from_text("[Chorus]\r\nLa la\r\n"),
+            Some(Lyrics::Plain("[Chorus]\nLa la".into()))
+        );
+        assert_eq!(Lyrics::from_text("  \n"), None);
+        assert_eq!(parse_timestamp("03:07:25"), Some(187.25));
+        assert_eq!(parse_timestamp("ar:x"), None);
+    }
+}
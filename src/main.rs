@@ -1,12 +1,29 @@
+mod chapters;
+mod cue;
+mod decode;
+mod dupes;
+mod history;
 mod library;
+mod loudness;
+mod lyrics;
 mod path_utils;
 mod playlist;
+mod playlist_store;
+mod ratings;
+mod resume;
+mod scan_report;
 mod server;
+mod smart_playlist;
+mod tag_writer;
+#[cfg(test)]
+#[path = "../tests/common/fixtures.rs"]
+mod test_fixtures;
+mod time;
+mod waveform;
 
 use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::{Arc, atomic::AtomicBool};
 
 use axum::Router;
 use clap::{ArgAction, Parser, Subcommand};
@@ -29,11 +46,12 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Serve a music folder over HTTP
+    /// Serve one or more music folders over HTTP
     Serve {
-        /// Root directory to scan and serve
-        #[arg(value_name = "ROOT", value_hint = clap::ValueHint::DirPath)]
-        path: PathBuf,
+        /// Root directories to scan and serve, optionally as NAME=DIR to mount
+        /// them under a named prefix (e.g. music=/mnt/a books=/mnt/b)
+        #[arg(value_name = "ROOT", required = true, num_args = 1.., value_hint = clap::ValueHint::DirPath)]
+        roots: Vec<String>,
 
         /// TCP port to listen on (default: 8080)
         #[arg(long, value_name = "PORT")]
@@ -50,6 +68,62 @@ enum Commands {
         /// Print a QR code for the UI URL
         #[arg(long)]
         qr: bool,
+
+        /// Token required for /admin/* requests (random if not set)
+        #[arg(
+            long = "admin-token",
+            value_name = "TOKEN",
+            env = "MUSRV_ADMIN_TOKEN",
+            hide_env_values = true
+        )]
+        admin_token: Option<String>,
+
+        /// Directory for server-side data such as saved playlists
+        /// (default: the first root's .musrv folder)
+        #[arg(long = "data-dir", value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+        data_dir: Option<PathBuf>,
+
+        /// Rewrite a path prefix in playlist files found in the library, as
+        /// FROM=TO where TO is a library path (e.g. 'C:\Music=music');
+        /// may be repeated
+        #[arg(long = "playlist-remap", value_name = "FROM=TO")]
+        playlist_remaps: Vec<String>,
+
+        /// Also write ratings, favorites and play counts into the audio
+        /// files' tags (modifies your music files)
+        #[arg(long = "allow-tag-writes")]
+        allow_tag_writes: bool,
+
+        /// Largest file accepted by the admin upload endpoint, in MiB
+        #[arg(long = "max-upload-size", value_name = "MIB", default_value_t = 2048)]
+        max_upload_mib: u64,
+
+        /// Mark a folder as long-form content such as audiobooks or DJ
+        /// mixes, so players resume where they left off; use a root's name
+        /// for the whole root; may be repeated
+        #[arg(long = "long-form", value_name = "FOLDER")]
+        long_form: Vec<String>,
+
+        /// Decode tracks without ReplayGain tags in the background after
+        /// each scan to measure their loudness (uses CPU while it runs)
+        #[arg(long = "analyze-loudness")]
+        analyze_loudness: bool,
+    },
+    /// Report likely duplicate tracks: identical files, the same audio
+    /// with different tags, and the same artist and title at nearly the
+    /// same length
+    Dupes {
+        /// Root directories to check; uses the cached scan when there is one
+        #[arg(value_name = "ROOT", required = true, num_args = 1.., value_hint = clap::ValueHint::DirPath)]
+        roots: Vec<PathBuf>,
+
+        /// Seconds two durations may differ by for an artist and title match
+        #[arg(long, value_name = "SECS", default_value_t = dupes::DEFAULT_TOLERANCE_SECS)]
+        tolerance: f64,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
     },
 }
 
@@ -65,30 +139,77 @@ async fn main() -> anyhow::Result<()> {
     fmt().with_env_filter(filter).init();
     match cli.command {
         Commands::Serve {
-            path,
+            roots,
             port,
             bind,
             public_url,
             qr,
+            admin_token,
+            data_dir,
+            playlist_remaps,
+            allow_tag_writes,
+            max_upload_mib,
+            long_form,
+            analyze_loudness,
         } => {
-            if !Path::new(&path).exists() {
-                anyhow::bail!("path does not exist: {}", path.display());
-            }
-            if !std::fs::metadata(&path)
-                .map(|m| m.is_dir())
-                .unwrap_or(false)
-            {
-                anyhow::bail!("path is not a directory: {}", path.display());
-            }
-            let root = std::fs::canonicalize(&path).unwrap_or(path);
-            let (initial_library, cached_ready) = match library::Library::load_cached(&root) {
-                Ok(lib) => (lib, true),
-                Err(err) => {
-                    tracing::warn!(?err, "failed to load cached library");
-                    (library::Library::empty(root.clone()), false)
+            let playlist_remaps = playlist_remaps
+                .iter()
+                .map(|arg| {
+                    let (from, to) = arg
+                        .split_once('=')
+                        .ok_or_else(|| anyhow::anyhow!("invalid playlist remap: {arg}"))?;
+                    Ok(playlist::PathRemap {
+                        from: from.to_string(),
+                        to: to.to_string(),
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let specs = roots
+                .iter()
+                .map(|arg| parse_root(arg))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let single = specs.len() == 1 && specs[0].0.is_none();
+            let mut mounts: Vec<server::state::Mount> = Vec::new();
+            for (name, path) in specs {
+                if !Path::new(&path).exists() {
+                    anyhow::bail!("path does not exist: {}", path.display());
                 }
-            };
-            let lib = Arc::new(initial_library);
+                if !std::fs::metadata(&path)
+                    .map(|m| m.is_dir())
+                    .unwrap_or(false)
+                {
+                    anyhow::bail!("path is not a directory: {}", path.display());
+                }
+                let root = std::fs::canonicalize(&path).unwrap_or(path);
+                let name = match name {
+                    Some(name) => name,
+                    None if single => String::new(),
+                    None => root
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .map(str::to_string)
+                        .filter(|n| valid_mount_name(n))
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("cannot derive a mount name for {}", root.display())
+                        })?,
+                };
+                if mounts.iter().any(|m| m.name == name) {
+                    anyhow::bail!("duplicate mount name: {name}");
+                }
+                let (initial_library, cached_ready) = match library::Library::load_cached(&root) {
+                    Ok(lib) => (lib, true),
+                    Err(err) => {
+                        tracing::warn!(?err, root = %root.display(), "failed to load cached library");
+                        (library::Library::empty(root.clone()), false)
+                    }
+                };
+                mounts.push(server::state::Mount::new(
+                    name,
+                    root,
+                    initial_library,
+                    cached_ready,
+                ));
+            }
             let bind = bind.unwrap_or_else(|| "127.0.0.1".parse().unwrap());
             let port = port.unwrap_or(8080);
             let default_host = if bind.is_unspecified() {
@@ -105,20 +226,40 @@ async fn main() -> anyhow::Result<()> {
                 None => format!("http://{default_host}:{port}/"),
             };
             let listen_addr = format!("http://{bind}:{port}/");
-            let state = server::AppState {
-                lib: Arc::new(arc_swap::ArcSwap::from(lib.clone())),
-                base: base.clone(),
-                root: root.clone(),
-                scan_ready: Arc::new(AtomicBool::new(cached_ready)),
-                scan_in_progress: Arc::new(AtomicBool::new(false)),
-            };
-            state.schedule_scan(!cached_ready);
+            let admin_token = admin_token.filter(|t| !t.is_empty());
+            let generated_token = admin_token.is_none();
+            let admin_token = admin_token.unwrap_or_else(server::auth::generate_token);
+            let mut state = server::AppState::new(base.clone(), mounts)
+                .with_admin_token(admin_token.clone())
+                .with_playlist_remaps(playlist_remaps)
+                .with_tag_writes(allow_tag_writes)
+                .with_max_upload_bytes(max_upload_mib.saturating_mul(1024 * 1024))
+                .with_long_form(long_form)
+                .with_loudness_analysis(analyze_loudness);
+            if let Some(dir) = data_dir {
+                state = state.with_data_dir(dir);
+            }
+            state.schedule_scan(false);
             let app: Router = server::build_router(state.clone());
             let addr = SocketAddr::new(bind, port);
-            println!("root: {}", root.display());
+            for mount in state.mounts.iter() {
+                if mount.name.is_empty() {
+                    println!("root: {}", mount.root.display());
+                } else {
+                    println!("root: {} = {}", mount.name, mount.root.display());
+                }
+            }
             println!("listen: {}", listen_addr.trim_end_matches('/'));
-            println!("tracks: {}", lib.tracks().len());
+            let track_count: usize = state
+                .mounts
+                .iter()
+                .map(|m| m.lib.load().tracks().len())
+                .sum();
+            println!("tracks: {track_count}");
             println!("ui: {}", base.trim_end_matches('/'));
+            if generated_token {
+                println!("admin token: {admin_token}");
+            }
             if qr {
                 let ui_url = base.trim_end_matches('/');
                 if let Ok(code) = qrcode::QrCode::new(ui_url.as_bytes()) {
@@ -130,10 +271,65 @@ async fn main() -> anyhow::Result<()> {
             let listener = tokio::net::TcpListener::bind(addr).await?;
             axum::serve(listener, app).await?;
         }
+        Commands::Dupes {
+            roots,
+            tolerance,
+            json,
+        } => {
+            let mut candidates: Vec<dupes::Candidate> = Vec::new();
+            for path in roots {
+                if !path.is_dir() {
+                    anyhow::bail!("path is not a directory: {}", path.display());
+                }
+                let root = std::fs::canonicalize(&path).unwrap_or(path);
+                let lib = library::Library::load_cached(&root)
+                    .unwrap_or_else(|_| library::Library::scan(root.clone()));
+                for track in lib.tracks() {
+                    let file = root.join(&track.path);
+                    candidates.push(dupes::Candidate {
+                        path: file.display().to_string(),
+                        file,
+                        track: track.clone(),
+                    });
+                }
+            }
+            let report =
+                tokio::task::spawn_blocking(move || dupes::find(&candidates, tolerance)).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_dupes(&report);
+            }
+        }
     }
     Ok(())
 }
 
+fn print_dupes(report: &dupes::DupeReport) {
+    for group in &report.groups {
+        let kind = match group.kind {
+            dupes::DupeKind::Exact => "identical files",
+            dupes::DupeKind::Audio => "same audio, different tags",
+            dupes::DupeKind::Metadata => "same artist, title and length",
+        };
+        println!("{kind}:");
+        for track in &group.tracks {
+            let size = track
+                .size
+                .map(|s| format!("{:.1} MB", s as f64 / 1_000_000.0))
+                .unwrap_or_else(|| "?".to_string());
+            let format = track.format.as_deref().unwrap_or("?");
+            println!("  {size:>10}  {format:<5} {}", track.path);
+        }
+        println!();
+    }
+    println!(
+        "{} duplicate groups, {} files read",
+        report.groups.len(),
+        report.files_hashed
+    );
+}
+
 fn normalize_base(input: &str) -> String {
     let trimmed = input.trim();
     if trimmed.is_empty() {
@@ -146,3 +342,25 @@ fn normalize_base(input: &str) -> String {
     };
     format!("{}/", with_scheme.trim_end_matches('/'))
 }
+
+/// Splits a `NAME=DIR` root argument. Anything whose prefix before `=` is not
+/// a plain name is treated as a bare directory.
+fn parse_root(arg: &str) -> anyhow::Result<(Option<String>, PathBuf)> {
+    if let Some((name, dir)) = arg.split_once('=')
+        && !name.is_empty()
+        && !name.contains(['/', '\\'])
+    {
+        if !valid_mount_name(name) {
+            anyhow::bail!("invalid mount name: {name}");
+        }
+        return Ok((Some(name.to_string()), PathBuf::from(dir)));
+    }
+    Ok((None, PathBuf::from(arg)))
+}
+
+fn valid_mount_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(['/', '\\', '\0'])
+        && !path_utils::is_hidden_name(name)
+        && !matches!(name, "api" | "admin")
+}
@@ -1,4 +1,7 @@
 use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub fn is_hidden_name(name: &str) -> bool {
     name.starts_with('.') || name.starts_with("._") || name == "Thumbs.db" || name == "desktop.ini"
@@ -7,3 +10,55 @@ pub fn is_hidden_name(name: &str) -> bool {
 pub fn is_hidden_component(component: &OsStr) -> bool {
     component.to_str().map(is_hidden_name).unwrap_or(false)
 }
+
+/// Writes through a temporary sibling and renames it over `path`, so readers
+/// never see a half-written file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(dir)?;
+    let tmp = temp_sibling(path, "tmp");
+    let written = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(data));
+    written
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp);
+        })
+}
+
+/// A hidden name next to `path` for a temporary file, never handed out twice
+/// by this process, so concurrent writers of one file don't share it.
+pub fn temp_sibling(path: &Path, suffix: &str) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!(".{file_name}.{}.{n}.{suffix}", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_siblings_are_unique() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("a.json");
+        let first = temp_sibling(&target, "tmp");
+        let second = temp_sibling(&target, "tmp");
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(tmp.path()));
+        assert!(is_hidden_component(first.file_name().unwrap()));
+
+        write_atomic(&target, b"one").unwrap();
+        write_atomic(&target, b"two").unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"two");
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+}
@@ -1,7 +1,134 @@
 use std::path::Path;
-use std::sync::Arc;
 
-use crate::library::Track;
+use crate::library::TrackRef;
+use crate::time;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "m3u" | "m3u8" => Some(PlaylistFormat::M3u),
+            "pls" => Some(PlaylistFormat::Pls),
+            _ => None,
+        }
+    }
+}
+
+/// Rewrites entries starting with `from` (e.g. `C:\Music`) to start with
+/// `to`, a path in the library (e.g. `music`).
+#[derive(Clone, Debug)]
+pub struct PathRemap {
+    pub from: String,
+    pub to: String,
+}
+
+/// Parses a playlist file into its title and entries, in order.
+pub fn parse_playlist(format: PlaylistFormat, data: &[u8]) -> (Option<String>, Vec<String>) {
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim_start_matches('\u{feff}');
+    match format {
+        PlaylistFormat::M3u => {
+            let mut title = None;
+            let mut entries = Vec::new();
+            for line in text.lines().map(str::trim) {
+                if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+                    title = Some(name.trim().to_string());
+                } else if !line.is_empty() && !line.starts_with('#') {
+                    entries.push(line.to_string());
+                }
+            }
+            (title, entries)
+        }
+        PlaylistFormat::Pls => {
+            let mut entries: Vec<(u32, String)> = Vec::new();
+            for line in text.lines().map(str::trim) {
+                let Some((key, value)) = line.split_once('=') else {
+                    continue;
+                };
+                if let Some(n) = key.strip_prefix("File").and_then(|n| n.parse().ok()) {
+                    entries.push((n, value.trim().to_string()));
+                }
+            }
+            entries.sort_by_key(|(n, _)| *n);
+            (None, entries.into_iter().map(|(_, e)| e).collect())
+        }
+    }
+}
+
+/// A playlist entry after remapping, ready to be matched to a track.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EntryPath {
+    /// Relative to the library (mount prefix included).
+    Library(String),
+    /// An absolute filesystem path, to be matched against the roots.
+    Absolute(String),
+}
+
+/// Normalizes one playlist entry. `dir` is the library path of the folder
+/// holding the playlist, used for relative entries. Returns `None` for
+/// entries that can never be local tracks, such as stream URLs.
+pub fn normalize_entry(entry: &str, dir: &str, remaps: &[PathRemap]) -> Option<EntryPath> {
+    let mut entry = entry.trim().to_string();
+    if let Some(rest) = entry.strip_prefix("file://") {
+        entry = urlencoding::decode(rest).ok()?.into_owned();
+        // file:///C:/x leaves a slash in front of the drive letter.
+        if entry.as_bytes().get(2) == Some(&b':') && entry.starts_with('/') {
+            entry.remove(0);
+        }
+    } else if entry.contains("://") {
+        return None;
+    }
+    let entry = entry.replace('\\', "/");
+    for remap in remaps {
+        let from = remap.from.replace('\\', "/");
+        let from = from.trim_end_matches('/');
+        if entry.len() >= from.len()
+            && entry.is_char_boundary(from.len())
+            && entry[..from.len()].eq_ignore_ascii_case(from)
+            && (entry.len() == from.len() || entry.as_bytes()[from.len()] == b'/')
+        {
+            let rest = entry[from.len()..].trim_start_matches('/');
+            let to = remap.to.trim_matches('/');
+            let joined = if to.is_empty() {
+                rest.to_string()
+            } else {
+                format!("{to}/{rest}")
+            };
+            return clean_relative(&joined).map(EntryPath::Library);
+        }
+    }
+    let is_drive = entry.len() > 2 && entry.as_bytes()[1] == b':' && entry.as_bytes()[2] == b'/';
+    if entry.starts_with('/') || is_drive {
+        return Some(EntryPath::Absolute(entry));
+    }
+    let joined = if dir.is_empty() {
+        entry
+    } else {
+        format!("{dir}/{entry}")
+    };
+    clean_relative(&joined).map(EntryPath::Library)
+}
+
+/// Resolves `.` and `..` segments; `None` if the path climbs above the top.
+fn clean_relative(path: &str) -> Option<String> {
+    let mut parts: Vec<&str> = Vec::new();
+    for seg in path.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            seg => parts.push(seg),
+        }
+    }
+    Some(parts.join("/"))
+}
 
 pub fn encode_path(rel: &str) -> String {
     rel.split('/')
@@ -10,51 +137,625 @@ pub fn encode_path(rel: &str) -> String {
         .join("/")
 }
 
-pub fn render_m3u8(base: &str, _root: &Path, tracks: &[Arc<Track>]) -> String {
-    let mut body = String::from("#EXTM3U\r\n");
-    for t in tracks {
+/// Output formats for generated playlists, picked by the endpoint's file
+/// extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    M3u8,
+    Pls,
+    Xspf,
+    Jspf,
+    Asx,
+    Rss,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 6] = [
+        ExportFormat::M3u8,
+        ExportFormat::Pls,
+        ExportFormat::Xspf,
+        ExportFormat::Jspf,
+        ExportFormat::Asx,
+        ExportFormat::Rss,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::M3u8 => "m3u8",
+            ExportFormat::Pls => "pls",
+            ExportFormat::Xspf => "xspf",
+            ExportFormat::Jspf => "jspf",
+            ExportFormat::Asx => "asx",
+            ExportFormat::Rss => "rss",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::M3u8 => "audio/x-mpegurl; charset=utf-8",
+            ExportFormat::Pls => "audio/x-scpls; charset=utf-8",
+            ExportFormat::Xspf => "application/xspf+xml; charset=utf-8",
+            ExportFormat::Jspf => "application/jspf+json; charset=utf-8",
+            ExportFormat::Asx => "video/x-ms-asf; charset=utf-8",
+            ExportFormat::Rss => "application/rss+xml; charset=utf-8",
+        }
+    }
+
+    /// Splits `name.ext` into the name and a known export format.
+    pub fn split_name(name: &str) -> (&str, Option<ExportFormat>) {
+        for format in ExportFormat::ALL {
+            if let Some(stem) = name
+                .strip_suffix(format.extension())
+                .and_then(|s| s.strip_suffix('.'))
+            {
+                return (stem, Some(format));
+            }
+        }
+        (name, None)
+    }
+}
+
+/// Settings shared by every output format.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderOptions<'a> {
+    /// Public URL prefix for track and artwork links.
+    pub base: &'a str,
+    /// Playlist name, written where the format has a place for it.
+    pub title: &'a str,
+    /// Public folder that track locations are written relative to, so the
+    /// playlist can be copied next to the music. Absolute URLs otherwise.
+    pub relative_to: Option<&'a str>,
+    /// Write `#PLAYLIST`, `#EXTALB`, `#EXTART` and `#EXTIMG` in M3U8. Off
+    /// for players that reject directives other than `#EXTINF`.
+    pub extended: bool,
+}
+
+impl<'a> RenderOptions<'a> {
+    pub fn new(base: &'a str, title: &'a str) -> Self {
+        RenderOptions {
+            base,
+            title,
+            relative_to: None,
+            extended: true,
+        }
+    }
+
+    pub fn relative_to(mut self, folder: &'a str) -> Self {
+        self.relative_to = Some(folder);
+        self
+    }
+
+    pub fn extended(mut self, extended: bool) -> Self {
+        self.extended = extended;
+        self
+    }
+}
+
+/// Per-track details shared by every output format.
+struct Entry<'a> {
+    /// URI reference for formats that require one.
+    url: String,
+    /// Plain location for line-based formats; unescaped when relative.
+    file: String,
+    title: String,
+    creator: Option<&'a str>,
+    album: Option<&'a str>,
+    duration: Option<f64>,
+    image: Option<String>,
+    /// Offsets into `file` for CUE tracks written as relative paths, where
+    /// there is no server to cut the rip.
+    span: Option<(f64, Option<f64>)>,
+}
+
+fn entries<'a>(
+    opts: &RenderOptions<'_>,
+    tracks: &'a [TrackRef],
+) -> impl Iterator<Item = Entry<'a>> {
+    let base = opts.base.to_string();
+    let relative_to = opts.relative_to.map(|folder| {
+        if folder.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", folder.trim_end_matches('/'))
+        }
+    });
+    tracks.iter().map(move |item| {
+        let t = &item.track;
         let file_name = t.path.file_name().and_then(|s| s.to_str()).unwrap_or("");
-        let mut display = t.metadata.title.as_deref().unwrap_or(file_name).to_string();
-        if let Some(artist) = t
-            .metadata
-            .artist
+        let meta = &t.metadata;
+        let relative = relative_to
             .as_deref()
-            .filter(|artist| !artist.is_empty())
-        {
-            display = format!("{artist} - {display}");
+            .map(|prefix| item.path.strip_prefix(prefix).unwrap_or(&item.path));
+        let mut span = None;
+        let (url, file) = match relative {
+            Some(rel) => match &t.cue {
+                Some(cue) => {
+                    let rip = item.sibling_path(&cue.source);
+                    let prefix = relative_to.as_deref().unwrap_or("");
+                    let rip = rip.strip_prefix(prefix).unwrap_or(&rip).to_string();
+                    span = Some((cue.start, cue.end));
+                    (encode_path(&rip), rip)
+                }
+                None => (encode_path(rel), rel.to_string()),
+            },
+            None => {
+                let url = format!("{base}{}", encode_path(&item.path));
+                (url.clone(), url)
+            }
+        };
+        Entry {
+            url,
+            file,
+            title: meta.title.as_deref().unwrap_or(file_name).to_string(),
+            creator: meta.artist.as_deref().filter(|a| !a.is_empty()),
+            album: meta.album.as_deref().filter(|a| !a.is_empty()),
+            duration: meta.duration,
+            image: meta
+                .artwork_id
+                .as_ref()
+                .map(|id| format!("{}/api/artwork/{id}", base.trim_end_matches('/'))),
+            span,
         }
-        let duration = t.metadata.duration.map(|d| d.round() as i64).unwrap_or(0);
-        let rel = t.path.to_string_lossy().replace('\\', "/");
-        let encoded = encode_path(&rel);
+    })
+}
+
+/// Renders tracks in any export format.
+pub fn render(format: ExportFormat, opts: &RenderOptions<'_>, tracks: &[TrackRef]) -> String {
+    match format {
+        ExportFormat::M3u8 => render_m3u8(opts, tracks),
+        ExportFormat::Pls => render_pls(opts, tracks),
+        ExportFormat::Xspf => render_xspf(opts, tracks),
+        ExportFormat::Jspf => render_jspf(opts, tracks),
+        ExportFormat::Asx => render_asx(opts, tracks),
+        ExportFormat::Rss => render_rss(opts, tracks),
+    }
+}
+
+/// Extended M3U with `#EXTALB`, `#EXTART` and `#EXTIMG` per track where the
+/// tags are known, and a `#PLAYLIST` name when one is given, unless
+/// [`RenderOptions::extended`] is off. CUE offsets are always written, as
+/// relative entries play the whole rip without them.
+pub fn render_m3u8(opts: &RenderOptions<'_>, tracks: &[TrackRef]) -> String {
+    let mut body = String::from("#EXTM3U\r\n");
+    if opts.extended && !opts.title.is_empty() {
+        body.push_str(&format!("#PLAYLIST:{}\r\n", single_line(opts.title)));
+    }
+    for entry in entries(opts, tracks) {
+        let display = match entry.creator {
+            Some(artist) => format!("{artist} - {}", entry.title),
+            None => entry.title,
+        };
+        let duration = entry.duration.map(|d| d.round() as i64).unwrap_or(0);
+        body.push_str(&format!("#EXTINF:{duration},{}\r\n", single_line(&display)));
+        if opts.extended {
+            if let Some(album) = entry.album {
+                body.push_str(&format!("#EXTALB:{}\r\n", single_line(album)));
+            }
+            if let Some(artist) = entry.creator {
+                body.push_str(&format!("#EXTART:{}\r\n", single_line(artist)));
+            }
+            if let Some(image) = &entry.image {
+                body.push_str(&format!("#EXTIMG:{image}\r\n"));
+            }
+        }
+        if let Some((start, stop)) = entry.span {
+            body.push_str(&format!("#EXTVLCOPT:start-time={start:.3}\r\n"));
+            if let Some(stop) = stop {
+                body.push_str(&format!("#EXTVLCOPT:stop-time={stop:.3}\r\n"));
+            }
+        }
+        body.push_str(&entry.file);
+        body.push_str("\r\n");
+    }
+    body
+}
+
+pub fn render_pls(opts: &RenderOptions<'_>, tracks: &[TrackRef]) -> String {
+    let mut body = String::from("[playlist]\r\n");
+    let mut count = 0;
+    for (i, entry) in entries(opts, tracks).enumerate() {
+        let n = i + 1;
+        let title = match entry.creator {
+            Some(artist) => format!("{artist} - {}", entry.title),
+            None => entry.title,
+        };
+        let length = entry.duration.map(|d| d.round() as i64).unwrap_or(-1);
         body.push_str(&format!(
-            "#EXTINF:{duration},{display}\r\n{base}{encoded}\r\n"
+            "File{n}={}\r\nTitle{n}={}\r\nLength{n}={length}\r\n",
+            entry.file,
+            single_line(&title)
         ));
+        count = n;
     }
+    body.push_str(&format!("NumberOfEntries={count}\r\nVersion=2\r\n"));
     body
 }
 
+pub fn render_xspf(opts: &RenderOptions<'_>, tracks: &[TrackRef]) -> String {
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    body.push_str(&format!(
+        "  <title>{}</title>\n  <trackList>\n",
+        xml_escape(opts.title)
+    ));
+    for entry in entries(opts, tracks) {
+        body.push_str("    <track>\n");
+        body.push_str(&format!(
+            "      <location>{}</location>\n",
+            xml_escape(&entry.url)
+        ));
+        body.push_str(&format!(
+            "      <title>{}</title>\n",
+            xml_escape(&entry.title)
+        ));
+        if let Some(creator) = entry.creator {
+            body.push_str(&format!(
+                "      <creator>{}</creator>\n",
+                xml_escape(creator)
+            ));
+        }
+        if let Some(album) = entry.album {
+            body.push_str(&format!("      <album>{}</album>\n", xml_escape(album)));
+        }
+        if let Some(duration) = entry.duration {
+            let ms = (duration * 1000.0).round() as u64;
+            body.push_str(&format!("      <duration>{ms}</duration>\n"));
+        }
+        if let Some(image) = &entry.image {
+            body.push_str(&format!("      <image>{}</image>\n", xml_escape(image)));
+        }
+        body.push_str("    </track>\n");
+    }
+    body.push_str("  </trackList>\n</playlist>\n");
+    body
+}
+
+pub fn render_jspf(opts: &RenderOptions<'_>, tracks: &[TrackRef]) -> String {
+    let track_list: Vec<serde_json::Value> = entries(opts, tracks)
+        .map(|entry| {
+            let mut track = serde_json::json!({
+                "location": [entry.url],
+                "title": entry.title,
+            });
+            if let Some(creator) = entry.creator {
+                track["creator"] = creator.into();
+            }
+            if let Some(album) = entry.album {
+                track["album"] = album.into();
+            }
+            if let Some(duration) = entry.duration {
+                track["duration"] = ((duration * 1000.0).round() as u64).into();
+            }
+            if let Some(image) = entry.image {
+                track["image"] = image.into();
+            }
+            track
+        })
+        .collect();
+    let doc = serde_json::json!({
+        "playlist": {
+            "title": opts.title,
+            "track": track_list,
+        }
+    });
+    doc.to_string()
+}
+
+pub fn render_asx(opts: &RenderOptions<'_>, tracks: &[TrackRef]) -> String {
+    let mut body = String::from("<asx version=\"3.0\">\r\n");
+    body.push_str(&format!("  <title>{}</title>\r\n", xml_escape(opts.title)));
+    for entry in entries(opts, tracks) {
+        body.push_str("  <entry>\r\n");
+        body.push_str(&format!(
+            "    <title>{}</title>\r\n",
+            xml_escape(&entry.title)
+        ));
+        if let Some(creator) = entry.creator {
+            body.push_str(&format!("    <author>{}</author>\r\n", xml_escape(creator)));
+        }
+        if let Some(album) = entry.album {
+            body.push_str(&format!(
+                "    <param name=\"Album\" value=\"{}\" />\r\n",
+                xml_escape(album)
+            ));
+        }
+        if let Some(duration) = entry.duration {
+            let secs = duration.round() as u64;
+            body.push_str(&format!(
+                "    <duration value=\"{:02}:{:02}:{:02}\" />\r\n",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60
+            ));
+        }
+        body.push_str(&format!(
+            "    <ref href=\"{}\" />\r\n",
+            xml_escape(&entry.url)
+        ));
+        body.push_str("  </entry>\r\n");
+    }
+    body.push_str("</asx>\r\n");
+    body
+}
+
+/// A podcast feed: RSS 2.0 with iTunes tags, one episode per track in
+/// playlist order. Enclosures need absolute URLs, so `relative_to` is
+/// ignored.
+pub fn render_rss(opts: &RenderOptions<'_>, tracks: &[TrackRef]) -> String {
+    let opts = RenderOptions {
+        relative_to: None,
+        ..*opts
+    };
+    let items: Vec<Entry<'_>> = entries(&opts, tracks).collect();
+    let title = xml_escape(opts.title);
+    let link = xml_escape(opts.base);
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\n  <channel>\n",
+    );
+    body.push_str(&format!(
+        "    <title>{title}</title>\n    <link>{link}</link>\n    <description>{title}</description>\n"
+    ));
+    // Serial feeds are played oldest first, following the episode numbers.
+    body.push_str("    <itunes:type>serial</itunes:type>\n");
+    body.push_str("    <itunes:explicit>false</itunes:explicit>\n");
+    let author = items.first().and_then(|e| e.creator);
+    if let Some(author) = author.filter(|a| items.iter().all(|e| e.creator == Some(*a))) {
+        body.push_str(&format!(
+            "    <itunes:author>{}</itunes:author>\n",
+            xml_escape(author)
+        ));
+    }
+    if let Some(image) = items.iter().find_map(|e| e.image.as_deref()) {
+        let image = xml_escape(image);
+        body.push_str(&format!(
+            "    <itunes:image href=\"{image}\" />\n    <image>\n      <url>{image}</url>\n      <title>{title}</title>\n      <link>{link}</link>\n    </image>\n"
+        ));
+    }
+    for (n, (entry, item)) in items.iter().zip(tracks).enumerate() {
+        let t = &item.track;
+        body.push_str("    <item>\n");
+        body.push_str(&format!(
+            "      <title>{}</title>\n",
+            xml_escape(&entry.title)
+        ));
+        if let Some(creator) = entry.creator {
+            body.push_str(&format!(
+                "      <itunes:author>{}</itunes:author>\n",
+                xml_escape(creator)
+            ));
+        }
+        // A track cut from a CUE rip is served in the rip's format.
+        let file = t.cue.as_ref().map_or(&t.path, |span| &span.source);
+        let mime = mime_guess::from_path(file).first_or_octet_stream();
+        body.push_str(&format!(
+            "      <enclosure url=\"{}\" length=\"{}\" type=\"{}\" />\n",
+            xml_escape(&entry.url),
+            t.size.unwrap_or(0),
+            xml_escape(mime.essence_str())
+        ));
+        body.push_str(&format!(
+            "      <guid isPermaLink=\"false\">{}</guid>\n",
+            xml_escape(&item.path)
+        ));
+        let published = t.modified.or_else(|| {
+            t.metadata
+                .year
+                .map(|y| time::unix_from_civil(i64::from(y), 1, 1))
+        });
+        if let Some(published) = published {
+            body.push_str(&format!(
+                "      <pubDate>{}</pubDate>\n",
+                time::rfc2822(published)
+            ));
+        }
+        if let Some(duration) = entry.duration {
+            body.push_str(&format!(
+                "      <itunes:duration>{}</itunes:duration>\n",
+                duration.round() as u64
+            ));
+        }
+        if let Some(image) = &entry.image {
+            body.push_str(&format!(
+                "      <itunes:image href=\"{}\" />\n",
+                xml_escape(image)
+            ));
+        }
+        body.push_str(&format!(
+            "      <itunes:episode>{}</itunes:episode>\n",
+            n + 1
+        ));
+        body.push_str("    </item>\n");
+    }
+    body.push_str("  </channel>\n</rss>\n");
+    body
+}
+
+/// Keeps a tag value on one line so it cannot start a new entry.
+fn single_line(s: &str) -> String {
+    s.replace(['\r', '\n'], " ")
+}
+
+pub fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::library::Track;
     use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn track_ref(path: &str, size: Option<u64>) -> TrackRef {
+        TrackRef {
+            path: path.to_string(),
+            track: Arc::new(Track {
+                path: PathBuf::from(path),
+                size,
+                modified: None,
+                metadata: crate::library::TrackMetadata::default(),
+                cue: None,
+                first_seen: None,
+                loudness: None,
+                album_loudness: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn parses_m3u_and_pls() {
+        let (title, entries) = parse_playlist(
+            PlaylistFormat::M3u,
+            b"\xef\xbb\xbf#EXTM3U\r\n#PLAYLIST:Road trip\r\n#EXTINF:10,A - B\r\na.mp3\r\n\r\nsub\\b.flac\r\n",
+        );
+        assert_eq!(title.as_deref(), Some("Road trip"));
+        assert_eq!(entries, vec!["a.mp3", "sub\\b.flac"]);
+
+        let (_, entries) = parse_playlist(
+            PlaylistFormat::Pls,
+            b"[playlist]\nFile2=two.mp3\nTitle2=Two\nFile1=one.mp3\nNumberOfEntries=2\n",
+        );
+        assert_eq!(entries, vec!["one.mp3", "two.mp3"]);
+    }
+
+    #[test]
+    fn normalizes_entries() {
+        let remaps = vec![PathRemap {
+            from: "C:\\Music".into(),
+            to: "music".into(),
+        }];
+        assert_eq!(
+            normalize_entry("..\\Other\\x.mp3", "music/Album", &remaps),
+            Some(EntryPath::Library("music/Other/x.mp3".into()))
+        );
+        assert_eq!(
+            normalize_entry("c:\\music\\A\\y.mp3", "", &remaps),
+            Some(EntryPath::Library("music/A/y.mp3".into()))
+        );
+        assert_eq!(
+            normalize_entry("file:///srv/My%20Music/z.mp3", "", &[]),
+            Some(EntryPath::Absolute("/srv/My Music/z.mp3".into()))
+        );
+        assert_eq!(normalize_entry("http://radio/stream", "", &[]), None);
+        assert_eq!(normalize_entry("../../x.mp3", "A", &[]), None);
+    }
+
+    fn tagged(path: &str) -> TrackRef {
+        let mut item = track_ref(path, None);
+        item.track = Arc::new(Track {
+            metadata: crate::library::TrackMetadata {
+                title: Some("Fish & Chips".into()),
+                artist: Some("The <Band>".into()),
+                album: Some("Lunch".into()),
+                duration: Some(125.4),
+                artwork_id: Some("abc".into()),
+                ..Default::default()
+            },
+            ..(*item.track).clone()
+        });
+        item
+    }
+
+    #[test]
+    fn export_formats_render_metadata() {
+        let opts = RenderOptions::new("http://h/", "Mix");
+        let tracks = vec![tagged("A/one.mp3"), track_ref("two.mp3", None)];
+        let pls = render(ExportFormat::Pls, &opts, &tracks);
+        assert!(pls.contains(
+            "File1=http://h/A/one.mp3\r\nTitle1=The <Band> - Fish & Chips\r\nLength1=125\r\n"
+        ));
+        assert!(pls.contains("Length2=-1"));
+        assert!(pls.contains("NumberOfEntries=2"));
+
+        let xspf = render(ExportFormat::Xspf, &opts, &tracks);
+        assert!(xspf.contains("<title>Fish &amp; Chips</title>"));
+        assert!(xspf.contains("<creator>The &lt;Band&gt;</creator>"));
+        assert!(xspf.contains("<duration>125400</duration>"));
+        assert!(xspf.contains("<image>http://h/api/artwork/abc</image>"));
+
+        let jspf: serde_json::Value =
+            serde_json::from_str(&render(ExportFormat::Jspf, &opts, &tracks)).unwrap();
+        assert_eq!(jspf["playlist"]["title"], "Mix");
+        assert_eq!(jspf["playlist"]["track"][0]["album"], "Lunch");
+        assert_eq!(
+            jspf["playlist"]["track"][1]["location"][0],
+            "http://h/two.mp3"
+        );
+
+        let asx = render(ExportFormat::Asx, &opts, &tracks);
+        assert!(asx.contains("<duration value=\"00:02:05\" />"));
+        assert!(asx.contains("<ref href=\"http://h/A/one.mp3\" />"));
+
+        let rss = render(ExportFormat::Rss, &opts.relative_to("A"), &tracks);
+        assert!(rss.contains("<title>Mix</title>"));
+        assert!(rss.contains("<itunes:image href=\"http://h/api/artwork/abc\" />"));
+        assert!(
+            rss.contains(
+                "<enclosure url=\"http://h/A/one.mp3\" length=\"0\" type=\"audio/mpeg\" />"
+            )
+        );
+        assert!(rss.contains("<itunes:duration>125</itunes:duration>"));
+        assert!(rss.contains("<itunes:episode>2</itunes:episode>"));
+        // The tracks have different artists, so the channel names none.
+        let channel = &rss[..rss.find("<item>").unwrap()];
+        assert!(!channel.contains("<itunes:author>"));
+    }
+
+    #[test]
+    fn splits_export_extension() {
+        assert_eq!(
+            ExportFormat::split_name("abc.xspf"),
+            ("abc", Some(ExportFormat::Xspf))
+        );
+        assert_eq!(ExportFormat::split_name("abc"), ("abc", None));
+        assert_eq!(ExportFormat::split_name("abcpls"), ("abcpls", None));
+    }
 
     #[test]
     fn m3u8_renders_crlf_and_urls() {
         let tracks = vec![
-            Arc::new(Track {
-                path: PathBuf::from("Album/song one.mp3"),
-                size: None,
-                metadata: crate::library::TrackMetadata::default(),
-            }),
-            Arc::new(Track {
-                path: PathBuf::from("Root.mp3"),
-                size: Some(123),
-                metadata: crate::library::TrackMetadata::default(),
-            }),
+            track_ref("Album/song one.mp3", None),
+            track_ref("Root.mp3", Some(123)),
         ];
-        let out = render_m3u8("http://h/", Path::new("/"), &tracks);
-        assert!(out.starts_with("#EXTM3U\r\n"));
+        let out = render_m3u8(&RenderOptions::new("http://h/", ""), &tracks);
+        assert!(out.starts_with("#EXTM3U\r\n#EXTINF"));
         assert!(out.contains("#EXTINF:0,Root.mp3\r\nhttp://h/Root.mp3\r\n"));
         assert!(out.contains("http://h/Album/song%20one.mp3"));
     }
+
+    #[test]
+    fn m3u8_extended_tags_and_relative_paths() {
+        let tracks = vec![tagged("A/B/song one.mp3"), track_ref("A/two.mp3", None)];
+        let opts = RenderOptions::new("http://h/", "My\nMix").relative_to("A");
+        let out = render_m3u8(&opts, &tracks);
+        assert!(out.starts_with("#EXTM3U\r\n#PLAYLIST:My Mix\r\n"));
+        assert!(out.contains(
+            "#EXTINF:125,The <Band> - Fish & Chips\r\n#EXTALB:Lunch\r\n#EXTART:The <Band>\r\n\
+             #EXTIMG:http://h/api/artwork/abc\r\nB/song one.mp3\r\n"
+        ));
+        assert!(out.contains("#EXTINF:0,two.mp3\r\ntwo.mp3\r\n"));
+
+        let xspf = render_xspf(&opts, &tracks);
+        assert!(xspf.contains("<location>B/song%20one.mp3</location>"));
+
+        let plain = render_m3u8(&opts.extended(false), &tracks);
+        assert_eq!(
+            plain,
+            "#EXTM3U\r\n#EXTINF:125,The <Band> - Fish & Chips\r\nB/song one.mp3\r\n\
+             #EXTINF:0,two.mp3\r\ntwo.mp3\r\n"
+        );
+    }
 }
@@ -0,0 +1,244 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::path_utils;
+use crate::time::unix_now;
+
+pub const PLAYLISTS_FILE: &str = "playlists.json";
+/// Longest name, in bytes, of a saved or smart playlist.
+pub const MAX_NAME_LEN: usize = 200;
+
+/// A named, user-managed playlist. Tracks are public relative paths (mount
+/// prefix included), so they keep working across rescans.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedPlaylist {
+    pub id: String,
+    pub name: String,
+    pub tracks: Vec<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError {
+    NotFound,
+    OutOfRange,
+    Io,
+}
+
+/// An entry kept in a [`JsonStore`].
+pub trait StoredItem: Clone + Serialize + DeserializeOwned {
+    fn id(&self) -> &str;
+    /// Records that the item was just changed.
+    fn touch(&mut self, now: u64);
+}
+
+/// A small list of items held in memory and saved as one JSON file in the
+/// data directory on every change.
+pub struct JsonStore<T> {
+    path: PathBuf,
+    items: Mutex<Vec<T>>,
+}
+
+pub type PlaylistStore = JsonStore<SavedPlaylist>;
+
+impl<T: StoredItem> JsonStore<T> {
+    pub fn open(dir: &Path, file_name: &str) -> Self {
+        let path = dir.join(file_name);
+        let items = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+                tracing::warn!(?err, path = %path.display(), "failed to parse store");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        JsonStore {
+            path,
+            items: Mutex::new(items),
+        }
+    }
+
+    pub fn list(&self) -> Vec<T> {
+        self.items.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, id: &str) -> Option<T> {
+        self.items
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|p| p.id() == id)
+            .cloned()
+    }
+
+    pub fn insert(&self, item: T) -> Result<T, StoreError> {
+        let mut items = self.items.lock().unwrap();
+        let mut next = items.clone();
+        next.push(item.clone());
+        self.persist(&next)?;
+        *items = next;
+        Ok(item)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), StoreError> {
+        let mut items = self.items.lock().unwrap();
+        let mut next = items.clone();
+        next.retain(|p| p.id() != id);
+        if next.len() == items.len() {
+            return Err(StoreError::NotFound);
+        }
+        self.persist(&next)?;
+        *items = next;
+        Ok(())
+    }
+
+    /// Applies `change` to one item and saves the result.
+    pub fn update(
+        &self,
+        id: &str,
+        change: impl FnOnce(&mut T) -> Result<(), StoreError>,
+    ) -> Result<T, StoreError> {
+        let mut items = self.items.lock().unwrap();
+        let mut next = items.clone();
+        let item = next
+            .iter_mut()
+            .find(|p| p.id() == id)
+            .ok_or(StoreError::NotFound)?;
+        change(item)?;
+        item.touch(unix_now());
+        let updated = item.clone();
+        self.persist(&next)?;
+        *items = next;
+        Ok(updated)
+    }
+
+    /// Saves `items`; callers only take them into memory once this worked,
+    /// so a failed write leaves the store as it was.
+    fn persist(&self, items: &[T]) -> Result<(), StoreError> {
+        let data = serde_json::to_vec_pretty(items).map_err(|_| StoreError::Io)?;
+        path_utils::write_atomic(&self.path, &data).map_err(|err| {
+            tracing::error!(?err, path = %self.path.display(), "failed to save store");
+            StoreError::Io
+        })
+    }
+}
+
+impl StoredItem for SavedPlaylist {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn touch(&mut self, now: u64) {
+        self.updated_at = now;
+    }
+}
+
+impl SavedPlaylist {
+    pub fn new(name: String, tracks: Vec<String>) -> Self {
+        let now = unix_now();
+        SavedPlaylist {
+            id: new_id(),
+            name,
+            tracks,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        position: Option<usize>,
+        tracks: Vec<String>,
+    ) -> Result<(), StoreError> {
+        let at = position.unwrap_or(self.tracks.len());
+        if at > self.tracks.len() {
+            return Err(StoreError::OutOfRange);
+        }
+        self.tracks.splice(at..at, tracks);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<(), StoreError> {
+        if index >= self.tracks.len() {
+            return Err(StoreError::OutOfRange);
+        }
+        self.tracks.remove(index);
+        Ok(())
+    }
+
+    pub fn move_track(&mut self, from: usize, to: usize) -> Result<(), StoreError> {
+        if from >= self.tracks.len() || to >= self.tracks.len() {
+            return Err(StoreError::OutOfRange);
+        }
+        let track = self.tracks.remove(from);
+        self.tracks.insert(to, track);
+        Ok(())
+    }
+}
+
+pub fn new_id() -> String {
+    use rand::Rng;
+    let bytes: [u8; 8] = rand::thread_rng().r#gen();
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crud_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = PlaylistStore::open(tmp.path(), PLAYLISTS_FILE);
+        let created = store
+            .insert(SavedPlaylist::new(
+                "Mix".into(),
+                vec!["a.mp3".into(), "b.mp3".into()],
+            ))
+            .unwrap();
+        store
+            .update(&created.id, |p| {
+                p.insert(Some(1), vec!["c.mp3".into()])?;
+                p.move_track(0, 2)
+            })
+            .unwrap();
+
+        let reopened = PlaylistStore::open(tmp.path(), PLAYLISTS_FILE);
+        let loaded = reopened.get(&created.id).unwrap();
+        assert_eq!(loaded.tracks, vec!["c.mp3", "b.mp3", "a.mp3"]);
+        assert_eq!(
+            reopened.update(&created.id, |p| p.remove(5)).unwrap_err(),
+            StoreError::OutOfRange
+        );
+        reopened.delete(&created.id).unwrap();
+        assert!(reopened.list().is_empty());
+        assert_eq!(reopened.delete(&created.id), Err(StoreError::NotFound));
+    }
+
+    #[test]
+    fn failed_writes_change_nothing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = PlaylistStore::open(tmp.path(), PLAYLISTS_FILE);
+        let created = store
+            .insert(SavedPlaylist::new("Mix".into(), vec!["a.mp3".into()]))
+            .unwrap();
+        // A directory in the file's place makes every save fail.
+        fs::remove_file(tmp.path().join(PLAYLISTS_FILE)).unwrap();
+        fs::create_dir(tmp.path().join(PLAYLISTS_FILE)).unwrap();
+
+        let other = SavedPlaylist::new("Other".into(), Vec::new());
+        assert_eq!(store.insert(other).err(), Some(StoreError::Io));
+        let renamed = store.update(&created.id, |p| {
+            p.name = "Renamed".into();
+            Ok(())
+        });
+        assert_eq!(renamed.err(), Some(StoreError::Io));
+        assert_eq!(store.delete(&created.id), Err(StoreError::Io));
+        let list = store.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "Mix");
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::path_utils;
+use crate::playlist_store::StoreError;
+use crate::time::unix_now;
+
+pub const RATINGS_FILE: &str = "ratings.json";
+pub const MAX_RATING: u8 = 5;
+
+/// A favorite flag and/or a 1–5 star rating.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rating {
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    pub updated_at: u64,
+}
+
+/// A partial change; fields left out keep their value and a rating of 0
+/// clears the stars.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct RatingUpdate {
+    pub favorite: Option<bool>,
+    pub rating: Option<u8>,
+}
+
+impl RatingUpdate {
+    pub fn is_valid(&self) -> bool {
+        self.rating.is_none_or(|r| r <= MAX_RATING)
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct RatingsFile {
+    #[serde(default)]
+    tracks: BTreeMap<String, Rating>,
+    #[serde(default)]
+    albums: BTreeMap<String, Rating>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RatingTarget {
+    /// Keyed by public track path.
+    Track,
+    /// Keyed by [`TrackMetadata::album_key`](crate::library::TrackMetadata::album_key).
+    Album,
+}
+
+/// Favorites and ratings for tracks and albums, saved as one JSON file in
+/// the data directory on every change.
+pub struct RatingStore {
+    path: PathBuf,
+    data: Mutex<RatingsFile>,
+}
+
+impl RatingStore {
+    pub fn open(dir: &Path) -> Self {
+        let path = dir.join(RATINGS_FILE);
+        let data = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+                tracing::warn!(?err, path = %path.display(), "failed to parse ratings");
+                RatingsFile::default()
+            }),
+            Err(_) => RatingsFile::default(),
+        };
+        RatingStore {
+            path,
+            data: Mutex::new(data),
+        }
+    }
+
+    pub fn get(&self, target: RatingTarget, key: &str) -> Option<Rating> {
+        let data = self.data.lock().unwrap();
+        map(&data, target).get(key).copied()
+    }
+
+    pub fn list(&self, target: RatingTarget) -> Vec<(String, Rating)> {
+        let data = self.data.lock().unwrap();
+        map(&data, target)
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect()
+    }
+
+    /// Applies a change and returns the new value. Entries that end up with
+    /// neither a favorite nor stars are dropped.
+    pub fn set(
+        &self,
+        target: RatingTarget,
+        key: &str,
+        update: RatingUpdate,
+    ) -> Result<Rating, StoreError> {
+        if !update.is_valid() {
+            return Err(StoreError::OutOfRange);
+        }
+        let mut data = self.data.lock().unwrap();
+        // Changed on a copy, so a failed save leaves the ratings as they were.
+        let mut next = data.clone();
+        let entries = map_mut(&mut next, target);
+        let mut rating = entries.get(key).copied().unwrap_or_default();
+        if let Some(favorite) = update.favorite {
+            rating.favorite = favorite;
+        }
+        if let Some(stars) = update.rating {
+            rating.rating = (stars > 0).then_some(stars);
+        }
+        rating.updated_at = unix_now();
+        if rating.favorite || rating.rating.is_some() {
+            entries.insert(key.to_string(), rating);
+        } else {
+            entries.remove(key);
+        }
+        let bytes = serde_json::to_vec_pretty(&next).map_err(|_| StoreError::Io)?;
+        path_utils::write_atomic(&self.path, &bytes).map_err(|err| {
+            tracing::error!(?err, path = %self.path.display(), "failed to save ratings");
+            StoreError::Io
+        })?;
+        *data = next;
+        Ok(rating)
+    }
+
+    /// Favorite tracks, most recently marked first.
+    pub fn favorite_tracks(&self) -> Vec<(String, Rating)> {
+        let mut favorites: Vec<_> = self
+            .list(RatingTarget::Track)
+            .into_iter()
+            .filter(|(_, r)| r.favorite)
+            .collect();
+        favorites.sort_by(|(a_path, a), (b_path, b)| {
+            b.updated_at.cmp(&a.updated_at).then(a_path.cmp(b_path))
+        });
+        favorites
+    }
+}
+
+fn map(data: &RatingsFile, target: RatingTarget) -> &BTreeMap<String, Rating> {
+    match target {
+        RatingTarget::Track => &data.tracks,
+        RatingTarget::Album => &data.albums,
+    }
+}
+
+fn map_mut(data: &mut RatingsFile, target: RatingTarget) -> &mut BTreeMap<String, Rating> {
+    match target {
+        RatingTarget::Track => &mut data.tracks,
+        RatingTarget::Album => &mut data.albums,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_merges_and_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RatingStore::open(dir.path());
+        let fav = RatingUpdate {
+            favorite: Some(true),
+            rating: None,
+        };
+        store.set(RatingTarget::Track, "a.mp3", fav).unwrap();
+        let stars = RatingUpdate {
+            favorite: None,
+            rating: Some(4),
+        };
+        let rating = store.set(RatingTarget::Track, "a.mp3", stars).unwrap();
+        assert!(rating.favorite);
+        assert_eq!(rating.rating, Some(4));
+        store.set(RatingTarget::Album, "x/y", stars).unwrap();
+        let too_many = RatingUpdate {
+            favorite: None,
+            rating: Some(6),
+        };
+        assert_eq!(
+            store.set(RatingTarget::Album, "x/y", too_many),
+            Err(StoreError::OutOfRange)
+        );
+
+        let store = RatingStore::open(dir.path());
+        assert_eq!(
+            store.get(RatingTarget::Track, "a.mp3").unwrap().rating,
+            Some(4)
+        );
+        assert_eq!(store.favorite_tracks().len(), 1);
+
+        let clear = RatingUpdate {
+            favorite: Some(false),
+            rating: Some(0),
+        };
+        store.set(RatingTarget::Track, "a.mp3", clear).unwrap();
+        assert_eq!(store.get(RatingTarget::Track, "a.mp3"), None);
+        assert_eq!(
+            store.get(RatingTarget::Album, "x/y").unwrap().rating,
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn failed_saves_change_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RatingStore::open(dir.path());
+        // A directory in the file's place makes every save fail.
+        fs::create_dir(dir.path().join(RATINGS_FILE)).unwrap();
+        let fav = RatingUpdate {
+            favorite: Some(true),
+            rating: None,
+        };
+        assert_eq!(
+            store.set(RatingTarget::Track, "a.mp3", fav),
+            Err(StoreError::Io)
+        );
+        assert_eq!(store.get(RatingTarget::Track, "a.mp3"), None);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::path_utils;
+use crate::playlist_store::StoreError;
+use crate::time::unix_now;
+
+pub const RESUME_FILE: &str = "resume.json";
+/// Positions kept per client; the least recently saved go first.
+const MAX_ENTRIES_PER_CLIENT: usize = 1000;
+
+/// Where a client stopped listening to a track.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResumePoint {
+    /// Seconds from the start of the track.
+    pub position: f64,
+    pub updated_at: u64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct ResumeFile {
+    /// Client name, then public track path.
+    #[serde(default)]
+    clients: BTreeMap<String, BTreeMap<String, ResumePoint>>,
+}
+
+/// Playback positions per client, saved as one JSON file in the data
+/// directory on every change. Clients name themselves, so a user or a
+/// device can keep its own positions.
+pub struct ResumeStore {
+    path: PathBuf,
+    data: Mutex<ResumeFile>,
+}
+
+impl ResumeStore {
+    pub fn open(dir: &Path) -> Self {
+        let path = dir.join(RESUME_FILE);
+        let data = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+                tracing::warn!(?err, path = %path.display(), "failed to parse resume positions");
+                ResumeFile::default()
+            }),
+            Err(_) => ResumeFile::default(),
+        };
+        ResumeStore {
+            path,
+            data: Mutex::new(data),
+        }
+    }
+
+    pub fn get(&self, client: &str, path: &str) -> Option<ResumePoint> {
+        let data = self.data.lock().unwrap();
+        data.clients.get(client)?.get(path).copied()
+    }
+
+    /// A client's positions, most recently saved first.
+    pub fn list(&self, client: &str) -> Vec<(String, ResumePoint)> {
+        let data = self.data.lock().unwrap();
+        let mut entries: Vec<_> = data
+            .clients
+            .get(client)
+            .map(|points| points.iter().map(|(k, v)| (k.clone(), *v)).collect())
+            .unwrap_or_default();
+        entries.sort_by(|(a_path, a), (b_path, b)| {
+            b.updated_at.cmp(&a.updated_at).then(a_path.cmp(b_path))
+        });
+        entries
+    }
+
+    pub fn set(&self, client: &str, path: &str, position: f64) -> Result<ResumePoint, StoreError> {
+        if !position.is_finite() || position < 0.0 {
+            return Err(StoreError::OutOfRange);
+        }
+        let point = ResumePoint {
+            position,
+            updated_at: unix_now(),
+        };
+        let mut data = self.data.lock().unwrap();
+        // Changed on a copy, so a failed save leaves the positions as they were.
+        let mut next = data.clone();
+        let points = next.clients.entry(client.to_string()).or_default();
+        points.insert(path.to_string(), point);
+        if points.len() > MAX_ENTRIES_PER_CLIENT
+            && let Some(oldest) = points
+                .iter()
+                .min_by_key(|(_, p)| p.updated_at)
+                .map(|(k, _)| k.clone())
+        {
+            points.remove(&oldest);
+        }
+        self.save(&next)?;
+        *data = next;
+        Ok(point)
+    }
+
+    /// Forgets a position; returns whether there was one.
+    pub fn clear(&self, client: &str, path: &str) -> Result<bool, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        let mut next = data.clone();
+        let Some(points) = next.clients.get_mut(client) else {
+            return Ok(false);
+        };
+        if points.remove(path).is_none() {
+            return Ok(false);
+        }
+        if points.is_empty() {
+            next.clients.remove(client);
+        }
+        self.save(&next)?;
+        *data = next;
+        Ok(true)
+    }
+
+    fn save(&self, data: &ResumeFile) -> Result<(), StoreError> {
+        let bytes = serde_json::to_vec_pretty(data).map_err(|_| StoreError::Io)?;
+        path_utils::write_atomic(&self.path, &bytes).map_err(|err| {
+            tracing::error!(?err, path = %self.path.display(), "failed to save resume positions");
+            StoreError::Io
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_are_kept_per_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ResumeStore::open(dir.path());
+        store.set("phone", "books/a.m4b", 120.5).unwrap();
+        store.set("laptop", "books/a.m4b", 30.0).unwrap();
+        store.set("phone", "mixes/b.mp3", 10.0).unwrap();
+        assert_eq!(
+            store.set("phone", "x.mp3", f64::NAN),
+            Err(StoreError::OutOfRange)
+        );
+
+        let store = ResumeStore::open(dir.path());
+        assert_eq!(store.get("phone", "books/a.m4b").unwrap().position, 120.5);
+        assert_eq!(store.get("laptop", "books/a.m4b").unwrap().position, 30.0);
+        assert_eq!(store.list("phone").len(), 2);
+        assert_eq!(store.clear("phone", "books/a.m4b"), Ok(true));
+        assert_eq!(store.clear("phone", "books/a.m4b"), Ok(false));
+        assert_eq!(store.get("phone", "books/a.m4b"), None);
+        assert!(store.get("laptop", "books/a.m4b").is_some());
+    }
+
+    #[test]
+    fn failed_saves_change_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ResumeStore::open(dir.path());
+        store.set("phone", "a.mp3", 12.0).unwrap();
+        // A directory in the file's place makes every save fail.
+        fs::remove_file(dir.path().join(RESUME_FILE)).unwrap();
+        fs::create_dir(dir.path().join(RESUME_FILE)).unwrap();
+        assert_eq!(store.set("phone", "a.mp3", 40.0), Err(StoreError::Io));
+        assert_eq!(store.clear("phone", "a.mp3"), Err(StoreError::Io));
+        assert_eq!(store.get("phone", "a.mp3").unwrap().position, 12.0);
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lofty::error::{ErrorKind, LoftyError};
+use serde::{Deserialize, Serialize};
+
+use crate::library;
+use crate::time::unix_now;
+
+const REPORT_FILE: &str = "scan-report.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanIssueKind {
+    Unreadable,
+    UnsupportedFormat,
+    TagParseError,
+    ZeroDuration,
+    /// A CUE sheet names an audio file that isn't there.
+    CueMissingSource,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanIssue {
+    pub path: String,
+    pub kind: ScanIssueKind,
+    pub message: String,
+}
+
+impl ScanIssue {
+    pub fn new(path: &Path, kind: ScanIssueKind, message: impl Into<String>) -> Self {
+        ScanIssue {
+            path: path.to_string_lossy().replace('\\', "/"),
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn from_lofty(path: &Path, err: &LoftyError) -> Self {
+        let kind = match err.kind() {
+            ErrorKind::Io(_) => ScanIssueKind::Unreadable,
+            ErrorKind::UnknownFormat | ErrorKind::UnsupportedTag => {
+                ScanIssueKind::UnsupportedFormat
+            }
+            _ => ScanIssueKind::TagParseError,
+        };
+        ScanIssue::new(path, kind, err.to_string())
+    }
+}
+
+/// Outcome of the last scan of a root, persisted next to the library cache.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub elapsed_ms: u64,
+    pub files_seen: u64,
+    pub files_parsed: u64,
+    pub tracks: usize,
+    pub folders: usize,
+    pub artworks: usize,
+    pub issues: Vec<ScanIssue>,
+    /// Set when the scan itself failed, e.g. it panicked or the cache could
+    /// not be written.
+    pub error: Option<String>,
+    /// Folder the scan was limited to, relative to the root.
+    pub scope: Option<String>,
+}
+
+impl ScanReport {
+    pub fn failed(started_at: u64, elapsed_ms: u64, error: impl Into<String>) -> Self {
+        ScanReport {
+            started_at,
+            finished_at: unix_now(),
+            elapsed_ms,
+            error: Some(error.into()),
+            ..ScanReport::default()
+        }
+    }
+
+    pub fn load(root: &Path) -> anyhow::Result<Self> {
+        let data = fs::read(report_path(root))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn save(&self, root: &Path) -> anyhow::Result<()> {
+        let path = report_path(root);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+fn report_path(root: &Path) -> PathBuf {
+    library::cache_dir(root).join(REPORT_FILE)
+}
@@ -0,0 +1,395 @@
+//! Store-only ZIP downloads of folders and playlists.
+//!
+//! Entries are written without compression and with a trailing data
+//! descriptor, so files are streamed straight from disk while their CRC is
+//! computed. Sizes are known before the first byte is sent, which lets the
+//! response carry an exact `Content-Length`.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+
+use axum::{
+    body::Body,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::state::AppState;
+use crate::library::TrackRef;
+use crate::playlist::{self, RenderOptions};
+use crate::time;
+
+const LOCAL_HEADER_LEN: u64 = 30;
+const DESCRIPTOR_LEN: u64 = 16;
+const CENTRAL_HEADER_LEN: u64 = 46;
+const END_RECORD_LEN: u64 = 22;
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Bit 3: sizes and CRC follow the data; bit 11: names are UTF-8.
+const FLAGS: u16 = (1 << 3) | (1 << 11);
+
+pub enum Source {
+    File(PathBuf),
+    Data(Bytes),
+}
+
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<u64>,
+    pub source: Source,
+}
+
+impl Entry {
+    pub fn data(name: String, data: impl Into<Bytes>) -> Self {
+        let data = data.into();
+        Entry {
+            name,
+            size: data.len() as u64,
+            modified: None,
+            source: Source::Data(data),
+        }
+    }
+}
+
+/// Total archive size, or `None` when it would need ZIP64.
+pub fn archive_len(entries: &[Entry]) -> Option<u64> {
+    if entries.len() > u16::MAX as usize {
+        return None;
+    }
+    let mut offset = 0u64;
+    let mut central = 0u64;
+    for entry in entries {
+        if entry.size >= u32::MAX as u64 || offset >= u32::MAX as u64 {
+            return None;
+        }
+        let name_len = entry.name.len() as u64;
+        offset += LOCAL_HEADER_LEN + name_len + entry.size + DESCRIPTOR_LEN;
+        central += CENTRAL_HEADER_LEN + name_len;
+    }
+    if offset + central >= u32::MAX as u64 {
+        return None;
+    }
+    Some(offset + central + END_RECORD_LEN)
+}
+
+/// Streams the archive from a background task.
+pub fn into_body(entries: Vec<Entry>) -> Body {
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        if let Err(err) = write_archive(entries, &tx).await {
+            tracing::warn!(?err, "zip download aborted");
+            let _ = tx.send(Err(err)).await;
+        }
+    });
+    Body::from_stream(ReceiverStream::new(rx))
+}
+
+async fn send(tx: &mpsc::Sender<io::Result<Bytes>>, data: Bytes) -> io::Result<()> {
+    tx.send(Ok(data))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+}
+
+struct CentralRecord {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+    time: u16,
+    date: u16,
+}
+
+async fn write_archive(
+    entries: Vec<Entry>,
+    tx: &mpsc::Sender<io::Result<Bytes>>,
+) -> io::Result<()> {
+    let mut offset = 0u64;
+    let mut records = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let (time, date) = dos_time(entry.modified.unwrap_or(0));
+        let size = entry.size as u32;
+        let mut header = BytesMut::with_capacity(LOCAL_HEADER_LEN as usize + entry.name.len());
+        header.put_u32_le(0x0403_4b50);
+        header.put_u16_le(20);
+        header.put_u16_le(FLAGS);
+        header.put_u16_le(0);
+        header.put_u16_le(time);
+        header.put_u16_le(date);
+        header.put_u32_le(0);
+        header.put_u32_le(size);
+        header.put_u32_le(size);
+        header.put_u16_le(entry.name.len() as u16);
+        header.put_u16_le(0);
+        header.put_slice(entry.name.as_bytes());
+        send(tx, header.freeze()).await?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        match entry.source {
+            Source::Data(data) => {
+                hasher.update(&data);
+                send(tx, data).await?;
+            }
+            Source::File(path) => {
+                let file = tokio::fs::File::open(&path).await?;
+                let mut reader = file.take(entry.size);
+                let mut remaining = entry.size;
+                while remaining > 0 {
+                    let mut buf = BytesMut::with_capacity(CHUNK_SIZE);
+                    let n = reader.read_buf(&mut buf).await?;
+                    if n == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            format!("{} shrank while downloading", path.display()),
+                        ));
+                    }
+                    hasher.update(&buf);
+                    remaining -= n as u64;
+                    send(tx, buf.freeze()).await?;
+                }
+            }
+        }
+        let crc = hasher.finalize();
+
+        let mut descriptor = BytesMut::with_capacity(DESCRIPTOR_LEN as usize);
+        descriptor.put_u32_le(0x0807_4b50);
+        descriptor.put_u32_le(crc);
+        descriptor.put_u32_le(size);
+        descriptor.put_u32_le(size);
+        send(tx, descriptor.freeze()).await?;
+
+        records.push(CentralRecord {
+            crc,
+            size,
+            offset: offset as u32,
+            time,
+            date,
+            name: entry.name.clone(),
+        });
+        offset += LOCAL_HEADER_LEN + entry.name.len() as u64 + entry.size + DESCRIPTOR_LEN;
+    }
+
+    let mut central = BytesMut::new();
+    for record in &records {
+        central.put_u32_le(0x0201_4b50);
+        central.put_u16_le(20);
+        central.put_u16_le(20);
+        central.put_u16_le(FLAGS);
+        central.put_u16_le(0);
+        central.put_u16_le(record.time);
+        central.put_u16_le(record.date);
+        central.put_u32_le(record.crc);
+        central.put_u32_le(record.size);
+        central.put_u32_le(record.size);
+        central.put_u16_le(record.name.len() as u16);
+        central.put_u16_le(0);
+        central.put_u16_le(0);
+        central.put_u16_le(0);
+        central.put_u16_le(0);
+        central.put_u32_le(0);
+        central.put_u32_le(record.offset);
+        central.put_slice(record.name.as_bytes());
+    }
+    let central_len = central.len() as u32;
+    central.put_u32_le(0x0605_4b50);
+    central.put_u16_le(0);
+    central.put_u16_le(0);
+    central.put_u16_le(records.len() as u16);
+    central.put_u16_le(records.len() as u16);
+    central.put_u32_le(central_len);
+    central.put_u32_le(offset as u32);
+    central.put_u16_le(0);
+    send(tx, central.freeze()).await
+}
+
+/// MS-DOS time and date fields for a Unix timestamp (UTC). Anything before
+/// 1980 is clamped to the earliest representable date.
+fn dos_time(secs: u64) -> (u16, u16) {
+    let (year, month, day) = time::civil_date(secs);
+    let rem = secs % 86_400;
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = year.min(2107);
+    let time = ((rem / 3600) << 11) | (((rem / 60) % 60) << 5) | ((rem % 60) / 2);
+    let date = ((year - 1980) << 9) | (i64::from(month) << 5) | i64::from(day);
+    (time as u16, date as u16)
+}
+
+/// Makes a name usable as a single path component inside the archive.
+fn component(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').to_string();
+    if cleaned.is_empty() {
+        "musrv".to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Builds a ZIP download holding `tracks` under a `name/` directory, laid
+/// out relative to `relative_to`, with one cover per folder and a generated
+/// M3U8 next to them.
+pub async fn tracks_archive(
+    state: &AppState,
+    name: &str,
+    relative_to: &str,
+    tracks: Vec<TrackRef>,
+) -> Result<Response, (StatusCode, String)> {
+    let dir = component(name);
+    let prefix = if relative_to.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", relative_to.trim_end_matches('/'))
+    };
+    let mut entries = Vec::new();
+    let mut included = Vec::new();
+    let mut seen = HashSet::new();
+    let mut covers = BTreeMap::new();
+    let mut stored = HashSet::new();
+    for item in tracks {
+        if !seen.insert(item.path.clone()) {
+            continue;
+        }
+        // A track cut from a rip by a CUE sheet ships as the rip and the
+        // sheet, each stored once however many of its tracks are picked.
+        let files = match &item.track.cue {
+            Some(span) => vec![
+                item.sibling_path(&span.source),
+                item.sibling_path(&span.sheet),
+            ],
+            None => vec![item.path.clone()],
+        };
+        let mut found = false;
+        for path in files {
+            if !stored.insert(path.clone()) {
+                found = true;
+                continue;
+            }
+            let Some((mount, inner)) = state.resolve(&path) else {
+                continue;
+            };
+            let abs = mount.root.join(&inner);
+            let Ok(meta) = tokio::fs::metadata(&abs).await else {
+                continue;
+            };
+            if !meta.is_file() {
+                continue;
+            }
+            let rel = path.strip_prefix(&prefix).unwrap_or(&path);
+            entries.push(Entry {
+                name: format!("{dir}/{rel}"),
+                size: meta.len(),
+                modified: item.track.modified,
+                source: Source::File(abs),
+            });
+            found = true;
+        }
+        if !found {
+            continue;
+        }
+        let rel = item.path.strip_prefix(&prefix).unwrap_or(&item.path);
+        let folder = rel.rsplit_once('/').map(|(d, _)| d).unwrap_or("");
+        if let Some(id) = &item.track.metadata.artwork_id {
+            covers
+                .entry(folder.to_string())
+                .or_insert_with(|| id.clone());
+        }
+        included.push(item);
+    }
+    for (folder, id) in covers {
+        let Some(art) = state.mounts.iter().find_map(|m| m.lib.load().artwork(&id)) else {
+            continue;
+        };
+        let ext = match art.mime.as_str() {
+            "image/jpeg" => "jpg",
+            other => mime_guess::get_mime_extensions_str(other)
+                .and_then(|exts| exts.first().copied())
+                .unwrap_or("jpg"),
+        };
+        let name = if folder.is_empty() {
+            format!("{dir}/cover.{ext}")
+        } else {
+            format!("{dir}/{folder}/cover.{ext}")
+        };
+        entries.push(Entry::data(name, Bytes::copy_from_slice(&art.data)));
+    }
+    let opts = RenderOptions::new(&state.base, name).relative_to(relative_to);
+    let m3u8 = playlist::render_m3u8(&opts, &included);
+    entries.push(Entry::data(format!("{dir}/{dir}.m3u8"), m3u8));
+
+    let Some(len) = archive_len(&entries) else {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "archive would exceed 4 GiB".to_string(),
+        ));
+    };
+    let disposition = format!(
+        "attachment; filename=\"{}.zip\"; filename*=UTF-8''{}.zip",
+        dir.replace(|c: char| !c.is_ascii() || c == '"', "_"),
+        playlist::encode_path(&dir)
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_LENGTH, len.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        into_body(entries),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn writes_a_readable_store_archive() {
+        let entries = vec![
+            Entry::data("a/one.txt".into(), "hello"),
+            Entry::data("a/two.txt".into(), "world!"),
+        ];
+        let len = archive_len(&entries).unwrap();
+        let body = axum::body::to_bytes(into_body(entries), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len() as u64, len);
+
+        let end = &body[body.len() - END_RECORD_LEN as usize..];
+        assert_eq!(&end[..4], &[0x50, 0x4b, 0x05, 0x06]);
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        let central = u32::from_le_bytes([end[16], end[17], end[18], end[19]]) as usize;
+        assert_eq!(&body[central..central + 4], &[0x50, 0x4b, 0x01, 0x02]);
+        let crc = u32::from_le_bytes(body[central + 16..central + 20].try_into().unwrap());
+        assert_eq!(crc, crc32fast::hash(b"hello"));
+        assert_eq!(&body[30..39], b"a/one.txt");
+        assert_eq!(&body[39..44], b"hello");
+    }
+
+    #[test]
+    fn converts_dos_time() {
+        // 2024-02-29 13:45:30 UTC
+        let (time, date) = dos_time(1_709_214_330);
+        assert_eq!(time, (13 << 11) | (45 << 5) | 15);
+        assert_eq!(date, ((2024 - 1980) << 9) | (2 << 5) | 29);
+        assert_eq!(dos_time(0), (0, (1 << 5) | 1));
+    }
+
+    #[test]
+    fn cleans_archive_names() {
+        assert_eq!(component("AC/DC: Live"), "AC_DC_ Live");
+        assert_eq!(component("..."), "musrv");
+    }
+}
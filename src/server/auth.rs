@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+
+use super::state::AppState;
+
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// Guards `/admin/*`. The token must arrive in a header, which a cross-site
+/// form or link cannot set, and requests the browser marks as cross-site are
+/// refused outright.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Err((StatusCode::FORBIDDEN, String::new()));
+    };
+    let headers = req.headers();
+    if is_cross_site(headers) {
+        return Err((StatusCode::FORBIDDEN, String::new()));
+    }
+    match provided_token(headers) {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            Ok(next.run(req).await)
+        }
+        _ => Err((StatusCode::UNAUTHORIZED, String::new())),
+    }
+}
+
+fn provided_token(headers: &HeaderMap) -> Option<&str> {
+    if let Some(value) = headers.get(ADMIN_TOKEN_HEADER) {
+        return value.to_str().ok();
+    }
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+fn is_cross_site(headers: &HeaderMap) -> bool {
+    headers
+        .get("sec-fetch-site")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v == "cross-site")
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Random token used when none is configured, printed at startup.
+pub fn generate_token() -> String {
+    use rand::Rng;
+    let bytes: [u8; 16] = rand::thread_rng().r#gen();
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+
+    #[test]
+    fn reads_bearer_or_header_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer abc".parse().unwrap());
+        assert_eq!(provided_token(&headers), Some("abc"));
+        headers.insert(ADMIN_TOKEN_HEADER, "xyz".parse().unwrap());
+        assert_eq!(provided_token(&headers), Some("xyz"));
+    }
+}
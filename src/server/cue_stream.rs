@@ -0,0 +1,135 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_stream::StreamExt;
+use tokio_util::io::ReaderStream;
+
+use crate::cue;
+use crate::library::CueSpan;
+
+/// Serves the part of a rip that a CUE sheet assigns to one track, as a
+/// standalone file. Single byte ranges are honoured so players can seek.
+pub async fn serve(
+    rip: PathBuf,
+    span: &CueSpan,
+    headers: &HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let (start, end) = (span.start, span.end);
+    let path = rip.clone();
+    let slice = tokio::task::spawn_blocking(move || cue::plan_slice(&path, start, end))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?
+        .map_err(|err| {
+            tracing::warn!(?err, path = %rip.display(), "failed to slice CUE track");
+            (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+        })?;
+    let total = slice.size();
+
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        None => None,
+        Some(value) => match parse_range(value, total) {
+            Ok(range) => range,
+            Err(()) => {
+                let mut res = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+                res.headers_mut().insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&format!("bytes */{total}")).unwrap(),
+                );
+                return Ok(res);
+            }
+        },
+    };
+    let (first, last) = range.unwrap_or((0, total.saturating_sub(1)));
+    let len = if total == 0 { 0 } else { last - first + 1 };
+
+    // The range may start inside the rewritten header, the audio, or both.
+    let head_len = slice.header.len() as u64;
+    let head = if first < head_len {
+        slice.header[first as usize..(first + len).min(head_len) as usize].to_vec()
+    } else {
+        Vec::new()
+    };
+    let audio_from = slice.start + first.saturating_sub(head_len);
+    let audio_len = len - head.len() as u64;
+    let mut file = tokio::fs::File::open(&rip)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, String::new()))?;
+    file.seek(SeekFrom::Start(audio_from))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    let stream =
+        tokio_stream::once(Ok(Bytes::from(head))).chain(ReaderStream::new(file.take(audio_len)));
+
+    let mut res = Body::from_stream(stream).into_response();
+    let out = res.headers_mut();
+    out.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(slice.content_type),
+    );
+    out.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    out.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if range.is_some() {
+        *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+        res.headers_mut().insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&format!("bytes {first}-{last}/{total}")).unwrap(),
+        );
+    }
+    Ok(res)
+}
+
+/// Parses a `Range` header into an inclusive byte range. Multiple ranges
+/// and other units are ignored, which means the whole body is sent.
+fn parse_range(value: &str, total: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let (from, to) = spec.trim().split_once('-').ok_or(())?;
+    let (first, last) = match (from.trim(), to.trim()) {
+        ("", suffix) => {
+            let n: u64 = suffix.parse().map_err(|_| ())?;
+            if n == 0 {
+                return Err(());
+            }
+            (total.saturating_sub(n), total.checked_sub(1).ok_or(())?)
+        }
+        (from, "") => (from.parse().map_err(|_| ())?, total.saturating_sub(1)),
+        (from, to) => {
+            let last: u64 = to.parse().map_err(|_| ())?;
+            (
+                from.parse().map_err(|_| ())?,
+                last.min(total.saturating_sub(1)),
+            )
+        }
+    };
+    if first > last || first >= total {
+        return Err(());
+    }
+    Ok(Some((first, last)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Ok(Some((990, 999))));
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=5-1", 1000), Err(()));
+    }
+}
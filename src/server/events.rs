@@ -0,0 +1,72 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Notifications pushed to `/api/events` subscribers.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    ScanStarted {
+        mount: String,
+        /// Set for a rescan limited to one folder of the mount.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        folder: Option<String>,
+    },
+    ScanProgress {
+        mount: String,
+        files_seen: u64,
+        files_parsed: u64,
+        current_folder: String,
+    },
+    ScanFinished {
+        mount: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        folder: Option<String>,
+        tracks: usize,
+        folders: usize,
+        artworks: usize,
+        elapsed_ms: u64,
+    },
+    LibraryChanged {
+        revision: u64,
+    },
+}
+
+impl ServerEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServerEvent::ScanStarted { .. } => "scan_started",
+            ServerEvent::ScanProgress { .. } => "scan_progress",
+            ServerEvent::ScanFinished { .. } => "scan_finished",
+            ServerEvent::LibraryChanged { .. } => "library_changed",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<ServerEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+        EventBus { tx }
+    }
+
+    /// Sends to current subscribers; events are dropped when nobody listens.
+    pub fn publish(&self, event: ServerEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,4 +1,10 @@
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+
+use crate::library::TrackRef;
 use crate::path_utils;
+use crate::playlist::{self, ExportFormat, RenderOptions};
+use crate::playlist_store::StoreError;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InputError {
@@ -37,10 +43,16 @@ pub fn validate_request_path(path: &str) -> Result<String, InputError> {
     let decoded = urlencoding::decode(path)
         .map_err(|_| InputError::Invalid)?
         .into_owned();
-    if decoded.starts_with('/') || decoded.contains('\0') {
+    validate_library_path(&decoded)
+}
+
+/// Checks a library path that is already decoded, such as one from a JSON
+/// body, where a `%` is part of the file name.
+pub fn validate_library_path(path: &str) -> Result<String, InputError> {
+    if path.is_empty() || path.starts_with('/') || path.contains('\0') {
         return Err(InputError::Invalid);
     }
-    for seg in decoded.split('/') {
+    for seg in path.split('/') {
         if seg.is_empty() {
             continue;
         }
@@ -51,7 +63,51 @@ pub fn validate_request_path(path: &str) -> Result<String, InputError> {
             return Err(InputError::Invalid);
         }
     }
-    Ok(decoded)
+    Ok(path.to_string())
+}
+
+pub fn store_error(err: StoreError) -> (StatusCode, String) {
+    match err {
+        StoreError::NotFound => (StatusCode::NOT_FOUND, String::new()),
+        StoreError::OutOfRange => (StatusCode::BAD_REQUEST, String::from("out of range")),
+        StoreError::Io => (StatusCode::INTERNAL_SERVER_ERROR, String::new()),
+    }
+}
+
+/// Address of a stored playlist's M3U8 export, such as
+/// `/api/playlists/<id>.m3u8`.
+pub fn m3u8_url(base: &str, collection: &str, id: &str) -> String {
+    format!("{}/api/{collection}/{id}.m3u8", base.trim_end_matches('/'))
+}
+
+/// Query options shared by the playlist export routes.
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    /// `0` leaves the extended M3U8 tags out.
+    extended: Option<String>,
+}
+
+impl ExportQuery {
+    pub fn extended(&self) -> bool {
+        self.extended.as_deref() != Some("0")
+    }
+}
+
+/// Renders tracks as a playlist download in the given format.
+pub fn playlist_response(
+    format: ExportFormat,
+    opts: &RenderOptions<'_>,
+    tracks: &[TrackRef],
+) -> Response {
+    let body = playlist::render(format, opts, tracks);
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type()),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        body,
+    )
+        .into_response()
 }
 
 #[cfg(test)]
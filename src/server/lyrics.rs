@@ -0,0 +1,60 @@
+use axum::{
+    Json, Router,
+    extract::{Path as AxPath, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+
+use super::{helpers, state::AppState, types::JsonLyricsResp};
+use crate::lyrics::{self, Lyrics, LyricsKind};
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/lyrics/*path", get(get_lyrics))
+}
+
+/// Timed lines as JSON when the track has synchronized lyrics, plain text
+/// otherwise. A sidecar file wins over tags of the same kind.
+async fn get_lyrics(
+    AxPath(path): AxPath<String>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, String::new());
+    let rel = helpers::validate_request_path(&path)
+        .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let item = state.find_track(&rel).ok_or_else(not_found)?;
+    let (mount, inner) = state.resolve(&rel).ok_or_else(not_found)?;
+    let abs = mount.root.join(inner);
+    let metadata = item.track.metadata.clone();
+    let found = tokio::task::spawn_blocking(move || {
+        let sidecar = metadata
+            .lyrics_file
+            .as_deref()
+            .and_then(|name| lyrics::read_sidecar(&abs.with_file_name(name)))
+            .map(|l| ("file", l));
+        if sidecar.as_ref().map(|(_, l)| l.kind()) == Some(LyricsKind::Synced) {
+            return sidecar;
+        }
+        let embedded = metadata
+            .lyrics
+            .and_then(|_| lyrics::read_embedded(&abs))
+            .map(|l| ("embedded", l));
+        match (sidecar, embedded) {
+            (_, Some(embedded)) if embedded.1.kind() == LyricsKind::Synced => Some(embedded),
+            (sidecar, embedded) => sidecar.or(embedded),
+        }
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    match found.ok_or_else(not_found)? {
+        (source, Lyrics::Synced(lines)) => Ok(Json(JsonLyricsResp {
+            path: item.path,
+            source,
+            lines,
+        })
+        .into_response()),
+        (_, Lyrics::Plain(text)) => {
+            Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response())
+        }
+    }
+}
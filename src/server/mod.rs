@@ -1,7 +1,22 @@
+pub mod archive;
+pub mod auth;
+pub mod cue_stream;
+pub mod events;
 pub mod helpers;
+pub mod lyrics;
+pub mod playlists;
+pub mod random;
+pub mod ratings;
+pub mod recent;
+pub mod resume;
 pub mod routes;
+pub mod scrobble;
+pub mod smart_playlists;
 pub mod state;
+pub mod tag_edit;
 pub mod types;
+pub mod upload;
+pub mod waveform;
 
 pub use routes::build_router;
 pub use state::AppState;
@@ -0,0 +1,383 @@
+use axum::{
+    Json, Router,
+    extract::{Path as AxPath, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+
+use super::{
+    archive, helpers,
+    state::AppState,
+    types::{JsonFolderTrack, JsonPlaylistResp, JsonPlaylistSummary},
+};
+use std::path::Path;
+
+use crate::library::TrackRef;
+use crate::playlist::{EntryPath, ExportFormat, RenderOptions, normalize_entry};
+use crate::playlist_store::{MAX_NAME_LEN, SavedPlaylist};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_playlists).post(create_playlist))
+        .route(
+            "/:id",
+            get(get_playlist)
+                .put(update_playlist)
+                .delete(delete_playlist),
+        )
+        .route("/:id/tracks", post(append_tracks))
+        .route("/:id/tracks/:index", axum::routing::delete(remove_track))
+        .route("/:id/move", post(move_track))
+}
+
+#[derive(serde::Deserialize)]
+struct CreatePlaylistReq {
+    name: String,
+    #[serde(default)]
+    tracks: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct UpdatePlaylistReq {
+    name: Option<String>,
+    tracks: Option<Vec<String>>,
+}
+
+#[derive(serde::Deserialize)]
+struct AppendTracksReq {
+    tracks: Vec<String>,
+    /// Insert before this index instead of appending.
+    position: Option<usize>,
+}
+
+#[derive(serde::Deserialize)]
+struct MoveTrackReq {
+    from: usize,
+    to: usize,
+}
+
+fn validate_name(name: &str) -> Result<String, (StatusCode, String)> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LEN || name.contains('\0') {
+        return Err((StatusCode::BAD_REQUEST, String::from("invalid name")));
+    }
+    Ok(name.to_string())
+}
+
+fn validate_tracks(tracks: Vec<String>) -> Result<Vec<String>, (StatusCode, String)> {
+    tracks
+        .iter()
+        .map(|t| helpers::validate_library_path(t))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| (StatusCode::BAD_REQUEST, String::from("invalid track path")))
+}
+
+/// Prefix of ids given to playlists imported from files in the library.
+const IMPORTED_PREFIX: &str = "file-";
+/// Id of the read-only playlist of favorite tracks.
+pub const FAVORITES_ID: &str = "favorites";
+
+/// A playlist file from the library, exposed read-only.
+pub struct ImportedPlaylist {
+    pub id: String,
+    pub name: String,
+    /// Library path of the playlist file.
+    pub source: String,
+    dir: String,
+    entries: Vec<String>,
+}
+
+pub fn imported_playlists(state: &AppState) -> Vec<ImportedPlaylist> {
+    let mut out = Vec::new();
+    for mount in state.mounts.iter() {
+        let lib = mount.lib.load();
+        for file in lib.playlists() {
+            let rel = file.path.to_string_lossy().replace('\\', "/");
+            let source = mount.public_path(&rel);
+            let dir = source
+                .rsplit_once('/')
+                .map(|(dir, _)| dir.to_string())
+                .unwrap_or_default();
+            let name = file.title.clone().unwrap_or_else(|| {
+                file.path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+            let hash = blake3::hash(source.as_bytes()).to_hex();
+            out.push(ImportedPlaylist {
+                id: format!("{IMPORTED_PREFIX}{}", &hash[..16]),
+                name,
+                source,
+                dir,
+                entries: file.entries.clone(),
+            });
+        }
+    }
+    out
+}
+
+fn find_imported(state: &AppState, id: &str) -> Option<ImportedPlaylist> {
+    imported_playlists(state).into_iter().find(|p| p.id == id)
+}
+
+/// Favorite tracks as a playlist, most recently marked first.
+fn favorites_playlist(state: &AppState) -> SavedPlaylist {
+    let favorites = state.ratings.favorite_tracks();
+    let updated_at = favorites
+        .iter()
+        .map(|(_, r)| r.updated_at)
+        .max()
+        .unwrap_or(0);
+    SavedPlaylist {
+        id: FAVORITES_ID.to_string(),
+        name: "Favorites".to_string(),
+        tracks: favorites.into_iter().map(|(path, _)| path).collect(),
+        created_at: 0,
+        updated_at,
+    }
+}
+
+/// A stored playlist, or the favorites playlist for its id.
+fn find_saved(state: &AppState, id: &str) -> Option<SavedPlaylist> {
+    if id == FAVORITES_ID {
+        Some(favorites_playlist(state))
+    } else {
+        state.playlists.get(id)
+    }
+}
+
+fn ensure_writable(id: &str) -> Result<(), (StatusCode, String)> {
+    if id.starts_with(IMPORTED_PREFIX) || id == FAVORITES_ID {
+        return Err((StatusCode::FORBIDDEN, String::from("playlist is read-only")));
+    }
+    Ok(())
+}
+
+/// Resolves a saved playlist's entries against the current library, keeping
+/// order and reporting entries that are not there.
+pub fn resolve_tracks(state: &AppState, playlist: &SavedPlaylist) -> (Vec<TrackRef>, Vec<String>) {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for path in &playlist.tracks {
+        match state.find_track(path) {
+            Some(track) => found.push(track),
+            None => missing.push(path.clone()),
+        }
+    }
+    (found, missing)
+}
+
+/// Resolves the entries of an imported playlist file. Unresolved entries
+/// are returned as written in the file.
+pub fn resolve_imported(
+    state: &AppState,
+    playlist: &ImportedPlaylist,
+) -> (Vec<TrackRef>, Vec<String>) {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for entry in &playlist.entries {
+        let public = match normalize_entry(entry, &playlist.dir, &state.playlist_remaps) {
+            Some(EntryPath::Library(path)) => Some(path),
+            Some(EntryPath::Absolute(path)) => state.public_path_for(Path::new(&path)),
+            None => None,
+        };
+        match public.and_then(|p| state.find_track(&p)) {
+            Some(track) => found.push(track),
+            None => missing.push(entry.clone()),
+        }
+    }
+    (found, missing)
+}
+
+fn tracks_json(state: &AppState, tracks: Vec<TrackRef>) -> Vec<JsonFolderTrack> {
+    tracks.into_iter().map(|t| state.track_json(t)).collect()
+}
+
+fn playlist_json(state: &AppState, playlist: SavedPlaylist) -> JsonPlaylistResp {
+    let (tracks, missing) = resolve_tracks(state, &playlist);
+    JsonPlaylistResp {
+        read_only: playlist.id == FAVORITES_ID,
+        m3u8: helpers::m3u8_url(&state.base, "playlists", &playlist.id),
+        id: playlist.id,
+        name: playlist.name,
+        created_at: Some(playlist.created_at),
+        updated_at: Some(playlist.updated_at),
+        source: None,
+        tracks: tracks_json(state, tracks),
+        missing,
+    }
+}
+
+fn imported_json(state: &AppState, playlist: ImportedPlaylist) -> JsonPlaylistResp {
+    let (tracks, missing) = resolve_imported(state, &playlist);
+    JsonPlaylistResp {
+        m3u8: helpers::m3u8_url(&state.base, "playlists", &playlist.id),
+        id: playlist.id,
+        name: playlist.name,
+        created_at: None,
+        updated_at: None,
+        read_only: true,
+        source: Some(playlist.source),
+        tracks: tracks_json(state, tracks),
+        missing,
+    }
+}
+
+async fn list_playlists(State(state): State<AppState>) -> Json<Vec<JsonPlaylistSummary>> {
+    let mut list: Vec<JsonPlaylistSummary> = std::iter::once(favorites_playlist(&state))
+        .chain(state.playlists.list())
+        .map(|p| {
+            let (tracks, missing) = resolve_tracks(&state, &p);
+            JsonPlaylistSummary {
+                read_only: p.id == FAVORITES_ID,
+                m3u8: helpers::m3u8_url(&state.base, "playlists", &p.id),
+                id: p.id,
+                name: p.name,
+                track_count: tracks.len(),
+                missing_count: missing.len(),
+                updated_at: Some(p.updated_at),
+                source: None,
+            }
+        })
+        .collect();
+    for p in imported_playlists(&state) {
+        let (tracks, missing) = resolve_imported(&state, &p);
+        list.push(JsonPlaylistSummary {
+            m3u8: helpers::m3u8_url(&state.base, "playlists", &p.id),
+            id: p.id,
+            name: p.name,
+            track_count: tracks.len(),
+            missing_count: missing.len(),
+            updated_at: None,
+            read_only: true,
+            source: Some(p.source),
+        });
+    }
+    Json(list)
+}
+
+async fn create_playlist(
+    State(state): State<AppState>,
+    Json(req): Json<CreatePlaylistReq>,
+) -> Result<(StatusCode, Json<JsonPlaylistResp>), (StatusCode, String)> {
+    let name = validate_name(&req.name)?;
+    let tracks = validate_tracks(req.tracks)?;
+    let playlist = state
+        .playlists
+        .insert(SavedPlaylist::new(name, tracks))
+        .map_err(helpers::store_error)?;
+    Ok((StatusCode::CREATED, Json(playlist_json(&state, playlist))))
+}
+
+/// Serves the playlist as JSON, as a playlist file when the id ends in an
+/// export extension such as `.m3u8` or `.xspf`, or as a ZIP download of its
+/// tracks for `.zip`.
+async fn get_playlist(
+    AxPath(id): AxPath<String>,
+    Query(export): Query<helpers::ExportQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, String::new());
+    let resolve = |id: &str| {
+        if id.starts_with(IMPORTED_PREFIX) {
+            let playlist = find_imported(&state, id).ok_or_else(not_found)?;
+            let tracks = resolve_imported(&state, &playlist).0;
+            Ok((playlist.name, tracks))
+        } else {
+            let playlist = find_saved(&state, id).ok_or_else(not_found)?;
+            let tracks = resolve_tracks(&state, &playlist).0;
+            Ok((playlist.name, tracks))
+        }
+    };
+    if let Some(id) = id.strip_suffix(".zip") {
+        let (name, tracks) = resolve(id)?;
+        return archive::tracks_archive(&state, &name, "", tracks).await;
+    }
+    if let (id, Some(format)) = ExportFormat::split_name(&id) {
+        let (name, tracks) = resolve(id)?;
+        return Ok(helpers::playlist_response(
+            format,
+            &RenderOptions::new(&state.base, &name).extended(export.extended()),
+            &tracks,
+        ));
+    }
+    let body = if id.starts_with(IMPORTED_PREFIX) {
+        imported_json(&state, find_imported(&state, &id).ok_or_else(not_found)?)
+    } else {
+        playlist_json(&state, find_saved(&state, &id).ok_or_else(not_found)?)
+    };
+    Ok(Json(body).into_response())
+}
+
+async fn update_playlist(
+    AxPath(id): AxPath<String>,
+    State(state): State<AppState>,
+    Json(req): Json<UpdatePlaylistReq>,
+) -> Result<Json<JsonPlaylistResp>, (StatusCode, String)> {
+    ensure_writable(&id)?;
+    let name = req.name.as_deref().map(validate_name).transpose()?;
+    let tracks = req.tracks.map(validate_tracks).transpose()?;
+    let playlist = state
+        .playlists
+        .update(&id, |p| {
+            if let Some(name) = name {
+                p.name = name;
+            }
+            if let Some(tracks) = tracks {
+                p.tracks = tracks;
+            }
+            Ok(())
+        })
+        .map_err(helpers::store_error)?;
+    Ok(Json(playlist_json(&state, playlist)))
+}
+
+async fn delete_playlist(
+    AxPath(id): AxPath<String>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    ensure_writable(&id)?;
+    state.playlists.delete(&id).map_err(helpers::store_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn append_tracks(
+    AxPath(id): AxPath<String>,
+    State(state): State<AppState>,
+    Json(req): Json<AppendTracksReq>,
+) -> Result<Json<JsonPlaylistResp>, (StatusCode, String)> {
+    ensure_writable(&id)?;
+    let tracks = validate_tracks(req.tracks)?;
+    let playlist = state
+        .playlists
+        .update(&id, |p| p.insert(req.position, tracks))
+        .map_err(helpers::store_error)?;
+    Ok(Json(playlist_json(&state, playlist)))
+}
+
+async fn remove_track(
+    AxPath((id, index)): AxPath<(String, usize)>,
+    State(state): State<AppState>,
+) -> Result<Json<JsonPlaylistResp>, (StatusCode, String)> {
+    ensure_writable(&id)?;
+    let playlist = state
+        .playlists
+        .update(&id, |p| p.remove(index))
+        .map_err(helpers::store_error)?;
+    Ok(Json(playlist_json(&state, playlist)))
+}
+
+async fn move_track(
+    AxPath(id): AxPath<String>,
+    State(state): State<AppState>,
+    Json(req): Json<MoveTrackReq>,
+) -> Result<Json<JsonPlaylistResp>, (StatusCode, String)> {
+    ensure_writable(&id)?;
+    let playlist = state
+        .playlists
+        .update(&id, |p| p.move_track(req.from, req.to))
+        .map_err(helpers::store_error)?;
+    Ok(Json(playlist_json(&state, playlist)))
+}
@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    response::Response,
+    routing::get,
+};
+use rand::Rng;
+
+use super::{
+    helpers,
+    state::AppState,
+    types::{JsonFolderTrack, JsonRandomResp},
+};
+use crate::library::TrackRef;
+use crate::playlist::{ExportFormat, RenderOptions};
+use crate::ratings::RatingTarget;
+use crate::time::unix_now;
+
+const DEFAULT_TRACKS: usize = 25;
+const DEFAULT_ALBUMS: usize = 5;
+const MAX_COUNT: usize = 500;
+/// Weight of an unrated track or album, as if it had three stars.
+const UNRATED_WEIGHT: f64 = 3.0;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/random", get(random))
+        .route("/api/random.m3u8", get(random_m3u8))
+}
+
+#[derive(Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    #[default]
+    Tracks,
+    /// Whole albums in random order, each in its own track order.
+    Albums,
+}
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Weight {
+    #[default]
+    None,
+    /// Stars, with favorites counting as five.
+    Rating,
+    /// All-time plays, so favorites by habit come up more often.
+    Plays,
+}
+
+#[derive(serde::Deserialize)]
+struct RandomQuery {
+    /// Tracks, or albums in album mode.
+    count: Option<usize>,
+    path: Option<String>,
+    #[serde(default)]
+    mode: Mode,
+    #[serde(default)]
+    weight: Weight,
+    /// `1` keeps the same artist from playing twice in a row.
+    spread_artists: Option<String>,
+    /// Leaves out anything played in the last this many days.
+    exclude_days: Option<u64>,
+}
+
+impl RandomQuery {
+    fn count(&self) -> usize {
+        let default = match self.mode {
+            Mode::Tracks => DEFAULT_TRACKS,
+            Mode::Albums => DEFAULT_ALBUMS,
+        };
+        self.count.unwrap_or(default).clamp(1, MAX_COUNT)
+    }
+}
+
+/// Orders items randomly, favouring heavier ones: each gets the key
+/// `u^(1/w)` and the largest keys come first (Efraimidis–Spirakis).
+fn weighted_shuffle<T>(items: Vec<(T, f64)>, rng: &mut impl Rng) -> Vec<T> {
+    let mut keyed: Vec<(f64, T)> = items
+        .into_iter()
+        .map(|(item, weight)| {
+            let u: f64 = rng.r#gen();
+            (u.powf(1.0 / weight.max(f64::MIN_POSITIVE)), item)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    keyed.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Takes up to `count` items in order, holding back any whose artist
+/// matches the one just taken until something else has played. Items
+/// without an artist never clash.
+fn spread<T>(order: Vec<T>, count: usize, artist: impl Fn(&T) -> Option<String>) -> Vec<T> {
+    let mut out: Vec<T> = Vec::with_capacity(count.min(order.len()));
+    let mut held: Vec<T> = Vec::new();
+    let mut rest = order.into_iter();
+    while out.len() < count {
+        let last = out.last().and_then(&artist);
+        let clashes = |item: &T| last.is_some() && artist(item) == last;
+        if let Some(i) = held.iter().position(|item| !clashes(item)) {
+            out.push(held.remove(i));
+            continue;
+        }
+        match rest.next() {
+            Some(item) if clashes(&item) => held.push(item),
+            Some(item) => out.push(item),
+            // Only one artist is left, so repeats can't be avoided.
+            None if !held.is_empty() => out.push(held.remove(0)),
+            None => break,
+        }
+    }
+    out
+}
+
+fn artist_of(item: &TrackRef) -> Option<String> {
+    let meta = &item.track.metadata;
+    meta.artist
+        .as_deref()
+        .or(meta.album_artist.as_deref())
+        .map(|a| a.trim().to_lowercase())
+        .filter(|a| !a.is_empty())
+}
+
+fn album_artist_of(tracks: &[TrackRef]) -> Option<String> {
+    let meta = &tracks.first()?.track.metadata;
+    meta.album_artist
+        .as_deref()
+        .or(meta.artist.as_deref())
+        .map(|a| a.trim().to_lowercase())
+        .filter(|a| !a.is_empty())
+}
+
+fn rating_weight(state: &AppState, target: RatingTarget, key: &str) -> f64 {
+    match state.ratings.get(target, key) {
+        Some(r) if r.favorite => 5.0,
+        Some(r) => r.rating.map_or(UNRATED_WEIGHT, f64::from),
+        None => UNRATED_WEIGHT,
+    }
+}
+
+fn pick(state: &AppState, q: &RandomQuery) -> Result<Vec<TrackRef>, (StatusCode, String)> {
+    let rel = match q.path.as_deref() {
+        Some(path) if !path.is_empty() => helpers::validate_request_path(path)
+            .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?,
+        _ => String::new(),
+    };
+    let recent: HashSet<String> = match q.exclude_days {
+        Some(days) => {
+            let since = unix_now().saturating_sub(days.saturating_mul(86_400));
+            state
+                .history
+                .play_counts(since, u64::MAX)
+                .into_keys()
+                .collect()
+        }
+        None => HashSet::new(),
+    };
+    let plays = match q.weight {
+        Weight::Plays => state.history.play_counts(0, u64::MAX),
+        _ => HashMap::new(),
+    };
+    let track_weight = |item: &TrackRef| match q.weight {
+        Weight::None => 1.0,
+        Weight::Rating => rating_weight(state, RatingTarget::Track, &item.path),
+        Weight::Plays => 1.0 + plays.get(&item.path).map_or(0, |c| c.plays) as f64,
+    };
+    let tracks = state.collect_tracks(&rel);
+    let spread_artists = q.spread_artists.as_deref() == Some("1");
+    let mut rng = rand::thread_rng();
+
+    if q.mode == Mode::Tracks {
+        let weighted = tracks
+            .into_iter()
+            .filter(|item| !recent.contains(&item.path))
+            .map(|item| {
+                let weight = track_weight(&item);
+                (item, weight)
+            })
+            .collect();
+        let order = weighted_shuffle(weighted, &mut rng);
+        return Ok(if spread_artists {
+            spread(order, q.count(), artist_of)
+        } else {
+            order.into_iter().take(q.count()).collect()
+        });
+    }
+
+    // Albums fall back to their folder when a track has no album tag.
+    let mut keys: Vec<String> = Vec::new();
+    let mut albums: HashMap<String, Vec<TrackRef>> = HashMap::new();
+    for item in tracks {
+        let key = match item.track.metadata.album_key() {
+            Some(album) => format!("album:{album}"),
+            None => format!(
+                "folder:{}",
+                item.path.rsplit_once('/').map_or("", |(d, _)| d)
+            ),
+        };
+        albums
+            .entry(key.clone())
+            .or_insert_with(|| {
+                keys.push(key);
+                Vec::new()
+            })
+            .push(item);
+    }
+    let weighted = keys
+        .into_iter()
+        .filter_map(|key| albums.remove_entry(&key))
+        .filter(|(_, tracks)| !tracks.iter().any(|t| recent.contains(&t.path)))
+        .map(|(key, tracks)| {
+            let weight = match (q.weight, key.strip_prefix("album:")) {
+                (Weight::Rating, Some(album)) => rating_weight(state, RatingTarget::Album, album),
+                // Average plays, so long albums aren't favoured.
+                (Weight::Plays, _) => {
+                    tracks.iter().map(&track_weight).sum::<f64>() / tracks.len() as f64
+                }
+                _ => 1.0,
+            };
+            (tracks, weight)
+        })
+        .collect();
+    let order = weighted_shuffle(weighted, &mut rng);
+    let order = if spread_artists {
+        spread(order, q.count(), |tracks| album_artist_of(tracks))
+    } else {
+        order.into_iter().take(q.count()).collect()
+    };
+    Ok(order.into_iter().flatten().collect())
+}
+
+/// Random tracks from the library or one folder, picked on the server.
+async fn random(
+    Query(q): Query<RandomQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<JsonRandomResp>, (StatusCode, String)> {
+    let tracks: Vec<JsonFolderTrack> = pick(&state, &q)?
+        .into_iter()
+        .map(|item| state.track_json(item))
+        .collect();
+    Ok(Json(JsonRandomResp {
+        mode: match q.mode {
+            Mode::Tracks => "tracks",
+            Mode::Albums => "albums",
+        },
+        tracks,
+    }))
+}
+
+/// The same pick as an M3U8 playlist; every request draws a new one.
+async fn random_m3u8(
+    Query(q): Query<RandomQuery>,
+    Query(export): Query<helpers::ExportQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    let tracks = pick(&state, &q)?;
+    Ok(helpers::playlist_response(
+        ExportFormat::M3u8,
+        &RenderOptions::new(&state.base, "Random").extended(export.extended()),
+        &tracks,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn weighted_shuffle_favours_heavy_items() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let order = weighted_shuffle(vec![("light", 1.0), ("heavy", 9.0)], &mut rng);
+            assert_eq!(order.len(), 2);
+            if order[0] == "heavy" {
+                heavy_first += 1;
+            }
+        }
+        // Expected 900 of 1000.
+        assert!((850..950).contains(&heavy_first), "{heavy_first}");
+    }
+
+    #[test]
+    fn spread_avoids_back_to_back_artists() {
+        let artist = |s: &&str| Some(s[..1].to_string());
+        let out = spread(vec!["a1", "a2", "a3", "b1", "c1"], 5, artist);
+        assert_eq!(out, vec!["a1", "b1", "a2", "c1", "a3"]);
+        let out = spread(vec!["a1", "a2", "b1"], 2, artist);
+        assert_eq!(out, vec!["a1", "b1"]);
+        // Repeats are allowed once nothing else is left.
+        let out = spread(vec!["a1", "a2"], 5, artist);
+        assert_eq!(out, vec!["a1", "a2"]);
+    }
+}
@@ -0,0 +1,95 @@
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode,
+    routing::{get, put},
+};
+
+use super::{
+    helpers,
+    state::AppState,
+    types::{JsonFolderTrack, JsonRatingEntry, JsonRatingsResp},
+};
+use crate::ratings::{RatingTarget, RatingUpdate};
+
+const MAX_KEY_LEN: usize = 1000;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_ratings))
+        .route("/track", put(rate_track))
+        .route("/album", put(rate_album))
+}
+
+#[derive(serde::Deserialize)]
+struct RateTrackReq {
+    path: String,
+    #[serde(flatten)]
+    update: RatingUpdate,
+}
+
+/// An album is named either by its key or by the path of any track on it.
+#[derive(serde::Deserialize)]
+struct RateAlbumReq {
+    key: Option<String>,
+    path: Option<String>,
+    #[serde(flatten)]
+    update: RatingUpdate,
+}
+
+fn entries(state: &AppState, target: RatingTarget) -> Vec<JsonRatingEntry> {
+    state
+        .ratings
+        .list(target)
+        .into_iter()
+        .map(|(key, rating)| JsonRatingEntry { key, rating })
+        .collect()
+}
+
+async fn list_ratings(State(state): State<AppState>) -> Json<JsonRatingsResp> {
+    Json(JsonRatingsResp {
+        tracks: entries(&state, RatingTarget::Track),
+        albums: entries(&state, RatingTarget::Album),
+    })
+}
+
+async fn rate_track(
+    State(state): State<AppState>,
+    Json(req): Json<RateTrackReq>,
+) -> Result<Json<JsonFolderTrack>, (StatusCode, String)> {
+    let rel = helpers::validate_library_path(&req.path)
+        .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let item = state
+        .find_track(&rel)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    state
+        .ratings
+        .set(RatingTarget::Track, &item.path, req.update)
+        .map_err(helpers::store_error)?;
+    state.schedule_tag_write(&item.path);
+    Ok(Json(state.track_json(item)))
+}
+
+async fn rate_album(
+    State(state): State<AppState>,
+    Json(req): Json<RateAlbumReq>,
+) -> Result<Json<JsonRatingEntry>, (StatusCode, String)> {
+    let bad_request = || (StatusCode::BAD_REQUEST, String::new());
+    let key = match (req.key, req.path) {
+        (Some(key), None) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key,
+        (None, Some(path)) => {
+            let rel = helpers::validate_library_path(&path).map_err(|_| bad_request())?;
+            let item = state
+                .find_track(&rel)
+                .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+            // Tracks without an album tag have no album to rate.
+            item.track.metadata.album_key().ok_or_else(bad_request)?
+        }
+        _ => return Err(bad_request()),
+    };
+    let rating = state
+        .ratings
+        .set(RatingTarget::Album, &key, req.update)
+        .map_err(helpers::store_error)?;
+    Ok(Json(JsonRatingEntry { key, rating }))
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+
+use super::{
+    state::AppState,
+    types::{JsonRecentGroup, JsonRecentResp},
+};
+use crate::library::TrackRef;
+use crate::playlist::xml_escape;
+use crate::time::{self, unix_now};
+
+const DEFAULT_DAYS: u64 = 30;
+const MAX_DAYS: u64 = 3650;
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+const SECS_PER_DAY: u64 = 86_400;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/recent", get(recent))
+        .route("/api/recent.atom", get(recent_atom))
+}
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum GroupBy {
+    /// Tracks without an album tag fall back to their folder.
+    #[default]
+    Album,
+    Folder,
+}
+
+#[derive(serde::Deserialize)]
+struct RecentQuery {
+    days: Option<u64>,
+    #[serde(default)]
+    group: GroupBy,
+    /// Most groups to return.
+    limit: Option<usize>,
+}
+
+impl RecentQuery {
+    fn days(&self) -> u64 {
+        self.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS)
+    }
+
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+}
+
+fn folder_of(path: &str) -> &str {
+    path.rsplit_once('/').map(|(d, _)| d).unwrap_or("")
+}
+
+/// Tracks first seen since `since`, grouped and sorted newest first.
+fn recent_groups(state: &AppState, since: u64, by: GroupBy, limit: usize) -> Vec<JsonRecentGroup> {
+    let mut order: Vec<String> = Vec::new();
+    let mut groups: HashMap<String, Vec<TrackRef>> = HashMap::new();
+    for item in state.collect_tracks("") {
+        if item.track.first_seen.is_none_or(|t| t < since) {
+            continue;
+        }
+        let album = match by {
+            GroupBy::Album => item.track.metadata.album_key(),
+            GroupBy::Folder => None,
+        };
+        let key = match album {
+            Some(album) => format!("album:{album}"),
+            None => format!("folder:{}", folder_of(&item.path)),
+        };
+        groups
+            .entry(key.clone())
+            .or_insert_with(|| {
+                order.push(key);
+                Vec::new()
+            })
+            .push(item);
+    }
+    let mut out: Vec<JsonRecentGroup> = order
+        .into_iter()
+        .filter_map(|key| {
+            let tracks = groups.remove(&key)?;
+            let first = tracks.first()?;
+            let meta = &first.track.metadata;
+            let folder = folder_of(&first.path).to_string();
+            let title = key
+                .starts_with("album:")
+                .then(|| meta.album.clone())
+                .flatten()
+                .unwrap_or_else(|| folder.rsplit('/').next().unwrap_or("").to_string());
+            let artist = meta.album_artist.clone().or_else(|| meta.artist.clone());
+            let added_at = tracks
+                .iter()
+                .filter_map(|t| t.track.first_seen)
+                .max()
+                .unwrap_or(since);
+            let tracks: Vec<_> = tracks
+                .into_iter()
+                .map(|item| state.track_json(item))
+                .collect();
+            let artwork_url = tracks.iter().find_map(|t| t.artwork_url.clone());
+            Some(JsonRecentGroup {
+                key,
+                title,
+                artist,
+                folder,
+                added_at,
+                artwork_url,
+                tracks,
+            })
+        })
+        .collect();
+    out.sort_by(|a, b| b.added_at.cmp(&a.added_at).then(a.key.cmp(&b.key)));
+    out.truncate(limit);
+    out
+}
+
+/// What was added to the library in the last `days` days (30 by default),
+/// grouped by album or by folder.
+async fn recent(
+    Query(q): Query<RecentQuery>,
+    State(state): State<AppState>,
+) -> Json<JsonRecentResp> {
+    let days = q.days();
+    let since = unix_now().saturating_sub(days * SECS_PER_DAY);
+    let groups = recent_groups(&state, since, q.group, q.limit());
+    Json(JsonRecentResp {
+        days,
+        since,
+        groups,
+    })
+}
+
+/// The same groups as an Atom feed, one entry per album or folder.
+async fn recent_atom(Query(q): Query<RecentQuery>, State(state): State<AppState>) -> Response {
+    let now = unix_now();
+    let since = now.saturating_sub(q.days() * SECS_PER_DAY);
+    let groups = recent_groups(&state, since, q.group, q.limit());
+    let base = state.base.trim_end_matches('/');
+    let feed_url = xml_escape(&format!("{base}/api/recent.atom"));
+    let updated = groups.first().map_or(now, |g| g.added_at);
+
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    body.push_str("  <title>Recently added</title>\n");
+    body.push_str(&format!("  <id>{feed_url}</id>\n"));
+    body.push_str(&format!("  <link rel=\"self\" href=\"{feed_url}\" />\n"));
+    body.push_str(&format!("  <link href=\"{}/\" />\n", xml_escape(base)));
+    body.push_str(&format!(
+        "  <updated>{}</updated>\n",
+        time::rfc3339(updated)
+    ));
+    body.push_str("  <author><name>musrv</name></author>\n");
+    for group in &groups {
+        let playlist = format!(
+            "{base}/api/folder.m3u8?path={}",
+            urlencoding::encode(&group.folder)
+        );
+        let title = match &group.artist {
+            Some(artist) => format!("{artist} – {}", group.title),
+            None => group.title.clone(),
+        };
+        body.push_str("  <entry>\n");
+        body.push_str(&format!("    <title>{}</title>\n", xml_escape(&title)));
+        // A group that grows later gets a new id, so readers show it again.
+        body.push_str(&format!(
+            "    <id>{}</id>\n",
+            xml_escape(&format!(
+                "{base}/api/recent/{}/{}",
+                urlencoding::encode(&group.key),
+                group.added_at
+            ))
+        ));
+        body.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            time::rfc3339(group.added_at)
+        ));
+        body.push_str(&format!(
+            "    <link rel=\"alternate\" type=\"audio/x-mpegurl\" href=\"{}\" />\n",
+            xml_escape(&playlist)
+        ));
+        let mut html = String::new();
+        if let Some(art) = &group.artwork_url {
+            html.push_str(&format!(
+                "<p><img src=\"{}\" alt=\"\" /></p>",
+                xml_escape(art)
+            ));
+        }
+        html.push_str("<ol>");
+        for track in &group.tracks {
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                xml_escape(&track.url),
+                xml_escape(&track.display_name)
+            ));
+        }
+        html.push_str("</ol>");
+        body.push_str(&format!(
+            "    <content type=\"html\">{}</content>\n",
+            xml_escape(&html)
+        ));
+        body.push_str("  </entry>\n");
+    }
+    body.push_str("</feed>\n");
+    (
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,143 @@
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+
+use super::{
+    helpers,
+    scrobble::client_name,
+    state::AppState,
+    types::{JsonResumeEntry, JsonResumeResp},
+};
+use crate::library::TrackRef;
+use crate::resume::ResumePoint;
+
+/// A save this close to the end counts as finished and clears the position.
+const FINISHED_MARGIN_SECS: f64 = 30.0;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_positions).put(save_position))
+        .route("/track", get(get_position).delete(clear_position))
+}
+
+#[derive(serde::Deserialize)]
+struct ClientQuery {
+    client: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct TrackQuery {
+    path: String,
+    client: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct SaveReq {
+    path: String,
+    /// Seconds from the start of the track.
+    position: f64,
+    client: Option<String>,
+}
+
+/// Positions saved without a client name are shared by all such clients.
+fn client_key(client: Option<String>) -> Result<String, (StatusCode, String)> {
+    Ok(client_name(client)?.unwrap_or_default())
+}
+
+fn lookup(state: &AppState, path: &str) -> Result<TrackRef, (StatusCode, String)> {
+    let rel = helpers::validate_request_path(path)
+        .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    state
+        .find_track(&rel)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))
+}
+
+fn entry(state: &AppState, path: String, point: ResumePoint) -> JsonResumeEntry {
+    let track = state.find_track(&path).map(|item| state.track_json(item));
+    JsonResumeEntry {
+        path,
+        position: point.position,
+        updated_at: point.updated_at,
+        track,
+    }
+}
+
+/// Everything a client can pick up again, most recent first.
+async fn list_positions(
+    Query(q): Query<ClientQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<JsonResumeResp>, (StatusCode, String)> {
+    let client = client_key(q.client)?;
+    let entries = state
+        .resume
+        .list(&client)
+        .into_iter()
+        .map(|(path, point)| entry(&state, path, point))
+        .collect();
+    Ok(Json(JsonResumeResp { client, entries }))
+}
+
+async fn get_position(
+    Query(q): Query<TrackQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<JsonResumeEntry>, (StatusCode, String)> {
+    let client = client_key(q.client)?;
+    let item = lookup(&state, &q.path)?;
+    let point = state
+        .resume
+        .get(&client, &item.path)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    Ok(Json(entry(&state, item.path, point)))
+}
+
+/// Saves where a client is in a track. Positions at the very start or
+/// within the last 30 seconds or 5% of the track, whichever is shorter,
+/// clear the entry instead, so finished tracks start over next time.
+async fn save_position(
+    State(state): State<AppState>,
+    Json(req): Json<SaveReq>,
+) -> Result<Response, (StatusCode, String)> {
+    let client = client_key(req.client)?;
+    // Body paths arrive decoded already.
+    let rel = helpers::validate_library_path(&req.path)
+        .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    let item = state
+        .find_track(&rel)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    if !req.position.is_finite() || req.position < 0.0 {
+        return Err((StatusCode::BAD_REQUEST, String::new()));
+    }
+    let finished = item.track.metadata.duration.is_some_and(|duration| {
+        req.position >= duration - FINISHED_MARGIN_SECS.min(duration * 0.05)
+    });
+    if req.position == 0.0 || finished {
+        state
+            .resume
+            .clear(&client, &item.path)
+            .map_err(helpers::store_error)?;
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    let point = state
+        .resume
+        .set(&client, &item.path, req.position)
+        .map_err(helpers::store_error)?;
+    Ok(Json(entry(&state, item.path, point)).into_response())
+}
+
+async fn clear_position(
+    Query(q): Query<TrackQuery>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let client = client_key(q.client)?;
+    let rel = helpers::validate_request_path(&q.path)
+        .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?;
+    state
+        .resume
+        .clear(&client, &rel)
+        .map_err(helpers::store_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
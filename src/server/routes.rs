@@ -2,19 +2,28 @@ use axum::Json;
 use axum::http::Request;
 use axum::{
     Router,
-    extract::{Path as AxPath, Query, State},
+    extract::{DefaultBodyLimit, Path as AxPath, Query, State},
     http::{StatusCode, header},
+    middleware,
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post, put},
 };
 
 use bytes::Bytes;
 
+use std::convert::Infallible;
 use std::sync::atomic::Ordering;
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
 use tower::util::ServiceExt;
 use tower_http::{services::ServeFile, trace::TraceLayer};
 
-use super::{helpers, state::AppState};
+use crate::dupes::{self, Candidate, DupeReport};
+use crate::playlist::{ExportFormat, RenderOptions};
+
+use super::{
+    archive, auth, cue_stream, helpers, lyrics, playlists, random, ratings, recent, resume,
+    scrobble, smart_playlists, state::AppState, tag_edit, upload, waveform,
+};
 
 pub fn build_router(state: AppState) -> Router {
     Router::new()
@@ -25,13 +34,42 @@ pub fn build_router(state: AppState) -> Router {
         .route("/icon.svg", get(app_icon))
         .route("/api/folder", get(api_folder))
         .route("/api/folder.m3u8", get(api_folder_m3u8))
+        .route("/api/folder.pls", get(api_folder_pls))
+        .route("/api/folder.xspf", get(api_folder_xspf))
+        .route("/api/folder.jspf", get(api_folder_jspf))
+        .route("/api/folder.asx", get(api_folder_asx))
+        .route("/api/folder.rss", get(api_folder_rss))
+        .route("/api/folder.zip", get(api_folder_zip))
         .route("/api/artwork/:id", get(api_artwork))
-        .route("/admin/rescan", get(admin_rescan))
+        .route("/api/events", get(api_events))
+        .merge(scrobble::router())
+        .merge(lyrics::router())
+        .merge(recent::router())
+        .merge(random::router())
+        .merge(waveform::router())
+        .merge(tag_edit::router(state.clone()))
+        .nest("/api/playlists", playlists::router())
+        .nest("/api/ratings", ratings::router())
+        .nest("/api/resume", resume::router())
+        .nest("/api/smart-playlists", smart_playlists::router())
+        .nest("/admin", admin_router(state.clone()))
         .route("/*path", get(static_file))
         .layer(TraceLayer::new_for_http())
         .with_state(state)
 }
 
+fn admin_router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/rescan", post(admin_rescan))
+        .route("/scan/status", get(admin_scan_status))
+        .route("/duplicates", get(admin_duplicates))
+        .route(
+            "/upload/*path",
+            put(upload::admin_upload).layer(DefaultBodyLimit::disable()),
+        )
+        .route_layer(middleware::from_fn_with_state(state, auth::require_admin))
+}
+
 fn static_asset(
     content_type: &'static str,
     body: &'static str,
@@ -83,9 +121,15 @@ async fn app_icon(
 #[derive(serde::Deserialize)]
 struct FolderQuery {
     path: Option<String>,
+    /// `1` writes playlist entries relative to the folder instead of as URLs.
+    relative: Option<String>,
+    #[serde(flatten)]
+    export: helpers::ExportQuery,
 }
 
-use super::types::{JsonFolderAlbum, JsonFolderResp, JsonFolderTrack};
+use super::types::{
+    JsonAdminResp, JsonFolderAlbum, JsonFolderResp, JsonMountScanStatus, JsonScanStatusResp,
+};
 
 async fn api_folder(
     Query(q): Query<FolderQuery>,
@@ -102,58 +146,38 @@ async fn api_folder(
         rel.rsplit('/').next().unwrap_or("").to_string()
     };
     let mut albums = Vec::new();
-    let lib = state.lib.load();
-
-    let scanning = !state.scan_ready.load(Ordering::SeqCst);
+    let scanning = state.is_scanning(&rel);
 
-    if let Some(entry) = lib.folder(&rel) {
-        for child in &entry.subfolders {
-            let child_name = child.rsplit('/').next().unwrap_or("").to_string();
+    if rel.is_empty() && state.has_named_mounts() {
+        for mount in state.mounts.iter() {
             albums.push(JsonFolderAlbum {
-                name: child_name,
-                path: child.clone(),
+                name: mount.name.clone(),
+                path: mount.name.clone(),
             });
         }
+    } else if let Some((mount, inner)) = state.resolve(&rel) {
+        let lib = mount.lib.load();
+        if let Some(entry) = lib.folder(&inner) {
+            for child in &entry.subfolders {
+                let child_name = child.rsplit('/').next().unwrap_or("").to_string();
+                albums.push(JsonFolderAlbum {
+                    name: child_name,
+                    path: mount.public_path(child),
+                });
+            }
+        }
     }
-    let base_url = state.base.clone();
-    let base_trimmed = state.base.trim_end_matches('/').to_string();
-
-    let tracks = lib
-        .collect_tracks_recursive(&rel)
+    let tracks = state
+        .collect_tracks(&rel)
         .into_iter()
-        .map(|track| {
-            let rel_path = track.path.to_string_lossy().replace('\\', "/");
-            let file_name = track
-                .path
-                .file_name()
-                .and_then(|s| s.to_str())
-                .unwrap_or("")
-                .to_string();
-            let metadata = &track.metadata;
-            let display_name = metadata.title.clone().unwrap_or_else(|| file_name.clone());
-            let encoded = crate::playlist::encode_path(&rel_path);
-            let artwork_url = metadata
-                .artwork_id
-                .as_ref()
-                .map(|id| format!("{base_trimmed}/api/artwork/{id}"));
-            JsonFolderTrack {
-                name: file_name,
-                display_name,
-                relative_path: rel_path,
-                url: format!("{base_url}{encoded}"),
-                title: metadata.title.clone(),
-                artist: metadata.artist.clone(),
-                album: metadata.album.clone(),
-                duration: metadata.duration,
-                artwork_url,
-            }
-        })
+        .map(|item| state.track_json(item))
         .collect();
     let m3u8 = format!(
         "{}/api/folder.m3u8?path={}",
         state.base.trim_end_matches('/'),
         urlencoding::encode(&rel)
     );
+    let long_form = state.is_long_form(&rel);
     let body = JsonFolderResp {
         name,
         path: rel,
@@ -161,37 +185,107 @@ async fn api_folder(
         albums,
         tracks,
         scanning,
+        long_form,
+        revision: state.revision.load(Ordering::SeqCst),
     };
     Ok(Json(body))
 }
 
 async fn api_folder_m3u8(
+    q: Query<FolderQuery>,
+    state: State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    folder_playlist(q, state, ExportFormat::M3u8)
+}
+
+async fn api_folder_pls(
+    q: Query<FolderQuery>,
+    state: State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    folder_playlist(q, state, ExportFormat::Pls)
+}
+
+async fn api_folder_xspf(
+    q: Query<FolderQuery>,
+    state: State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    folder_playlist(q, state, ExportFormat::Xspf)
+}
+
+async fn api_folder_jspf(
+    q: Query<FolderQuery>,
+    state: State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    folder_playlist(q, state, ExportFormat::Jspf)
+}
+
+async fn api_folder_asx(
+    q: Query<FolderQuery>,
+    state: State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    folder_playlist(q, state, ExportFormat::Asx)
+}
+
+async fn api_folder_rss(
+    q: Query<FolderQuery>,
+    state: State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    folder_playlist(q, state, ExportFormat::Rss)
+}
+
+fn folder_playlist(
     Query(q): Query<FolderQuery>,
     State(state): State<AppState>,
-) -> Result<impl axum::response::IntoResponse, (StatusCode, String)> {
+    format: ExportFormat,
+) -> Result<Response, (StatusCode, String)> {
     let rel = match q.path {
         Some(path) if !path.is_empty() => helpers::validate_request_path(&path)
             .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?,
         _ => String::new(),
     };
-    let lib = state.lib.load();
-    let tracks = lib.collect_tracks_recursive(&rel);
-    let body = crate::playlist::render_m3u8(&state.base, &state.root, &tracks);
-    Ok((
-        [
-            (header::CONTENT_TYPE, "audio/x-mpegurl; charset=utf-8"),
-            (header::CACHE_CONTROL, "no-cache"),
-        ],
-        body,
-    ))
+    let tracks = state.collect_tracks(&rel);
+    let title = rel
+        .rsplit('/')
+        .next()
+        .filter(|s| !s.is_empty())
+        .unwrap_or("musrv");
+    let mut opts = RenderOptions::new(&state.base, title).extended(q.export.extended());
+    if q.relative.as_deref() == Some("1") {
+        opts = opts.relative_to(&rel);
+    }
+    Ok(helpers::playlist_response(format, &opts, &tracks))
+}
+
+async fn api_folder_zip(
+    Query(q): Query<FolderQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    let rel = match q.path {
+        Some(path) if !path.is_empty() => helpers::validate_request_path(&path)
+            .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?,
+        _ => String::new(),
+    };
+    let tracks = state.collect_tracks(&rel);
+    if tracks.is_empty() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    let name = rel
+        .rsplit('/')
+        .next()
+        .filter(|s| !s.is_empty())
+        .unwrap_or("musrv");
+    archive::tracks_archive(&state, name, &rel, tracks).await
 }
 
 async fn api_artwork(
     AxPath(id): AxPath<String>,
     State(state): State<AppState>,
 ) -> Result<Response, (StatusCode, String)> {
-    let lib = state.lib.load();
-    let Some(art) = lib.artwork(&id) else {
+    let Some(art) = state
+        .mounts
+        .iter()
+        .find_map(|mount| mount.lib.load().artwork(&id))
+    else {
         return Err((StatusCode::NOT_FOUND, String::new()));
     };
 
@@ -209,46 +303,154 @@ async fn api_artwork(
     Ok(response)
 }
 
+async fn api_events(
+    State(state): State<AppState>,
+) -> axum::response::Sse<impl Stream<Item = Result<axum::response::sse::Event, Infallible>>> {
+    use axum::response::sse::{Event, KeepAlive, Sse};
+
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(|item| {
+        // Lagged subscribers skip the missed events and carry on.
+        let event = item.ok()?;
+        Event::default()
+            .event(event.name())
+            .json_data(&event)
+            .ok()
+            .map(Ok)
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
 async fn static_file(
     AxPath(path): AxPath<String>,
     State(state): State<AppState>,
     req: Request<axum::body::Body>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<Response, (StatusCode, String)> {
     let decoded = helpers::validate_request_path(&path)
         .map_err(|_| (StatusCode::NOT_FOUND, String::new()))?;
-    let abs = state.root.join(&decoded);
+    let Some((mount, inner)) = state.resolve(&decoded) else {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    };
+    // Tracks split out of a rip by a CUE sheet have no file of their own.
+    let span = mount.lib.load().track(&inner).and_then(|t| t.cue.clone());
+    let abs = match &span {
+        Some(span) => mount.root.join(&span.source),
+        None => mount.root.join(&inner),
+    };
     let abs = match tokio::fs::canonicalize(&abs).await {
         Ok(p) => p,
         Err(_) => return Err((StatusCode::NOT_FOUND, String::new())),
     };
-    if !abs.starts_with(&state.root) {
+    if !abs.starts_with(&mount.root) {
         return Err((StatusCode::NOT_FOUND, String::new()));
     }
+    if let Some(span) = span {
+        return cue_stream::serve(abs, &span, req.headers()).await;
+    }
     let svc = ServeFile::new(abs);
     let res = svc
         .oneshot(req)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
-    Ok(res)
-}
-
-async fn admin_rescan(State(state): State<AppState>) -> impl axum::response::IntoResponse {
-    let started = state.schedule_scan(false);
-    if started {
-        (
-            [
-                (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
-                (header::CACHE_CONTROL, "no-cache"),
-            ],
-            String::from("scheduled"),
-        )
+    Ok(res.into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct RescanQuery {
+    path: Option<String>,
+}
+
+async fn admin_rescan(
+    Query(q): Query<RescanQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<JsonAdminResp>, (StatusCode, String)> {
+    let rel = match q.path {
+        Some(path) if !path.is_empty() => helpers::validate_request_path(&path)
+            .map_err(|_| (StatusCode::BAD_REQUEST, String::new()))?,
+        _ => String::new(),
+    };
+    let status = if rel.is_empty() {
+        if state.schedule_scan(false) {
+            "scheduled"
+        } else {
+            "busy"
+        }
     } else {
-        (
-            [
-                (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
-                (header::CACHE_CONTROL, "no-cache"),
-            ],
-            String::from("busy"),
-        )
+        let Some((mount, inner)) = state.resolve(&rel) else {
+            return Err((StatusCode::NOT_FOUND, String::new()));
+        };
+        let inner = inner.trim_end_matches('/').to_string();
+        if inner.is_empty() {
+            if state.schedule_mount_scan(mount, false) {
+                "scheduled"
+            } else {
+                "busy"
+            }
+        } else {
+            // Folders that vanished from disk are still accepted so their
+            // tracks get dropped from the library.
+            let on_disk = tokio::fs::symlink_metadata(mount.root.join(&inner))
+                .await
+                .map(|m| m.is_dir())
+                .unwrap_or(false);
+            if !on_disk && mount.lib.load().folder(&inner).is_none() {
+                return Err((StatusCode::NOT_FOUND, String::new()));
+            }
+            state.schedule_folder_scan(mount, inner);
+            "queued"
+        }
+    };
+    Ok(Json(JsonAdminResp {
+        status,
+        path: (!rel.is_empty()).then_some(rel),
+    }))
+}
+
+async fn admin_scan_status(State(state): State<AppState>) -> Json<JsonScanStatusResp> {
+    let mounts = state
+        .mounts
+        .iter()
+        .map(|mount| JsonMountScanStatus {
+            name: mount.name.clone(),
+            scanning: mount.scan_active(),
+            last_scan: mount.last_report.load_full().map(|r| (*r).clone()),
+        })
+        .collect();
+    Json(JsonScanStatusResp { mounts })
+}
+
+#[derive(serde::Deserialize)]
+struct DuplicatesQuery {
+    /// Seconds two durations may differ by for a metadata match.
+    tolerance: Option<f64>,
+}
+
+/// Groups likely duplicate tracks across every root. Files are read to
+/// compare their contents, so this can take a while on large libraries.
+async fn admin_duplicates(
+    Query(q): Query<DuplicatesQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<DupeReport>, (StatusCode, String)> {
+    let tolerance = q.tolerance.unwrap_or(dupes::DEFAULT_TOLERANCE_SECS);
+    if !(0.0..=60.0).contains(&tolerance) {
+        return Err((StatusCode::BAD_REQUEST, String::new()));
     }
+    let candidates: Vec<Candidate> = state
+        .mounts
+        .iter()
+        .flat_map(|mount| {
+            let lib = mount.lib.load_full();
+            lib.tracks()
+                .iter()
+                .map(|track| Candidate {
+                    path: mount.public_path(&track.path.to_string_lossy().replace('\\', "/")),
+                    file: mount.root.join(&track.path),
+                    track: track.clone(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let report = tokio::task::spawn_blocking(move || dupes::find(&candidates, tolerance))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?;
+    Ok(Json(report))
 }
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+
+use super::{
+    helpers,
+    state::{AppState, NowPlaying},
+    types::{
+        JsonFolderTrack, JsonHistoryResp, JsonNowPlaying, JsonPlayEvent, JsonTopArtist,
+        JsonTopResp, JsonTopTrack,
+    },
+};
+use crate::history::PlayEvent;
+use crate::library::TrackRef;
+use crate::time::unix_now;
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 1000;
+/// How long a now-playing entry lives past the track's end.
+const NOW_PLAYING_GRACE_SECS: u64 = 60;
+/// Used for tracks without a known duration.
+const NOW_PLAYING_FALLBACK_SECS: u64 = 600;
+const MAX_CLIENT_LEN: usize = 100;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/scrobble", post(scrobble))
+        .route("/api/now-playing", get(list_now_playing).post(now_playing))
+        .route("/api/history", get(history))
+        .route("/api/history/top-tracks", get(top_tracks))
+        .route("/api/history/top-artists", get(top_artists))
+}
+
+#[derive(serde::Deserialize)]
+struct ScrobbleReq {
+    path: String,
+    /// Unix seconds when playback started; defaults to now.
+    timestamp: Option<u64>,
+    /// Seconds actually listened.
+    played: Option<f64>,
+    client: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct NowPlayingReq {
+    path: String,
+    client: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct HistoryQuery {
+    limit: Option<usize>,
+    /// Only events that started before this Unix time, for paging.
+    before: Option<u64>,
+}
+
+/// A time window given either as `days` back from now or as explicit
+/// `since`/`until` Unix times. No bounds means all time.
+#[derive(serde::Deserialize)]
+struct WindowQuery {
+    days: Option<u64>,
+    since: Option<u64>,
+    until: Option<u64>,
+    limit: Option<usize>,
+}
+
+impl WindowQuery {
+    fn bounds(&self, now: u64) -> (u64, u64) {
+        let since = match (self.since, self.days) {
+            (Some(since), _) => since,
+            (None, Some(days)) => now.saturating_sub(days.saturating_mul(86_400)),
+            (None, None) => 0,
+        };
+        (since, self.until.unwrap_or(u64::MAX))
+    }
+
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+    }
+}
+
+fn bad_request() -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, String::new())
+}
+
+fn lookup(state: &AppState, path: &str) -> Result<TrackRef, (StatusCode, String)> {
+    let rel = helpers::validate_library_path(path).map_err(|_| bad_request())?;
+    state
+        .find_track(&rel)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))
+}
+
+pub(super) fn client_name(client: Option<String>) -> Result<Option<String>, (StatusCode, String)> {
+    match client.map(|c| c.trim().to_string()) {
+        Some(c) if c.len() > MAX_CLIENT_LEN || c.chars().any(char::is_control) => {
+            Err(bad_request())
+        }
+        Some(c) if c.is_empty() => Ok(None),
+        other => Ok(other),
+    }
+}
+
+fn track_json(state: &AppState, path: &str) -> Option<JsonFolderTrack> {
+    state.find_track(path).map(|item| state.track_json(item))
+}
+
+async fn scrobble(
+    State(state): State<AppState>,
+    Json(req): Json<ScrobbleReq>,
+) -> Result<(StatusCode, Json<JsonPlayEvent>), (StatusCode, String)> {
+    let item = lookup(&state, &req.path)?;
+    let now = unix_now();
+    let at = req.timestamp.unwrap_or(now);
+    // Allow for some clock skew between clients and the server.
+    if at > now + 300 {
+        return Err(bad_request());
+    }
+    if req.played.is_some_and(|p| !p.is_finite() || p < 0.0) {
+        return Err(bad_request());
+    }
+    let client = client_name(req.client)?;
+    let event = PlayEvent {
+        path: item.path.clone(),
+        at,
+        played: req.played,
+        client,
+    };
+    let history = state.history.clone();
+    let recorded = event.clone();
+    tokio::task::spawn_blocking(move || history.record(recorded))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?
+        .map_err(|err| {
+            tracing::error!(?err, "failed to record play");
+            (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+        })?;
+    {
+        let mut playing = state.now_playing.lock().unwrap();
+        let key = event.client.clone().unwrap_or_default();
+        if playing.get(&key).is_some_and(|np| np.path == event.path) {
+            playing.remove(&key);
+        }
+    }
+    state.schedule_tag_write(&item.path);
+    let body = JsonPlayEvent {
+        track: Some(state.track_json(item)),
+        path: event.path,
+        at: event.at,
+        played: event.played,
+        client: event.client,
+    };
+    Ok((StatusCode::CREATED, Json(body)))
+}
+
+async fn now_playing(
+    State(state): State<AppState>,
+    Json(req): Json<NowPlayingReq>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let item = lookup(&state, &req.path)?;
+    let client = client_name(req.client)?.unwrap_or_default();
+    let now = unix_now();
+    let length = item
+        .track
+        .metadata
+        .duration
+        .map(|d| d.ceil() as u64)
+        .unwrap_or(NOW_PLAYING_FALLBACK_SECS);
+    state.now_playing.lock().unwrap().insert(
+        client,
+        NowPlaying {
+            path: item.path,
+            started_at: now,
+            expires_at: now + length + NOW_PLAYING_GRACE_SECS,
+        },
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_now_playing(State(state): State<AppState>) -> Json<Vec<JsonNowPlaying>> {
+    let now = unix_now();
+    let entries: Vec<(String, NowPlaying)> = {
+        let mut playing = state.now_playing.lock().unwrap();
+        playing.retain(|_, np| np.expires_at > now);
+        playing
+            .iter()
+            .map(|(client, np)| (client.clone(), np.clone()))
+            .collect()
+    };
+    let mut body: Vec<JsonNowPlaying> = entries
+        .into_iter()
+        .map(|(client, np)| JsonNowPlaying {
+            track: track_json(&state, &np.path),
+            client,
+            path: np.path,
+            started_at: np.started_at,
+        })
+        .collect();
+    body.sort_by_key(|np| std::cmp::Reverse(np.started_at));
+    Json(body)
+}
+
+async fn history(
+    Query(q): Query<HistoryQuery>,
+    State(state): State<AppState>,
+) -> Json<JsonHistoryResp> {
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let events = state
+        .history
+        .recent(limit, q.before)
+        .into_iter()
+        .map(|event| JsonPlayEvent {
+            track: track_json(&state, &event.path),
+            path: event.path,
+            at: event.at,
+            played: event.played,
+            client: event.client,
+        })
+        .collect();
+    Json(JsonHistoryResp { events })
+}
+
+async fn top_tracks(
+    Query(q): Query<WindowQuery>,
+    State(state): State<AppState>,
+) -> Json<JsonTopResp<JsonTopTrack>> {
+    let (since, until) = q.bounds(unix_now());
+    let mut counts: Vec<_> = state
+        .history
+        .play_counts(since, until)
+        .into_iter()
+        .collect();
+    counts.sort_by(|(a_path, a), (b_path, b)| {
+        b.plays
+            .cmp(&a.plays)
+            .then(b.last_played.cmp(&a.last_played))
+            .then(a_path.cmp(b_path))
+    });
+    let items = counts
+        .into_iter()
+        .take(q.limit())
+        .map(|(path, count)| JsonTopTrack {
+            track: track_json(&state, &path),
+            path,
+            plays: count.plays,
+            last_played: count.last_played,
+        })
+        .collect();
+    Json(JsonTopResp {
+        since,
+        until,
+        items,
+    })
+}
+
+/// Plays grouped by the artist tag of each track. Tracks that are gone from
+/// the library or have no artist are left out.
+async fn top_artists(
+    Query(q): Query<WindowQuery>,
+    State(state): State<AppState>,
+) -> Json<JsonTopResp<JsonTopArtist>> {
+    let (since, until) = q.bounds(unix_now());
+    let mut artists: HashMap<String, JsonTopArtist> = HashMap::new();
+    for (path, count) in state.history.play_counts(since, until) {
+        let Some(item) = state.find_track(&path) else {
+            continue;
+        };
+        let Some(artist) = item.track.metadata.artist.as_deref() else {
+            continue;
+        };
+        let entry = artists
+            .entry(artist.to_string())
+            .or_insert_with(|| JsonTopArtist {
+                artist: artist.to_string(),
+                plays: 0,
+                tracks: 0,
+                last_played: 0,
+            });
+        entry.plays += count.plays;
+        entry.tracks += 1;
+        entry.last_played = entry.last_played.max(count.last_played);
+    }
+    let mut items: Vec<JsonTopArtist> = artists.into_values().collect();
+    items.sort_by(|a, b| {
+        b.plays
+            .cmp(&a.plays)
+            .then(b.last_played.cmp(&a.last_played))
+            .then(a.artist.cmp(&b.artist))
+    });
+    items.truncate(q.limit());
+    Json(JsonTopResp {
+        since,
+        until,
+        items,
+    })
+}
@@ -0,0 +1,145 @@
+use axum::{
+    Json, Router,
+    extract::{Path as AxPath, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Serialize;
+
+use super::{helpers, state::AppState, types::JsonFolderTrack};
+use crate::library::TrackRef;
+use crate::playlist::{ExportFormat, RenderOptions};
+use crate::playlist_store::new_id;
+use crate::smart_playlist::{SmartPlaylist, SmartPlaylistDef};
+use crate::time::unix_now;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_smart).post(create_smart))
+        .route(
+            "/:id",
+            get(get_smart).put(update_smart).delete(delete_smart),
+        )
+}
+
+#[derive(Serialize)]
+struct JsonSmartSummary {
+    #[serde(flatten)]
+    playlist: SmartPlaylist,
+    m3u8: String,
+    track_count: usize,
+}
+
+#[derive(Serialize)]
+struct JsonSmartResp {
+    #[serde(flatten)]
+    playlist: SmartPlaylist,
+    m3u8: String,
+    tracks: Vec<JsonFolderTrack>,
+}
+
+/// Evaluates the rules against the library as it is now, so results follow
+/// every scan without being stored.
+pub fn smart_tracks(state: &AppState, playlist: &SmartPlaylist) -> Vec<TrackRef> {
+    playlist.def.evaluate(state.collect_tracks(""), unix_now())
+}
+
+fn validated(mut def: SmartPlaylistDef) -> Result<SmartPlaylistDef, (StatusCode, String)> {
+    def.validate()
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+    def.name = def.name.trim().to_string();
+    Ok(def)
+}
+
+fn smart_json(state: &AppState, playlist: SmartPlaylist) -> JsonSmartResp {
+    let tracks = smart_tracks(state, &playlist)
+        .into_iter()
+        .map(|t| state.track_json(t))
+        .collect();
+    JsonSmartResp {
+        m3u8: helpers::m3u8_url(&state.base, "smart-playlists", &playlist.id),
+        playlist,
+        tracks,
+    }
+}
+
+async fn list_smart(State(state): State<AppState>) -> Json<Vec<JsonSmartSummary>> {
+    let list = state
+        .smart_playlists
+        .list()
+        .into_iter()
+        .map(|playlist| JsonSmartSummary {
+            m3u8: helpers::m3u8_url(&state.base, "smart-playlists", &playlist.id),
+            track_count: smart_tracks(&state, &playlist).len(),
+            playlist,
+        })
+        .collect();
+    Json(list)
+}
+
+async fn create_smart(
+    State(state): State<AppState>,
+    Json(def): Json<SmartPlaylistDef>,
+) -> Result<(StatusCode, Json<JsonSmartResp>), (StatusCode, String)> {
+    let now = unix_now();
+    let playlist = SmartPlaylist {
+        id: new_id(),
+        def: validated(def)?,
+        created_at: now,
+        updated_at: now,
+    };
+    let playlist = state
+        .smart_playlists
+        .insert(playlist)
+        .map_err(helpers::store_error)?;
+    Ok((StatusCode::CREATED, Json(smart_json(&state, playlist))))
+}
+
+/// Serves the playlist as JSON, or as a playlist file when the id ends in
+/// an export extension such as `.m3u8` or `.xspf`.
+async fn get_smart(
+    AxPath(id): AxPath<String>,
+    Query(export): Query<helpers::ExportQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, String::new());
+    if let (id, Some(format)) = ExportFormat::split_name(&id) {
+        let playlist = state.smart_playlists.get(id).ok_or_else(not_found)?;
+        let tracks = smart_tracks(&state, &playlist);
+        return Ok(helpers::playlist_response(
+            format,
+            &RenderOptions::new(&state.base, &playlist.def.name).extended(export.extended()),
+            &tracks,
+        ));
+    }
+    let playlist = state.smart_playlists.get(&id).ok_or_else(not_found)?;
+    Ok(Json(smart_json(&state, playlist)).into_response())
+}
+
+async fn update_smart(
+    AxPath(id): AxPath<String>,
+    State(state): State<AppState>,
+    Json(def): Json<SmartPlaylistDef>,
+) -> Result<Json<JsonSmartResp>, (StatusCode, String)> {
+    let def = validated(def)?;
+    let playlist = state
+        .smart_playlists
+        .update(&id, |p| {
+            p.def = def;
+            Ok(())
+        })
+        .map_err(helpers::store_error)?;
+    Ok(Json(smart_json(&state, playlist)))
+}
+
+async fn delete_smart(
+    AxPath(id): AxPath<String>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .smart_playlists
+        .delete(&id)
+        .map_err(helpers::store_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,24 +1,316 @@
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
 };
+use std::time::{Duration, Instant};
 
-use crate::library::Library;
-use arc_swap::ArcSwap;
+use super::events::{EventBus, ServerEvent};
+use super::types::JsonFolderTrack;
+use crate::history::History;
+use crate::library::{self, Library, Track, TrackRef};
+use crate::loudness;
+use crate::playlist::PathRemap;
+use crate::playlist_store::{JsonStore, PLAYLISTS_FILE, PlaylistStore};
+use crate::ratings::{RatingStore, RatingTarget};
+use crate::resume::ResumeStore;
+use crate::scan_report::ScanReport;
+use crate::smart_playlist::SmartPlaylist;
+use crate::tag_writer::{self, TagEdit, TagStats};
+use crate::time;
+use arc_swap::{ArcSwap, ArcSwapOption};
 
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const SMART_PLAYLISTS_FILE: &str = "smart-playlists.json";
+pub const DEFAULT_MAX_UPLOAD_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+/// Tracks measured between library updates during loudness analysis.
+const LOUDNESS_BATCH: usize = 50;
+/// How long rating and play count changes must be quiet before their tags
+/// are written.
+const TAG_WRITE_DELAY: Duration = Duration::from_secs(5);
+/// Longest a queued tag write waits for a quiet moment.
+const TAG_WRITE_MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// A library root served under a name prefix. The name is empty when a
+/// single root is served without one.
 #[derive(Clone)]
-pub struct AppState {
-    pub lib: Arc<ArcSwap<Library>>,
-    pub base: String,
+pub struct Mount {
+    pub name: String,
     pub root: PathBuf,
+    pub lib: Arc<ArcSwap<Library>>,
     pub scan_ready: Arc<AtomicBool>,
     pub scan_in_progress: Arc<AtomicBool>,
+    pub last_report: Arc<ArcSwapOption<ScanReport>>,
+    scan_lock: Arc<tokio::sync::Mutex<()>>,
+    pending_folders: Arc<Mutex<HashSet<String>>>,
+    /// Folder scans that are queued or running.
+    folder_scans: Arc<AtomicUsize>,
+    loudness_running: Arc<AtomicBool>,
+    loudness_requested: Arc<AtomicBool>,
+    /// Files that could not be decoded, skipped until the server restarts.
+    loudness_failed: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl Mount {
+    pub fn new(name: impl Into<String>, root: PathBuf, lib: Library, ready: bool) -> Self {
+        let last_report = ScanReport::load(&root).ok().map(Arc::new);
+        Mount {
+            name: name.into(),
+            root,
+            lib: Arc::new(ArcSwap::from_pointee(lib)),
+            scan_ready: Arc::new(AtomicBool::new(ready)),
+            scan_in_progress: Arc::new(AtomicBool::new(false)),
+            last_report: Arc::new(ArcSwapOption::new(last_report)),
+            scan_lock: Arc::new(tokio::sync::Mutex::new(())),
+            pending_folders: Arc::new(Mutex::new(HashSet::new())),
+            folder_scans: Arc::new(AtomicUsize::new(0)),
+            loudness_running: Arc::new(AtomicBool::new(false)),
+            loudness_requested: Arc::new(AtomicBool::new(false)),
+            loudness_failed: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Turns a path relative to this mount's root into a public path.
+    pub fn public_path(&self, rel: &str) -> String {
+        if self.name.is_empty() {
+            rel.to_string()
+        } else if rel.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}/{}", self.name, rel)
+        }
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        !self.scan_ready.load(Ordering::SeqCst)
+    }
+
+    /// Whether a full or folder scan of this mount is queued or running.
+    pub fn scan_active(&self) -> bool {
+        self.scan_in_progress.load(Ordering::SeqCst) || self.folder_scans.load(Ordering::SeqCst) > 0
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pub mounts: Arc<Vec<Mount>>,
+    pub base: String,
+    pub events: EventBus,
+    pub revision: Arc<AtomicU64>,
+    /// Token required by `/admin/*`; admin routes are refused without one.
+    pub admin_token: Option<Arc<str>>,
+    /// Where server-side state such as saved playlists is kept. Defaults to
+    /// the first root's cache directory.
+    pub data_dir: PathBuf,
+    pub playlists: Arc<PlaylistStore>,
+    pub smart_playlists: Arc<JsonStore<SmartPlaylist>>,
+    /// Prefix rewrites applied to entries of playlist files in the tree.
+    pub playlist_remaps: Arc<Vec<PathRemap>>,
+    pub history: Arc<History>,
+    pub ratings: Arc<RatingStore>,
+    pub resume: Arc<ResumeStore>,
+    /// Folders holding audiobooks, mixes and other long-form content that
+    /// players should resume rather than restart. An empty entry covers
+    /// everything.
+    pub long_form: Arc<Vec<String>>,
+    /// What each client reported as playing, keyed by client name.
+    pub now_playing: Arc<Mutex<HashMap<String, NowPlaying>>>,
+    /// Mirror ratings and play counts into the audio files' own tags.
+    pub allow_tag_writes: bool,
+    /// Public paths of tracks whose tags are waiting to be written.
+    pending_tag_writes: Arc<Mutex<HashSet<String>>>,
+    tag_writes_running: Arc<AtomicBool>,
+    /// Counts queued tag writes, so the writer can tell when they stop.
+    tag_write_requests: Arc<AtomicU64>,
+    /// Largest file accepted by `/admin/upload`.
+    pub max_upload_bytes: u64,
+    /// Measure the loudness of tracks without ReplayGain tags after scans.
+    pub analyze_loudness: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct NowPlaying {
+    pub path: String,
+    pub started_at: u64,
+    /// When the entry is dropped if the client never reports again.
+    pub expires_at: u64,
 }
 
 impl AppState {
+    pub fn new(base: String, mounts: Vec<Mount>) -> Self {
+        let data_dir = library::cache_dir(&mounts[0].root);
+        AppState {
+            mounts: Arc::new(mounts),
+            base,
+            events: EventBus::new(),
+            revision: Arc::new(AtomicU64::new(1)),
+            admin_token: None,
+            playlists: Arc::new(PlaylistStore::open(&data_dir, PLAYLISTS_FILE)),
+            smart_playlists: Arc::new(JsonStore::open(&data_dir, SMART_PLAYLISTS_FILE)),
+            history: Arc::new(History::open(&data_dir)),
+            ratings: Arc::new(RatingStore::open(&data_dir)),
+            resume: Arc::new(ResumeStore::open(&data_dir)),
+            data_dir,
+            long_form: Arc::new(Vec::new()),
+            playlist_remaps: Arc::new(Vec::new()),
+            now_playing: Arc::new(Mutex::new(HashMap::new())),
+            allow_tag_writes: false,
+            pending_tag_writes: Arc::new(Mutex::new(HashSet::new())),
+            tag_writes_running: Arc::new(AtomicBool::new(false)),
+            tag_write_requests: Arc::new(AtomicU64::new(0)),
+            max_upload_bytes: DEFAULT_MAX_UPLOAD_BYTES,
+            analyze_loudness: false,
+        }
+    }
+
+    pub fn with_playlist_remaps(mut self, remaps: Vec<PathRemap>) -> Self {
+        self.playlist_remaps = Arc::new(remaps);
+        self
+    }
+
+    pub fn with_data_dir(mut self, dir: PathBuf) -> Self {
+        self.playlists = Arc::new(PlaylistStore::open(&dir, PLAYLISTS_FILE));
+        self.smart_playlists = Arc::new(JsonStore::open(&dir, SMART_PLAYLISTS_FILE));
+        self.history = Arc::new(History::open(&dir));
+        self.ratings = Arc::new(RatingStore::open(&dir));
+        self.resume = Arc::new(ResumeStore::open(&dir));
+        self.data_dir = dir;
+        self
+    }
+
+    pub fn with_long_form(mut self, folders: Vec<String>) -> Self {
+        self.long_form = Arc::new(
+            folders
+                .into_iter()
+                .map(|f| f.trim_matches('/').to_string())
+                .collect(),
+        );
+        self
+    }
+
+    /// Whether a public path lies in one of the long-form folders.
+    pub fn is_long_form(&self, path: &str) -> bool {
+        self.long_form.iter().any(|folder| {
+            folder.is_empty()
+                || path
+                    .strip_prefix(folder.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    pub fn with_tag_writes(mut self, allow: bool) -> Self {
+        self.allow_tag_writes = allow;
+        self
+    }
+
+    pub fn with_max_upload_bytes(mut self, bytes: u64) -> Self {
+        self.max_upload_bytes = bytes;
+        self
+    }
+
+    pub fn with_loudness_analysis(mut self, enabled: bool) -> Self {
+        self.analyze_loudness = enabled;
+        self
+    }
+
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(Arc::from(token.into()));
+        self
+    }
+
+    /// True when the mounts are listed as top-level folders rather than a
+    /// single root being served directly.
+    pub fn has_named_mounts(&self) -> bool {
+        !(self.mounts.len() == 1 && self.mounts[0].name.is_empty())
+    }
+
+    /// Finds the mount a public path belongs to and returns the remainder
+    /// relative to that mount's root.
+    pub fn resolve(&self, rel: &str) -> Option<(&Mount, String)> {
+        if !self.has_named_mounts() {
+            return Some((&self.mounts[0], rel.to_string()));
+        }
+        let (head, rest) = rel.split_once('/').unwrap_or((rel, ""));
+        self.mounts
+            .iter()
+            .find(|m| m.name == head)
+            .map(|m| (m, rest.to_string()))
+    }
+
+    /// Tracks under a public folder path, including every mount for the
+    /// virtual root.
+    pub fn collect_tracks(&self, rel: &str) -> Vec<TrackRef> {
+        if rel.is_empty() && self.has_named_mounts() {
+            return self
+                .mounts
+                .iter()
+                .flat_map(|m| mount_tracks(m, ""))
+                .collect();
+        }
+        match self.resolve(rel) {
+            Some((mount, inner)) => mount_tracks(mount, &inner),
+            None => Vec::new(),
+        }
+    }
+
+    /// Looks up a single track by its public path.
+    pub fn find_track(&self, rel: &str) -> Option<TrackRef> {
+        let (mount, inner) = self.resolve(rel)?;
+        let track = mount.lib.load().track(&inner)?;
+        Some(TrackRef {
+            path: rel.to_string(),
+            track,
+        })
+    }
+
+    /// JSON for a track, with its favorite and rating state filled in.
+    pub fn track_json(&self, item: TrackRef) -> JsonFolderTrack {
+        let mut json = JsonFolderTrack::from_track(&self.base, item);
+        json.long_form = self.is_long_form(&json.relative_path);
+        if let Some(r) = self.ratings.get(RatingTarget::Track, &json.relative_path) {
+            json.favorite = r.favorite;
+            json.rating = r.rating;
+        }
+        if let Some(r) = json
+            .album_key
+            .as_deref()
+            .and_then(|key| self.ratings.get(RatingTarget::Album, key))
+        {
+            json.album_favorite = r.favorite;
+            json.album_rating = r.rating;
+        }
+        json
+    }
+
+    /// Maps an absolute filesystem path under one of the roots to its public
+    /// path.
+    pub fn public_path_for(&self, abs: &Path) -> Option<String> {
+        self.mounts.iter().find_map(|mount| {
+            let rel = abs.strip_prefix(&mount.root).ok()?;
+            Some(mount.public_path(&rel.to_string_lossy().replace('\\', "/")))
+        })
+    }
+
+    pub fn is_scanning(&self, rel: &str) -> bool {
+        if rel.is_empty() {
+            return self.mounts.iter().any(Mount::is_scanning);
+        }
+        self.resolve(rel)
+            .map(|(mount, _)| mount.is_scanning())
+            .unwrap_or(false)
+    }
+
     pub fn schedule_scan(&self, mark_unready: bool) -> bool {
-        if self
+        let mut started = false;
+        for mount in self.mounts.iter() {
+            started |= self.schedule_mount_scan(mount, mark_unready);
+        }
+        started
+    }
+
+    pub fn schedule_mount_scan(&self, mount: &Mount, mark_unready: bool) -> bool {
+        if mount
             .scan_in_progress
             .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
             .is_err()
@@ -26,31 +318,389 @@ impl AppState {
             return false;
         }
         if mark_unready {
-            self.scan_ready.store(false, Ordering::SeqCst);
+            mount.scan_ready.store(false, Ordering::SeqCst);
+        }
+        let state = self.clone();
+        let mount = mount.clone();
+        tokio::spawn(async move {
+            let finished = state.run_scan(&mount, None).await;
+            mount.scan_ready.store(true, Ordering::SeqCst);
+            mount.scan_in_progress.store(false, Ordering::SeqCst);
+            // Flags are settled first so clients reacting to these events see
+            // the finished state.
+            state.publish_scan_finished(finished);
+            state.schedule_loudness_analysis(&mount);
+        });
+        true
+    }
+
+    /// Queues a rescan of one folder of a mount, relative to its root.
+    /// Returns false when the same folder is already waiting to be scanned.
+    pub fn schedule_folder_scan(&self, mount: &Mount, folder: String) -> bool {
+        if !mount.pending_folders.lock().unwrap().insert(folder.clone()) {
+            return false;
         }
+        mount.folder_scans.fetch_add(1, Ordering::SeqCst);
         let state = self.clone();
+        let mount = mount.clone();
         tokio::spawn(async move {
-            let root = state.root.clone();
-            let result = tokio::task::spawn_blocking(move || -> anyhow::Result<Library> {
-                let lib = crate::library::Library::scan(root);
-                lib.save_cached()?;
-                Ok(lib)
+            let finished = state.run_scan(&mount, Some(folder)).await;
+            mount.folder_scans.fetch_sub(1, Ordering::SeqCst);
+            state.publish_scan_finished(finished);
+            state.schedule_loudness_analysis(&mount);
+        });
+        true
+    }
+
+    /// Runs one scan once the mount's scan lock is free, so full and folder
+    /// scans never overlap and each merges into the latest library.
+    async fn run_scan(&self, mount: &Mount, folder: Option<String>) -> Option<ServerEvent> {
+        let _guard = mount.scan_lock.lock().await;
+        if let Some(folder) = &folder {
+            mount.pending_folders.lock().unwrap().remove(folder);
+        }
+        self.events.publish(ServerEvent::ScanStarted {
+            mount: mount.name.clone(),
+            folder: folder.clone(),
+        });
+        let started = Instant::now();
+        let started_at = time::unix_now();
+        let current = mount.lib.load_full();
+        let events = self.events.clone();
+        let progress_mount = mount.clone();
+        let scope = folder.clone().unwrap_or_default();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut last_sent: Option<Instant> = None;
+            let (lib, mut report) = current.rescan_folder(&scope, &mut |p| {
+                if last_sent.is_some_and(|t| t.elapsed() < PROGRESS_INTERVAL) {
+                    return;
+                }
+                last_sent = Some(Instant::now());
+                events.publish(ServerEvent::ScanProgress {
+                    mount: progress_mount.name.clone(),
+                    files_seen: p.files_seen,
+                    files_parsed: p.files_parsed,
+                    current_folder: progress_mount.public_path(&p.current_folder),
+                });
+            });
+            if let Err(err) = lib.save_cached() {
+                tracing::error!(?err, "failed to save library cache");
+                report.error = Some(format!("failed to save library cache: {err:#}"));
+            }
+            (lib, report)
+        })
+        .await;
+        let (finished, report) = match result {
+            Ok((lib, report)) => {
+                let summary = ServerEvent::ScanFinished {
+                    mount: mount.name.clone(),
+                    folder,
+                    tracks: lib.tracks().len(),
+                    folders: lib.folder_count(),
+                    artworks: lib.artwork_count(),
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                };
+                mount.lib.store(Arc::new(lib));
+                (Some(summary), report)
+            }
+            Err(err) => {
+                tracing::error!(mount = %mount.name, "scan task join error: {}", err);
+                let elapsed_ms = started.elapsed().as_millis() as u64;
+                let mut report =
+                    ScanReport::failed(started_at, elapsed_ms, format!("scan task failed: {err}"));
+                report.scope = folder;
+                (None, report)
+            }
+        };
+        if let Err(err) = report.save(&mount.root) {
+            tracing::warn!(?err, mount = %mount.name, "failed to save scan report");
+        }
+        mount.last_report.store(Some(Arc::new(report)));
+        finished
+    }
+
+    /// Measures tracks that have neither ReplayGain tags nor a stored
+    /// measurement, when loudness analysis is enabled. Runs in the
+    /// background one batch at a time; a request while it runs makes it
+    /// look again once it is done.
+    pub fn schedule_loudness_analysis(&self, mount: &Mount) {
+        if !self.analyze_loudness {
+            return;
+        }
+        mount.loudness_requested.store(true, Ordering::SeqCst);
+        if mount
+            .loudness_running
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return;
+        }
+        let state = self.clone();
+        let mount = mount.clone();
+        tokio::spawn(async move {
+            while mount.loudness_requested.swap(false, Ordering::SeqCst) {
+                state.run_loudness_analysis(&mount).await;
+            }
+            mount.loudness_running.store(false, Ordering::SeqCst);
+            if mount.loudness_requested.load(Ordering::SeqCst) {
+                state.schedule_loudness_analysis(&mount);
+            }
+        });
+    }
+
+    async fn run_loudness_analysis(&self, mount: &Mount) {
+        loop {
+            let batch: Vec<Arc<Track>> = {
+                let failed = mount.loudness_failed.lock().unwrap();
+                mount
+                    .lib
+                    .load()
+                    .tracks()
+                    .iter()
+                    .filter(|t| {
+                        t.loudness.is_none()
+                            && t.cue.is_none()
+                            && t.metadata
+                                .replay_gain
+                                .is_none_or(|g| g.track_gain.is_none())
+                            && !failed.contains(&t.path)
+                    })
+                    .take(LOUDNESS_BATCH)
+                    .cloned()
+                    .collect()
+            };
+            let done = batch.is_empty();
+            let root = mount.root.clone();
+            let Ok(results) = tokio::task::spawn_blocking(move || {
+                batch
+                    .into_iter()
+                    .map(|track| {
+                        let result = loudness::analyze(&root.join(&track.path));
+                        (track, result)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await
+            else {
+                return;
+            };
+            let mut measured = Vec::new();
+            {
+                let mut failed = mount.loudness_failed.lock().unwrap();
+                for (track, result) in results {
+                    match result {
+                        Ok(loudness) => measured.push((track, loudness)),
+                        Err(err) => {
+                            tracing::debug!(?err, path = %track.path.display(), "failed to measure loudness");
+                            failed.insert(track.path.clone());
+                        }
+                    }
+                }
+            }
+            // Merged under the scan lock, so a scan that finished meanwhile
+            // is built on rather than overwritten. Album values are
+            // recomputed even when nothing new was measured.
+            let guard = mount.scan_lock.lock().await;
+            let current = mount.lib.load_full();
+            let updated = tokio::task::spawn_blocking(move || {
+                let lib = current.with_loudness(&measured)?;
+                if let Err(err) = lib.save_cached() {
+                    tracing::warn!(?err, "failed to save library cache");
+                }
+                Some(lib)
+            })
+            .await
+            .ok()
+            .flatten();
+            if let Some(lib) = updated {
+                mount.lib.store(Arc::new(lib));
+                self.bump_revision();
+            }
+            drop(guard);
+            if done {
+                return;
+            }
+        }
+    }
+
+    fn publish_scan_finished(&self, finished: Option<ServerEvent>) {
+        if let Some(summary) = finished {
+            self.events.publish(summary);
+            self.bump_revision();
+        }
+    }
+
+    /// Queues a track's rating, favorite flag and play count to be written
+    /// into its file when tag writes are enabled. The writer runs in the
+    /// background once requests have been quiet for a few seconds, so a
+    /// burst of ratings or plays rewrites each file once. Anything still
+    /// queued when the server stops is written with the track's next change.
+    pub fn schedule_tag_write(&self, path: &str) {
+        if !self.allow_tag_writes {
+            return;
+        }
+        self.pending_tag_writes
+            .lock()
+            .unwrap()
+            .insert(path.to_string());
+        self.tag_write_requests.fetch_add(1, Ordering::SeqCst);
+        if self
+            .tag_writes_running
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return;
+        }
+        let state = self.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            loop {
+                let seen = state.tag_write_requests.load(Ordering::SeqCst);
+                tokio::time::sleep(TAG_WRITE_DELAY).await;
+                if state.tag_write_requests.load(Ordering::SeqCst) == seen
+                    || started.elapsed() >= TAG_WRITE_MAX_WAIT
+                {
+                    break;
+                }
+            }
+            state.flush_tag_writes().await;
+            state.tag_writes_running.store(false, Ordering::SeqCst);
+            // Requests that came in while writing start a new round.
+            let next = state
+                .pending_tag_writes
+                .lock()
+                .unwrap()
+                .iter()
+                .next()
+                .cloned();
+            if let Some(path) = next {
+                state.schedule_tag_write(&path);
+            }
+        });
+    }
+
+    /// Writes every queued tag update now. Failures are logged; the stored
+    /// ratings and plays stay authoritative.
+    pub async fn flush_tag_writes(&self) {
+        let paths: Vec<String> = std::mem::take(&mut *self.pending_tag_writes.lock().unwrap())
+            .into_iter()
+            .collect();
+        if paths.is_empty() {
+            return;
+        }
+        let stats: HashMap<String, TagStats> = paths
+            .iter()
+            .map(|path| {
+                let rating = self.ratings.get(RatingTarget::Track, path);
+                let stats = TagStats {
+                    rating: rating.and_then(|r| r.rating),
+                    favorite: rating.is_some_and(|r| r.favorite),
+                    play_count: self.history.play_count(path),
+                };
+                (path.clone(), stats)
+            })
+            .collect();
+        let stats = Arc::new(stats);
+        let failed = self
+            .rewrite_tracks(paths, move |path, abs| {
+                tag_writer::write_stats(abs, &stats[path])
             })
             .await;
-            match result {
-                Ok(Ok(lib)) => {
-                    state.lib.store(Arc::new(lib));
+        for (path, err) in failed {
+            tracing::warn!(?err, path, "failed to write tags");
+        }
+    }
+
+    /// Applies a metadata edit to tracks given by public path and returns
+    /// the ones that could not be changed, with the reason.
+    pub async fn edit_tracks(&self, paths: Vec<String>, edit: TagEdit) -> Vec<(String, String)> {
+        let edit = Arc::new(edit);
+        self.rewrite_tracks(paths, move |_, abs| tag_writer::write_edit(abs, &edit))
+            .await
+            .into_iter()
+            .map(|(path, err)| (path, format!("{err:#}")))
+            .collect()
+    }
+
+    /// Rewrites track files under their mount's scan lock, so a scan never
+    /// reads a half-done file, then re-reads just those tracks into the
+    /// library and cache.
+    async fn rewrite_tracks<F>(&self, paths: Vec<String>, write: F) -> Vec<(String, anyhow::Error)>
+    where
+        F: Fn(&str, &Path) -> anyhow::Result<()> + Clone + Send + 'static,
+    {
+        let mut failed = Vec::new();
+        let mut changed = false;
+        for mount in self.mounts.iter() {
+            let targets: Vec<(String, PathBuf)> = paths
+                .iter()
+                .filter_map(|path| {
+                    let (m, inner) = self.resolve(path)?;
+                    (m.name == mount.name).then(|| (path.clone(), PathBuf::from(inner)))
+                })
+                .collect();
+            if targets.is_empty() {
+                continue;
+            }
+            let _guard = mount.scan_lock.lock().await;
+            let current = mount.lib.load_full();
+            let write = write.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let mut failed = Vec::new();
+                let mut written = Vec::new();
+                for (path, rel) in targets {
+                    match write(&path, &current.root().join(&rel)) {
+                        Ok(()) => written.push(rel),
+                        Err(err) => failed.push((path, err)),
+                    }
                 }
-                Ok(Err(err)) => {
-                    tracing::error!(?err, "scan failed");
+                if written.is_empty() {
+                    return (None, failed);
+                }
+                let lib = current.reread_tracks(&written);
+                if let Err(err) = lib.save_cached() {
+                    tracing::warn!(?err, "failed to save library cache");
+                }
+                (Some(lib), failed)
+            })
+            .await;
+            match result {
+                Ok((lib, mount_failed)) => {
+                    if let Some(lib) = lib {
+                        mount.lib.store(Arc::new(lib));
+                        changed = true;
+                    }
+                    failed.extend(mount_failed);
                 }
                 Err(err) => {
-                    tracing::error!("scan task join error: {}", err);
+                    tracing::error!(mount = %mount.name, "tag write task join error: {}", err)
                 }
             }
-            state.scan_ready.store(true, Ordering::SeqCst);
-            state.scan_in_progress.store(false, Ordering::SeqCst);
-        });
-        true
+        }
+        if changed {
+            self.bump_revision();
+        }
+        failed
     }
+
+    /// Marks the library as changed and tells subscribers the new revision.
+    pub fn bump_revision(&self) -> u64 {
+        let revision = self.revision.fetch_add(1, Ordering::SeqCst) + 1;
+        self.events
+            .publish(ServerEvent::LibraryChanged { revision });
+        revision
+    }
+}
+
+fn mount_tracks(mount: &Mount, inner: &str) -> Vec<TrackRef> {
+    mount
+        .lib
+        .load()
+        .collect_tracks_recursive(inner)
+        .into_iter()
+        .map(|track| TrackRef {
+            path: mount.public_path(&track.path.to_string_lossy().replace('\\', "/")),
+            track,
+        })
+        .collect()
 }
@@ -0,0 +1,219 @@
+use axum::{
+    Json, Router,
+    extract::{DefaultBodyLimit, Path as AxPath, State},
+    http::StatusCode,
+    middleware,
+    routing::patch,
+};
+
+use super::{
+    auth, helpers,
+    state::AppState,
+    types::{JsonAlbumEditResp, JsonEditFailure, JsonFolderTrack},
+};
+use crate::tag_writer::{CoverEdit, TagEdit};
+
+/// Room for an embedded cover sent as a data URL.
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+const MAX_TEXT_LEN: usize = 1000;
+const MAX_NUMBER: u32 = 9999;
+
+/// Tag editing is an admin action, so these routes sit behind the admin
+/// token even though they live under `/api`.
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/api/tracks/*path", patch(edit_track))
+        .route("/api/albums", patch(edit_album))
+        .route_layer(middleware::from_fn_with_state(state, auth::require_admin))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+}
+
+#[derive(serde::Deserialize)]
+struct TrackEditReq {
+    #[serde(flatten)]
+    edit: TagEdit,
+    /// A `data:image/...;base64,` URL, or an empty string to remove the
+    /// embedded cover.
+    cover: Option<String>,
+}
+
+/// An album is named either by its tag-derived key or by its folder.
+#[derive(serde::Deserialize)]
+struct AlbumEditReq {
+    key: Option<String>,
+    folder: Option<String>,
+    #[serde(flatten)]
+    edit: TagEdit,
+    cover: Option<String>,
+}
+
+fn bad_request() -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, String::new())
+}
+
+/// Tracks cut from a rip by a CUE sheet have no file of their own to tag.
+fn cue_conflict() -> (StatusCode, String) {
+    (
+        StatusCode::CONFLICT,
+        "track comes from a CUE sheet; edit the .cue file instead".to_string(),
+    )
+}
+
+/// Checks the edit and attaches the decoded cover.
+fn build_edit(mut edit: TagEdit, cover: Option<String>) -> Result<TagEdit, (StatusCode, String)> {
+    let texts = [
+        &edit.title,
+        &edit.artist,
+        &edit.album,
+        &edit.album_artist,
+        &edit.genre,
+    ];
+    let bad_text = texts
+        .iter()
+        .filter_map(|t| t.as_deref())
+        .any(|t| t.len() > MAX_TEXT_LEN || t.chars().any(char::is_control));
+    let numbers = [
+        edit.year,
+        edit.track_number,
+        edit.track_total,
+        edit.disc_number,
+        edit.disc_total,
+    ];
+    if bad_text || numbers.iter().flatten().any(|n| *n > MAX_NUMBER) {
+        return Err(bad_request());
+    }
+    edit.cover = match cover.as_deref() {
+        None => None,
+        Some("") => Some(CoverEdit::Remove),
+        Some(url) => {
+            let (mime, data) = decode_data_url(url).ok_or_else(bad_request)?;
+            Some(CoverEdit::Set { mime, data })
+        }
+    };
+    if edit.is_empty() {
+        return Err(bad_request());
+    }
+    Ok(edit)
+}
+
+async fn edit_track(
+    AxPath(path): AxPath<String>,
+    State(state): State<AppState>,
+    Json(req): Json<TrackEditReq>,
+) -> Result<Json<JsonFolderTrack>, (StatusCode, String)> {
+    let rel = helpers::validate_request_path(&path).map_err(|_| bad_request())?;
+    let item = state
+        .find_track(&rel)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    if item.track.cue.is_some() {
+        return Err(cue_conflict());
+    }
+    let edit = build_edit(req.edit, req.cover)?;
+    if let Some((_, error)) = state.edit_tracks(vec![item.path.clone()], edit).await.pop() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, error));
+    }
+    let item = state
+        .find_track(&item.path)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    Ok(Json(state.track_json(item)))
+}
+
+/// Applies album-wide fields to every track of an album. Per-track fields
+/// such as the title or track number are refused.
+async fn edit_album(
+    State(state): State<AppState>,
+    Json(req): Json<AlbumEditReq>,
+) -> Result<Json<JsonAlbumEditResp>, (StatusCode, String)> {
+    if req.edit.title.is_some() || req.edit.track_number.is_some() {
+        return Err(bad_request());
+    }
+    let tracks = match (req.key, req.folder) {
+        (Some(key), None) if !key.is_empty() => state
+            .collect_tracks("")
+            .into_iter()
+            .filter(|t| t.track.metadata.album_key().as_deref() == Some(key.as_str()))
+            .collect(),
+        (None, Some(folder)) => {
+            let rel = helpers::validate_request_path(&folder).map_err(|_| bad_request())?;
+            state.collect_tracks(rel.trim_end_matches('/'))
+        }
+        _ => return Err(bad_request()),
+    };
+    if tracks.is_empty() {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    if tracks.iter().any(|t| t.track.cue.is_some()) {
+        return Err(cue_conflict());
+    }
+    let edit = build_edit(req.edit, req.cover)?;
+    let paths: Vec<String> = tracks.into_iter().map(|t| t.path).collect();
+    let failed = state.edit_tracks(paths.clone(), edit).await;
+    let tracks = paths
+        .iter()
+        .filter(|path| !failed.iter().any(|(p, _)| p == *path))
+        .filter_map(|path| state.find_track(path))
+        .map(|item| state.track_json(item))
+        .collect();
+    let failed = failed
+        .into_iter()
+        .map(|(path, error)| JsonEditFailure { path, error })
+        .collect();
+    Ok(Json(JsonAlbumEditResp { tracks, failed }))
+}
+
+/// Splits a base64 `data:` URL holding an image into its MIME type and
+/// bytes.
+fn decode_data_url(url: &str) -> Option<(String, Vec<u8>)> {
+    let (header, payload) = url.strip_prefix("data:")?.split_once(',')?;
+    let mime = header.strip_suffix(";base64")?;
+    if !mime.starts_with("image/") {
+        return None;
+    }
+    let data = decode_base64(payload)?;
+    (!data.is_empty()).then(|| (mime.to_string(), data))
+}
+
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some(u32::from(c - b'A')),
+            b'a'..=b'z' => Some(u32::from(c - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(c - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+    let input = input.trim_end_matches('=').as_bytes();
+    if input.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut acc = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            acc |= value(c)? << (18 - 6 * i);
+        }
+        let bytes = acc.to_be_bytes();
+        out.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_image_data_urls() {
+        assert_eq!(
+            decode_data_url("data:image/png;base64,iVBORw=="),
+            Some(("image/png".to_string(), vec![0x89, b'P', b'N', b'G']))
+        );
+        assert_eq!(decode_base64("TWFu").as_deref(), Some(&b"Man"[..]));
+        assert_eq!(decode_base64("TWE").as_deref(), Some(&b"Ma"[..]));
+        assert_eq!(decode_data_url("data:text/plain;base64,TWFu"), None);
+        assert_eq!(decode_data_url("data:image/png,raw"), None);
+        assert_eq!(decode_base64("T!=="), None);
+    }
+}
@@ -1,5 +1,11 @@
 use serde::Serialize;
 
+use crate::chapters::Chapter;
+use crate::library::{Track, TrackRef};
+use crate::lyrics::{LyricLine, LyricsKind};
+use crate::ratings::Rating;
+use crate::scan_report::ScanReport;
+
 #[derive(Serialize)]
 pub struct JsonFolderAlbum {
     pub name: String,
@@ -17,6 +23,133 @@ pub struct JsonFolderTrack {
     pub album: Option<String>,
     pub duration: Option<f64>,
     pub artwork_url: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    /// Best lyrics available from tags or a sidecar file.
+    pub lyrics: Option<LyricsKind>,
+    /// Set for tracks cut from a single-file rip by a CUE sheet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cue: Option<JsonCueSpan>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Chapter>,
+    /// Players should resume this track where it was left off.
+    pub long_form: bool,
+    /// Unix time when a scan first found the track.
+    pub first_seen: Option<u64>,
+    /// Gains for volume normalization, from tags or loudness analysis.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_gain: Option<JsonReplayGain>,
+    /// Tag-derived album identity used for album ratings.
+    pub album_key: Option<String>,
+    pub favorite: bool,
+    pub rating: Option<u8>,
+    pub album_favorite: bool,
+    pub album_rating: Option<u8>,
+}
+
+impl JsonFolderTrack {
+    pub fn from_track(base: &str, item: TrackRef) -> Self {
+        let track = &item.track;
+        let file_name = track
+            .path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("")
+            .to_string();
+        let metadata = &track.metadata;
+        let display_name = metadata.title.clone().unwrap_or_else(|| file_name.clone());
+        let encoded = crate::playlist::encode_path(&item.path);
+        let base_trimmed = base.trim_end_matches('/');
+        let artwork_url = metadata
+            .artwork_id
+            .as_ref()
+            .map(|id| format!("{base_trimmed}/api/artwork/{id}"));
+        let cue = track.cue.as_ref().map(|span| JsonCueSpan {
+            file: item.sibling_path(&span.source),
+            sheet: item.sibling_path(&span.sheet),
+            start: span.start,
+            end: span.end,
+        });
+        JsonFolderTrack {
+            name: file_name,
+            display_name,
+            relative_path: item.path,
+            url: format!("{base}{encoded}"),
+            title: metadata.title.clone(),
+            artist: metadata.artist.clone(),
+            album: metadata.album.clone(),
+            duration: metadata.duration,
+            artwork_url,
+            genre: metadata.genre.clone(),
+            year: metadata.year,
+            album_artist: metadata.album_artist.clone(),
+            track_number: metadata.track_number,
+            disc_number: metadata.disc_number,
+            // Caches from before sidecar kinds were recorded only say
+            // that there is a file.
+            lyrics: metadata
+                .lyrics_file
+                .as_ref()
+                .map(|_| metadata.lyrics_file_kind.unwrap_or(LyricsKind::Plain))
+                .max(metadata.lyrics),
+            cue,
+            chapters: metadata.chapters.clone(),
+            long_form: false,
+            first_seen: track.first_seen,
+            replay_gain: JsonReplayGain::from_track(track),
+            album_key: metadata.album_key(),
+            favorite: false,
+            rating: None,
+            album_favorite: false,
+            album_rating: None,
+        }
+    }
+}
+
+/// ReplayGain 2.0 values: gains in dB to reach -18 LUFS and linear peaks.
+/// Tag values win over measured ones field by field.
+#[derive(Serialize)]
+pub struct JsonReplayGain {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+    /// Measured integrated loudness in LUFS, when analysed.
+    pub loudness: Option<f64>,
+    /// Where the track gain came from: `tags` or `analysis`.
+    pub source: &'static str,
+}
+
+impl JsonReplayGain {
+    fn from_track(track: &Track) -> Option<Self> {
+        let tags = track.metadata.replay_gain.unwrap_or_default();
+        let (measured, album) = (track.loudness, track.album_loudness);
+        let json = JsonReplayGain {
+            track_gain: tags.track_gain.or(measured.map(|l| l.gain())),
+            track_peak: tags.track_peak.or(measured.map(|l| l.peak)),
+            album_gain: tags.album_gain.or(album.map(|l| l.gain())),
+            album_peak: tags.album_peak.or(album.map(|l| l.peak)),
+            loudness: measured.map(|l| l.integrated),
+            source: if tags.track_gain.is_none() && measured.is_some() {
+                "analysis"
+            } else {
+                "tags"
+            },
+        };
+        (json.track_gain.is_some() || json.album_gain.is_some()).then_some(json)
+    }
+}
+
+#[derive(Serialize)]
+pub struct JsonCueSpan {
+    /// Public path of the rip the track is cut from.
+    pub file: String,
+    pub sheet: String,
+    pub start: f64,
+    pub end: Option<f64>,
 }
 
 #[derive(Serialize)]
@@ -27,4 +160,193 @@ pub struct JsonFolderResp {
     pub albums: Vec<JsonFolderAlbum>,
     pub tracks: Vec<JsonFolderTrack>,
     pub scanning: bool,
+    pub long_form: bool,
+    pub revision: u64,
+}
+
+#[derive(Serialize)]
+pub struct JsonMountScanStatus {
+    pub name: String,
+    pub scanning: bool,
+    pub last_scan: Option<ScanReport>,
+}
+
+#[derive(Serialize)]
+pub struct JsonScanStatusResp {
+    pub mounts: Vec<JsonMountScanStatus>,
+}
+
+#[derive(Serialize)]
+pub struct JsonAdminResp {
+    /// `scheduled`, `queued` or `busy`.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JsonPlaylistSummary {
+    pub id: String,
+    pub name: String,
+    pub track_count: usize,
+    pub missing_count: usize,
+    pub updated_at: Option<u64>,
+    pub m3u8: String,
+    /// Playlists imported from files in the library cannot be edited.
+    pub read_only: bool,
+    /// Library path of the playlist file, for imported playlists.
+    pub source: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JsonPlaylistResp {
+    pub id: String,
+    pub name: String,
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+    pub m3u8: String,
+    pub read_only: bool,
+    pub source: Option<String>,
+    pub tracks: Vec<JsonFolderTrack>,
+    /// Entries whose file is not in the library right now.
+    pub missing: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct JsonPlayEvent {
+    pub path: String,
+    pub at: u64,
+    pub played: Option<f64>,
+    pub client: Option<String>,
+    /// Missing when the track is no longer in the library.
+    pub track: Option<JsonFolderTrack>,
+}
+
+#[derive(Serialize)]
+pub struct JsonHistoryResp {
+    pub events: Vec<JsonPlayEvent>,
+}
+
+#[derive(Serialize)]
+pub struct JsonNowPlaying {
+    pub client: String,
+    pub path: String,
+    pub started_at: u64,
+    pub track: Option<JsonFolderTrack>,
+}
+
+#[derive(Serialize)]
+pub struct JsonTopTrack {
+    pub path: String,
+    pub plays: u64,
+    pub last_played: u64,
+    pub track: Option<JsonFolderTrack>,
+}
+
+#[derive(Serialize)]
+pub struct JsonTopArtist {
+    pub artist: String,
+    pub plays: u64,
+    /// Distinct tracks by the artist played in the window.
+    pub tracks: usize,
+    pub last_played: u64,
+}
+
+#[derive(Serialize)]
+pub struct JsonTopResp<T> {
+    pub since: u64,
+    pub until: u64,
+    pub items: Vec<T>,
+}
+
+#[derive(Serialize)]
+pub struct JsonRatingEntry {
+    /// Track path or album key.
+    pub key: String,
+    #[serde(flatten)]
+    pub rating: Rating,
+}
+
+#[derive(Serialize)]
+pub struct JsonRatingsResp {
+    pub tracks: Vec<JsonRatingEntry>,
+    pub albums: Vec<JsonRatingEntry>,
+}
+
+#[derive(Serialize)]
+pub struct JsonEditFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct JsonAlbumEditResp {
+    pub tracks: Vec<JsonFolderTrack>,
+    pub failed: Vec<JsonEditFailure>,
+}
+
+#[derive(Serialize)]
+pub struct JsonUploadResp {
+    pub path: String,
+    pub size: u64,
+    /// State of the rescan picking up the new file.
+    pub status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct JsonLyricsResp {
+    pub path: String,
+    /// `file` for a sidecar `.lrc`, `embedded` for tags.
+    pub source: &'static str,
+    pub lines: Vec<LyricLine>,
+}
+
+#[derive(Serialize)]
+pub struct JsonResumeEntry {
+    pub path: String,
+    pub position: f64,
+    pub updated_at: u64,
+    /// `None` when the track is no longer in the library.
+    pub track: Option<JsonFolderTrack>,
+}
+
+#[derive(Serialize)]
+pub struct JsonResumeResp {
+    pub client: String,
+    pub entries: Vec<JsonResumeEntry>,
+}
+
+#[derive(Serialize)]
+pub struct JsonRecentGroup {
+    /// `album:<album key>` or `folder:<path>`.
+    pub key: String,
+    pub title: String,
+    pub artist: Option<String>,
+    /// Folder of the group's first track.
+    pub folder: String,
+    /// Newest first-seen time among the tracks.
+    pub added_at: u64,
+    pub artwork_url: Option<String>,
+    pub tracks: Vec<JsonFolderTrack>,
+}
+
+#[derive(Serialize)]
+pub struct JsonRecentResp {
+    pub days: u64,
+    pub since: u64,
+    pub groups: Vec<JsonRecentGroup>,
+}
+
+#[derive(Serialize)]
+pub struct JsonWaveform {
+    pub points: usize,
+    pub duration: f64,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+#[derive(Serialize)]
+pub struct JsonRandomResp {
+    pub mode: &'static str,
+    pub tracks: Vec<JsonFolderTrack>,
 }
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path as AxPath, Query, State},
+    http::{HeaderMap, StatusCode, header},
+};
+use tokio::io::AsyncWriteExt;
+use tokio_stream::StreamExt;
+
+use super::{helpers, state::AppState, types::JsonUploadResp};
+use crate::{library, path_utils};
+
+#[derive(serde::Deserialize)]
+pub struct UploadQuery {
+    /// `1` replaces an existing file instead of refusing with 409.
+    overwrite: Option<String>,
+}
+
+fn error(status: StatusCode) -> (StatusCode, String) {
+    (status, String::new())
+}
+
+/// Streams the request body into a new track file at the given library
+/// path, creating folders as needed, then queues a rescan of its folder.
+pub async fn admin_upload(
+    AxPath(path): AxPath<String>,
+    Query(q): Query<UploadQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, Json<JsonUploadResp>), (StatusCode, String)> {
+    let rel = helpers::validate_request_path(&path).map_err(|_| error(StatusCode::BAD_REQUEST))?;
+    if rel.ends_with('/') {
+        return Err(error(StatusCode::BAD_REQUEST));
+    }
+    if !library::is_audio_path(Path::new(&rel)) {
+        return Err(error(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+    }
+    let (mount, inner) = state
+        .resolve(&rel)
+        .ok_or_else(|| error(StatusCode::NOT_FOUND))?;
+    let inner = inner.trim_start_matches('/').to_string();
+    let (folder, file_name) = match inner.rsplit_once('/') {
+        Some((folder, name)) => (folder.trim_end_matches('/').to_string(), name.to_string()),
+        None => (String::new(), inner.clone()),
+    };
+    if file_name.is_empty() {
+        return Err(error(StatusCode::BAD_REQUEST));
+    }
+    let overwrite = q.overwrite.as_deref() == Some("1");
+    let limit = state.max_upload_bytes;
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > limit) {
+        return Err(error(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    let real_dir = create_folder(&mount.root, &folder).await.map_err(error)?;
+    let target = real_dir.join(&file_name);
+    if !overwrite && tokio::fs::symlink_metadata(&target).await.is_ok() {
+        return Err(error(StatusCode::CONFLICT));
+    }
+
+    let tmp = path_utils::temp_sibling(&target, "upload.tmp");
+    let size = match write_body(&tmp, body, limit).await {
+        Ok(size) => size,
+        Err(status) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(error(status));
+        }
+    };
+    if !overwrite && tokio::fs::symlink_metadata(&target).await.is_ok() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(error(StatusCode::CONFLICT));
+    }
+    if let Err(err) = tokio::fs::rename(&tmp, &target).await {
+        tracing::error!(?err, path = %target.display(), "failed to move upload into place");
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(error(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+    tracing::info!(path = %rel, size, "uploaded track");
+
+    // Queued rather than started, so an upload during a running scan is
+    // still picked up once that scan is done.
+    state.schedule_folder_scan(mount, folder);
+    Ok((
+        StatusCode::CREATED,
+        Json(JsonUploadResp {
+            path: rel,
+            size,
+            status: "queued",
+        }),
+    ))
+}
+
+/// Creates `folder` under `root` and returns its real path. A symlinked
+/// folder must not let an upload land outside the root, so the deepest
+/// folder that already exists is resolved and checked before anything is
+/// created, and the result is checked again afterwards.
+async fn create_folder(root: &Path, folder: &str) -> Result<PathBuf, StatusCode> {
+    let real_root = tokio::fs::canonicalize(root)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut existing = root.join(folder);
+    let mut missing = Vec::new();
+    while tokio::fs::metadata(&existing).await.is_err() {
+        let Some(name) = existing.file_name() else {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        };
+        missing.push(name.to_os_string());
+        existing.pop();
+    }
+    let mut dir = tokio::fs::canonicalize(&existing)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !dir.starts_with(&real_root) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if missing.is_empty() {
+        return Ok(dir);
+    }
+    dir.extend(missing.iter().rev());
+    tokio::fs::create_dir_all(&dir).await.map_err(|err| {
+        tracing::error!(?err, dir = %dir.display(), "failed to create upload folder");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let real_dir = tokio::fs::canonicalize(&dir)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !real_dir.starts_with(&real_root) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(real_dir)
+}
+
+/// Copies the body to `tmp`, stopping as soon as it grows past `limit`.
+async fn write_body(tmp: &Path, body: Body, limit: u64) -> Result<u64, StatusCode> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(tmp)
+        .await
+        .map_err(|err| {
+            tracing::error!(?err, path = %tmp.display(), "failed to create upload file");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let mut stream = body.into_data_stream();
+    let mut size: u64 = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        size += chunk.len() as u64;
+        if size > limit {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        file.write_all(&chunk)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    if size == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    file.sync_all()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(size)
+}
@@ -0,0 +1,73 @@
+use axum::{
+    Json, Router,
+    extract::{Path as AxPath, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+
+use super::{helpers, state::AppState, types::JsonWaveform};
+use crate::waveform::{self, CACHE_POINTS, WAVEFORM_DIR};
+
+const DEFAULT_POINTS: usize = 800;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/waveform/*path", get(track_waveform))
+}
+
+#[derive(serde::Deserialize)]
+struct WaveformQuery {
+    points: Option<usize>,
+    /// `bin` for pairs of signed bytes instead of JSON.
+    format: Option<String>,
+}
+
+/// Peak data for drawing a track's waveform. The first request decodes
+/// the track; later ones are served from the cache in the data directory.
+async fn track_waveform(
+    AxPath(path): AxPath<String>,
+    Query(q): Query<WaveformQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, String)> {
+    let binary = match q.format.as_deref() {
+        None | Some("json") => false,
+        Some("bin") => true,
+        Some(_) => return Err((StatusCode::BAD_REQUEST, String::new())),
+    };
+    let rel = helpers::validate_request_path(&path)
+        .map_err(|_| (StatusCode::NOT_FOUND, String::new()))?;
+    let item = state
+        .find_track(&rel)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    let (mount, _) = state
+        .resolve(&item.path)
+        .ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    // Tracks from a CUE sheet are drawn from their part of the rip.
+    let (file, span) = match &item.track.cue {
+        Some(span) => (mount.root.join(&span.source), Some((span.start, span.end))),
+        None => (mount.root.join(&item.track.path), None),
+    };
+    let dir = state.data_dir.join(WAVEFORM_DIR);
+    let wave = tokio::task::spawn_blocking(move || waveform::cached(&dir, &file, span))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new()))?
+        .map_err(|err| {
+            tracing::debug!(?err, path = %item.path, "failed to draw waveform");
+            (StatusCode::UNPROCESSABLE_ENTITY, String::new())
+        })?
+        .downsample(q.points.unwrap_or(DEFAULT_POINTS).clamp(1, CACHE_POINTS));
+    if binary {
+        return Ok((
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            wave.to_bytes(),
+        )
+            .into_response());
+    }
+    Ok(Json(JsonWaveform {
+        points: wave.min.len(),
+        duration: wave.duration,
+        min: wave.min,
+        max: wave.max,
+    })
+    .into_response())
+}
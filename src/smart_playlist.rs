@@ -0,0 +1,416 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::library::TrackRef;
+use crate::playlist_store::{MAX_NAME_LEN, StoredItem};
+
+const SECS_PER_DAY: u64 = 86_400;
+/// Longest text value, in bytes, a rule may compare against.
+const MAX_RULE_TEXT_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Duration,
+    /// Library path, mount prefix included.
+    Path,
+    /// Lower-case file extension, e.g. `flac`.
+    Format,
+    Size,
+    /// File modification time.
+    Modified,
+    /// When a scan first found the track.
+    Added,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Eq,
+    Ne,
+    Contains,
+    NotContains,
+    StartsWith,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    WithinDays,
+    NotWithinDays,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    #[default]
+    All,
+    Any,
+}
+
+/// One rule: either a single condition or a nested group of rules.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Rule {
+    Group {
+        #[serde(rename = "match", default)]
+        mode: MatchMode,
+        rules: Vec<Rule>,
+    },
+    Condition {
+        field: Field,
+        op: Op,
+        value: serde_json::Value,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Duration,
+    Path,
+    Format,
+    Size,
+    Modified,
+    Added,
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sort {
+    pub by: SortKey,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+/// The user-editable part of a smart playlist.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmartPlaylistDef {
+    pub name: String,
+    #[serde(rename = "match", default)]
+    pub mode: MatchMode,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    pub sort: Option<Sort>,
+    pub limit: Option<usize>,
+}
+
+/// A playlist whose tracks are whatever currently matches its rules.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmartPlaylist {
+    pub id: String,
+    #[serde(flatten)]
+    pub def: SmartPlaylistDef,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl StoredItem for SmartPlaylist {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn touch(&mut self, now: u64) {
+        self.updated_at = now;
+    }
+}
+
+enum FieldValue {
+    Text(Option<String>),
+    Number(Option<f64>),
+}
+
+fn field_value(track: &TrackRef, field: Field) -> FieldValue {
+    let meta = &track.track.metadata;
+    match field {
+        Field::Title => FieldValue::Text(meta.title.clone()),
+        Field::Artist => FieldValue::Text(meta.artist.clone()),
+        Field::Album => FieldValue::Text(meta.album.clone()),
+        Field::Genre => FieldValue::Text(meta.genre.clone()),
+        Field::Path => FieldValue::Text(Some(track.path.clone())),
+        Field::Format => FieldValue::Text(
+            track
+                .track
+                .path
+                .extension()
+                .map(|e| e.to_string_lossy().to_ascii_lowercase()),
+        ),
+        Field::Year => FieldValue::Number(meta.year.map(f64::from)),
+        Field::Duration => FieldValue::Number(meta.duration),
+        Field::Size => FieldValue::Number(track.track.size.map(|s| s as f64)),
+        Field::Modified => FieldValue::Number(track.track.modified.map(|s| s as f64)),
+        Field::Added => FieldValue::Number(track.track.first_seen.map(|s| s as f64)),
+    }
+}
+
+fn is_numeric(field: Field) -> bool {
+    matches!(
+        field,
+        Field::Year | Field::Duration | Field::Size | Field::Modified | Field::Added
+    )
+}
+
+impl Rule {
+    /// Checks that every condition's value suits its field and operator.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Rule::Group { rules, .. } => rules.iter().try_for_each(Rule::validate),
+            Rule::Condition { field, op, value } => {
+                let text_op = matches!(op, Op::Contains | Op::NotContains | Op::StartsWith);
+                let day_op = matches!(op, Op::WithinDays | Op::NotWithinDays);
+                let time_field = matches!(field, Field::Modified | Field::Added);
+                if day_op && (!time_field || !value.is_number()) {
+                    return Err(format!(
+                        "{op:?} needs the modified or added field and a number of days"
+                    ));
+                }
+                if is_numeric(*field) && (text_op || !value.is_number()) {
+                    return Err(format!("{field:?} needs a numeric comparison"));
+                }
+                if !is_numeric(*field) && !value.is_string() {
+                    return Err(format!("{field:?} needs a text value"));
+                }
+                if value.as_str().is_some_and(|v| v.len() > MAX_RULE_TEXT_LEN) {
+                    return Err(format!("{field:?} value is too long"));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn matches(&self, track: &TrackRef, now: u64) -> bool {
+        match self {
+            Rule::Group { mode, rules } => matches_all(*mode, rules, track, now),
+            Rule::Condition { field, op, value } => match field_value(track, *field) {
+                FieldValue::Text(text) => match_text(text.as_deref(), *op, value),
+                FieldValue::Number(number) => match_number(number, *op, value, now),
+            },
+        }
+    }
+}
+
+fn matches_all(mode: MatchMode, rules: &[Rule], track: &TrackRef, now: u64) -> bool {
+    match mode {
+        MatchMode::All => rules.iter().all(|r| r.matches(track, now)),
+        MatchMode::Any => rules.is_empty() || rules.iter().any(|r| r.matches(track, now)),
+    }
+}
+
+fn match_text(actual: Option<&str>, op: Op, value: &serde_json::Value) -> bool {
+    let expected = value.as_str().unwrap_or_default().to_lowercase();
+    let Some(actual) = actual.map(str::to_lowercase) else {
+        return matches!(op, Op::Ne | Op::NotContains);
+    };
+    match op {
+        Op::Eq => actual == expected,
+        Op::Ne => actual != expected,
+        Op::Contains => actual.contains(&expected),
+        Op::NotContains => !actual.contains(&expected),
+        Op::StartsWith => actual.starts_with(&expected),
+        Op::Lt => actual < expected,
+        Op::Le => actual <= expected,
+        Op::Gt => actual > expected,
+        Op::Ge => actual >= expected,
+        Op::WithinDays | Op::NotWithinDays => false,
+    }
+}
+
+fn match_number(actual: Option<f64>, op: Op, value: &serde_json::Value, now: u64) -> bool {
+    let expected = value.as_f64().unwrap_or_default();
+    let Some(actual) = actual else {
+        return op == Op::Ne;
+    };
+    match op {
+        Op::Eq => actual == expected,
+        Op::Ne => actual != expected,
+        Op::Lt => actual < expected,
+        Op::Le => actual <= expected,
+        Op::Gt => actual > expected,
+        Op::Ge => actual >= expected,
+        Op::WithinDays | Op::NotWithinDays => {
+            let cutoff = now.saturating_sub((expected.max(0.0) as u64) * SECS_PER_DAY) as f64;
+            (actual >= cutoff) == (op == Op::WithinDays)
+        }
+        Op::Contains | Op::NotContains | Op::StartsWith => false,
+    }
+}
+
+impl SmartPlaylistDef {
+    pub fn validate(&self) -> Result<(), String> {
+        let name = self.name.trim();
+        if name.is_empty() || name.len() > MAX_NAME_LEN || name.contains('\0') {
+            return Err(String::from("invalid name"));
+        }
+        self.rules.iter().try_for_each(Rule::validate)
+    }
+
+    /// Picks, orders and trims the matching tracks.
+    pub fn evaluate(&self, tracks: Vec<TrackRef>, now: u64) -> Vec<TrackRef> {
+        let mut out: Vec<TrackRef> = tracks
+            .into_iter()
+            .filter(|t| matches_all(self.mode, &self.rules, t, now))
+            .collect();
+        if let Some(sort) = &self.sort {
+            sort_tracks(&mut out, sort);
+        }
+        if let Some(limit) = self.limit {
+            out.truncate(limit);
+        }
+        out
+    }
+}
+
+fn sort_tracks(tracks: &mut [TrackRef], sort: &Sort) {
+    let field = match sort.by {
+        SortKey::Random => {
+            use rand::seq::SliceRandom;
+            tracks.shuffle(&mut rand::thread_rng());
+            return;
+        }
+        SortKey::Title => Field::Title,
+        SortKey::Artist => Field::Artist,
+        SortKey::Album => Field::Album,
+        SortKey::Genre => Field::Genre,
+        SortKey::Year => Field::Year,
+        SortKey::Duration => Field::Duration,
+        SortKey::Path => Field::Path,
+        SortKey::Format => Field::Format,
+        SortKey::Size => Field::Size,
+        SortKey::Modified => Field::Modified,
+        SortKey::Added => Field::Added,
+    };
+    // Tracks without a value always go last.
+    tracks.sort_by(
+        |a, b| match (field_value(a, field), field_value(b, field)) {
+            (FieldValue::Text(Some(x)), FieldValue::Text(Some(y))) => {
+                order(x.to_lowercase().cmp(&y.to_lowercase()), sort.order)
+            }
+            (FieldValue::Number(Some(x)), FieldValue::Number(Some(y))) => {
+                order(x.partial_cmp(&y).unwrap_or(Ordering::Equal), sort.order)
+            }
+            (FieldValue::Text(x), FieldValue::Text(y)) => y.is_some().cmp(&x.is_some()),
+            (FieldValue::Number(x), FieldValue::Number(y)) => y.is_some().cmp(&x.is_some()),
+            _ => Ordering::Equal,
+        },
+    );
+}
+
+fn order(ord: Ordering, order: SortOrder) -> Ordering {
+    match order {
+        SortOrder::Asc => ord,
+        SortOrder::Desc => ord.reverse(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Track, TrackMetadata};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn track(path: &str, genre: &str, year: Option<u32>, modified: u64) -> TrackRef {
+        TrackRef {
+            path: path.to_string(),
+            track: Arc::new(Track {
+                path: PathBuf::from(path),
+                size: Some(1),
+                modified: Some(modified),
+                metadata: TrackMetadata {
+                    genre: Some(genre.to_string()),
+                    year,
+                    ..TrackMetadata::default()
+                },
+                cue: None,
+                first_seen: None,
+                loudness: None,
+                album_loudness: None,
+            }),
+        }
+    }
+
+    fn def(json: serde_json::Value) -> SmartPlaylistDef {
+        let def: SmartPlaylistDef = serde_json::from_value(json).unwrap();
+        def.validate().unwrap();
+        def
+    }
+
+    #[test]
+    fn evaluates_rules_sort_and_limit() {
+        let now = 100 * SECS_PER_DAY;
+        let tracks = vec![
+            track("a.flac", "Jazz", Some(1959), now),
+            track("b.mp3", "jazz", Some(1965), now),
+            track("c.flac", "Jazz", Some(1980), now),
+            track("d.flac", "Rock", Some(1960), now - 40 * SECS_PER_DAY),
+            track("e.flac", "Jazz", None, now),
+        ];
+        let old_jazz = def(serde_json::json!({
+            "name": "Old jazz",
+            "rules": [
+                {"field": "genre", "op": "eq", "value": "JAZZ"},
+                {"field": "year", "op": "lt", "value": 1970}
+            ],
+            "sort": {"by": "year", "order": "desc"}
+        }));
+        let paths: Vec<_> = old_jazz
+            .evaluate(tracks.clone(), now)
+            .into_iter()
+            .map(|t| t.path)
+            .collect();
+        assert_eq!(paths, vec!["b.mp3", "a.flac"]);
+
+        let recent_flac = def(serde_json::json!({
+            "name": "New FLAC",
+            "rules": [
+                {"field": "modified", "op": "within_days", "value": 30},
+                {"match": "any", "rules": [
+                    {"field": "format", "op": "eq", "value": "flac"},
+                    {"field": "genre", "op": "eq", "value": "rock"}
+                ]}
+            ],
+            "sort": {"by": "year"},
+            "limit": 2
+        }));
+        let paths: Vec<_> = recent_flac
+            .evaluate(tracks, now)
+            .into_iter()
+            .map(|t| t.path)
+            .collect();
+        assert_eq!(paths, vec!["a.flac", "c.flac"]);
+    }
+
+    #[test]
+    fn rejects_mismatched_values() {
+        let bad: SmartPlaylistDef = serde_json::from_value(serde_json::json!({
+            "name": "x",
+            "rules": [{"field": "year", "op": "contains", "value": "19"}]
+        }))
+        .unwrap();
+        assert!(bad.validate().is_err());
+        let bad: SmartPlaylistDef = serde_json::from_value(serde_json::json!({
+            "name": "x",
+            "rules": [{"field": "genre", "op": "within_days", "value": 3}]
+        }))
+        .unwrap();
+        assert!(bad.validate().is_err());
+    }
+}
@@ -2,6 +2,7 @@ const API_BASE = '/api';
 const audio = document.getElementById('audio');
 const playPauseBtn = document.getElementById('play-pause-btn');
 const progressFill = document.getElementById('progress-fill');
+const waveformCanvas = document.getElementById('waveform');
 const PLAY_ICON = '<svg width="24" height="24" viewBox="0 0 24 24" fill="currentColor"><path d="M8 5v14l11-7z"/></svg>';
 const PAUSE_ICON = '<svg width="24" height="24" viewBox="0 0 24 24" fill="currentColor"><path d="M6 19h4V5H6v14zm8-14v14h4V5h-4z"/></svg>';
 const shuffleBtn = document.getElementById('shuffle-btn');
@@ -27,6 +28,9 @@ let playQueue = [];
 let queueIndex = -1;
 let isShuffleEnabled = false;
 let scanPollTimer = null;
+let libraryRevision = 0;
+let playReport = null;
+let resumeState = null;
 
 function cloneTrack(track) {
     if (!track) {
@@ -42,9 +46,38 @@ function cloneTrack(track) {
         album: track.album || null,
         duration: typeof track.duration === 'number' ? track.duration : null,
         artwork_url: track.artwork_url || null,
+        long_form: Boolean(track.long_form),
+        album_key: track.album_key || null,
+        replay_gain: track.replay_gain || null,
     };
 }
 
+// Lowers the volume of loud tracks to the ReplayGain reference level. The
+// album gain is used while neighbouring queue entries are from the same
+// album, so quiet songs stay quiet within it. Browsers cannot raise the
+// volume past 1, so quiet tracks play as they are.
+function applyReplayGain(track) {
+    const gain = track && track.replay_gain;
+    if (!gain) {
+        audio.volume = 1;
+        return;
+    }
+    const sameAlbum = (other) => other && track.album_key && other.album_key === track.album_key;
+    const inAlbum = sameAlbum(playQueue[queueIndex - 1]) || sameAlbum(playQueue[queueIndex + 1]);
+    const useAlbum = inAlbum && typeof gain.album_gain === 'number';
+    const db = useAlbum ? gain.album_gain : gain.track_gain;
+    const peak = useAlbum ? gain.album_peak : gain.track_peak;
+    if (typeof db !== 'number') {
+        audio.volume = 1;
+        return;
+    }
+    let scale = Math.pow(10, db / 20);
+    if (typeof peak === 'number' && peak > 0) {
+        scale = Math.min(scale, 1 / peak);
+    }
+    audio.volume = Math.max(0, Math.min(1, scale));
+}
+
 function clearQueue() {
     playQueue = [];
     queueIndex = -1;
@@ -55,6 +88,7 @@ function clearQueue() {
     setPlayPauseVisual(false);
     currentTrackIndex = -1;
     updatePlayerInfo(null);
+    loadWaveform(null);
     updateTrackHighlight();
     updateShuffleButton();
 }
@@ -84,6 +118,8 @@ function playQueueTrack(index, autoplay = true) {
     if (audio.src !== track.url) {
         audio.src = track.url;
     }
+    applyReplayGain(track);
+    loadWaveform(track);
     if (autoplay) {
         audio.play().catch((error) => {
             console.error('Playback error:', error);
@@ -144,6 +180,31 @@ setPlayPauseVisual(false);
 updatePlayerInfo(null);
 updateShuffleButton();
 loadFolder();
+subscribeToEvents();
+
+function subscribeToEvents() {
+    if (typeof EventSource === 'undefined') {
+        return;
+    }
+    const source = new EventSource(`${API_BASE}/events`);
+    source.addEventListener('scan_progress', (event) => {
+        if (!scanPollTimer) {
+            return;
+        }
+        const data = JSON.parse(event.data);
+        const label = playlistContentEl.querySelector('.loading span');
+        if (label) {
+            label.textContent = `Scanning music library... ${data.files_parsed} tracks`;
+        }
+    });
+    source.addEventListener('library_changed', (event) => {
+        const data = JSON.parse(event.data);
+        if (data.revision === libraryRevision) {
+            return;
+        }
+        loadFolder(currentPath);
+    });
+}
 
 async function loadFolder(path = '') {
     try {
@@ -170,6 +231,7 @@ async function loadFolder(path = '') {
             return;
         }
         currentPath = data.path;
+        libraryRevision = data.revision || 0;
         currentAlbums = data.albums || [];
         currentM3U8 = data.m3u8 || '';
         breadcrumbEl.textContent = currentPath || 'home';
@@ -321,8 +383,11 @@ function updatePlaylistContent() {
           ${escapeHtml(track.album || '')}
         </div>
 
-        <!-- Col 4: Duration -->
+        <!-- Col 4: Favorite + Duration -->
         <div class="track-col duration-col">
+          <button class="favorite-btn${track.favorite ? ' active' : ''}"
+                  aria-label="${track.favorite ? 'Remove from favorites' : 'Add to favorites'}"
+                  onclick="toggleFavorite(event, ${track.playlistIndex})">${track.favorite ? '&#9829;' : '&#9825;'}</button>
           ${formatDuration(track.duration || 0)}
         </div>
       </div>
@@ -340,6 +405,30 @@ function updatePlaylistContent() {
 }
 
 
+async function toggleFavorite(event, playlistIndex) {
+    event.stopPropagation();
+    const track = currentPlaylist[playlistIndex];
+    if (!track) {
+        return;
+    }
+    try {
+        const response = await fetch(`${API_BASE}/ratings/track`, {
+            method: 'PUT',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ path: track.relative_path, favorite: !track.favorite }),
+        });
+        if (!response.ok) {
+            throw new Error(`HTTP ${response.status}`);
+        }
+        const updated = await response.json();
+        track.favorite = updated.favorite;
+        currentDisplayTracks = computeDisplayTracks(currentPlaylist);
+        updatePlaylistContent();
+    } catch (error) {
+        console.error('Favorite update failed:', error);
+    }
+}
+
 function formatDuration(seconds) {
     const m = Math.floor(seconds / 60);
     const s = Math.floor(seconds % 60).toString().padStart(2, '0');
@@ -479,10 +568,85 @@ function updateProgress() {
     }
     if (!audio.duration || Number.isNaN(audio.duration)) {
         progressFill.style.width = '0%';
+        drawWaveform(0);
         return;
     }
     const percentage = (audio.currentTime / audio.duration) * 100;
     progressFill.style.width = `${percentage}%`;
+    drawWaveform(audio.currentTime / audio.duration);
+}
+
+// Peaks for the playing track, drawn in place of the plain seek bar. The
+// bar comes back if the server can't decode the track.
+let waveform = null;
+let waveformPath = null;
+
+function showWaveform(visible) {
+    if (!waveformCanvas) {
+        return;
+    }
+    waveformCanvas.hidden = !visible;
+    waveformCanvas.parentElement.classList.toggle('has-waveform', visible);
+}
+
+async function loadWaveform(track) {
+    if (!waveformCanvas) {
+        return;
+    }
+    const path = track && track.relative_path;
+    if (path === waveformPath) {
+        return;
+    }
+    waveformPath = path;
+    waveform = null;
+    showWaveform(false);
+    if (!path) {
+        return;
+    }
+    const points = Math.max(1, Math.round(waveformCanvas.parentElement.clientWidth / 3));
+    const encoded = path.split('/').map(encodeURIComponent).join('/');
+    try {
+        const response = await fetch(`${API_BASE}/waveform/${encoded}?points=${points}`);
+        if (!response.ok) {
+            return;
+        }
+        const data = await response.json();
+        if (waveformPath !== path) {
+            return;
+        }
+        waveform = data;
+        showWaveform(true);
+        updateProgress();
+    } catch (error) {
+        console.error('Waveform error:', error);
+    }
+}
+
+function drawWaveform(played) {
+    if (!waveformCanvas || !waveform || waveformCanvas.hidden) {
+        return;
+    }
+    const ratio = window.devicePixelRatio || 1;
+    const width = Math.round(waveformCanvas.clientWidth * ratio);
+    const height = Math.round(waveformCanvas.clientHeight * ratio);
+    if (waveformCanvas.width !== width || waveformCanvas.height !== height) {
+        waveformCanvas.width = width;
+        waveformCanvas.height = height;
+    }
+    const ctx = waveformCanvas.getContext('2d');
+    const styles = getComputedStyle(document.documentElement);
+    const playedColor = styles.getPropertyValue('--accent-primary').trim();
+    const restColor = styles.getPropertyValue('--bg-active').trim();
+    ctx.clearRect(0, 0, width, height);
+    const count = waveform.min.length;
+    const bar = width / count;
+    const mid = height / 2;
+    for (let i = 0; i < count; i += 1) {
+        const top = mid - Math.max(waveform.max[i], 0) * mid;
+        const bottom = mid - Math.min(waveform.min[i], 0) * mid;
+        ctx.fillStyle = (i + 0.5) / count <= played ? playedColor : restColor;
+        ctx.fillRect(i * bar, top, Math.max(bar - ratio, 1), Math.max(bottom - top, ratio));
+    }
 }
 
 function setPlayPauseVisual(isPlaying) {
@@ -493,33 +657,178 @@ function setPlayPauseVisual(isPlaying) {
     playPauseBtn.setAttribute('aria-label', isPlaying ? 'Pause' : 'Play');
 }
 
+// Mirrors the usual scrobbling rule: a play counts once half the track, or
+// four minutes of it, has been heard.
+const SCROBBLE_MAX_SECONDS = 240;
+
+function currentQueueTrack() {
+    return queueIndex >= 0 ? playQueue[queueIndex] : null;
+}
+
+function postPlayEvent(endpoint, body) {
+    fetch(`${API_BASE}/${endpoint}`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ ...body, client: 'web' }),
+    }).catch((error) => console.warn(`${endpoint} failed:`, error));
+}
+
+function reportPlayStart() {
+    const track = currentQueueTrack();
+    if (!track || !track.relative_path) {
+        return;
+    }
+    if (playReport && playReport.url === track.url && !playReport.ended) {
+        return;
+    }
+    playReport = {
+        url: track.url,
+        path: track.relative_path,
+        startedAt: Math.floor(Date.now() / 1000),
+        listened: 0,
+        lastTime: audio.currentTime,
+        scrobbled: false,
+        ended: false,
+    };
+    postPlayEvent('now-playing', { path: track.relative_path });
+}
+
+function trackListening() {
+    if (!playReport || playReport.scrobbled || audio.src !== playReport.url) {
+        return;
+    }
+    const delta = audio.currentTime - playReport.lastTime;
+    playReport.lastTime = audio.currentTime;
+    // Seeks show up as large jumps and do not count as listening.
+    if (delta > 0 && delta < 5) {
+        playReport.listened += delta;
+    }
+    const duration = Number.isFinite(audio.duration) ? audio.duration : 0;
+    const threshold = duration ? Math.min(duration / 2, SCROBBLE_MAX_SECONDS) : SCROBBLE_MAX_SECONDS;
+    if (playReport.listened >= threshold) {
+        playReport.scrobbled = true;
+        postPlayEvent('scrobble', {
+            path: playReport.path,
+            timestamp: playReport.startedAt,
+            played: Math.round(playReport.listened),
+        });
+    }
+}
+
+// Long-form tracks remember where playback stopped and pick up there.
+const RESUME_SAVE_SECONDS = 15;
+
+function restoreResumePosition() {
+    const track = currentQueueTrack();
+    if (!track || !track.long_form || audio.src !== track.url) {
+        resumeState = null;
+        return;
+    }
+    if (resumeState && resumeState.url === track.url) {
+        return;
+    }
+    const state = { url: track.url, path: track.relative_path, savedAt: 0, ready: false };
+    resumeState = state;
+    const query = new URLSearchParams({ path: track.relative_path, client: 'web' });
+    fetch(`${API_BASE}/resume/track?${query}`)
+        .then((response) => (response.ok ? response.json() : null))
+        .then((entry) => {
+            if (entry && audio.src === track.url && audio.currentTime < 1) {
+                audio.currentTime = entry.position;
+            }
+        })
+        .catch((error) => console.warn('resume lookup failed:', error))
+        .finally(() => {
+            // Saving before the lookup finishes would overwrite the position.
+            state.ready = true;
+        });
+}
+
+function saveResumePosition(force = false) {
+    if (!resumeState || !resumeState.ready || audio.src !== resumeState.url) {
+        return;
+    }
+    const now = Date.now() / 1000;
+    if (!force && now - resumeState.savedAt < RESUME_SAVE_SECONDS) {
+        return;
+    }
+    resumeState.savedAt = now;
+    fetch(`${API_BASE}/resume`, {
+        method: 'PUT',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({
+            path: resumeState.path,
+            position: audio.ended ? audio.duration : audio.currentTime,
+            client: 'web',
+        }),
+    }).catch((error) => console.warn('resume save failed:', error));
+}
+
 audio.addEventListener('play', () => {
+    reportPlayStart();
     setPlayPauseVisual(true);
     updatePlaybackState('playing');
     updatePositionState();
 });
 
 audio.addEventListener('pause', () => {
+    saveResumePosition(true);
     setPlayPauseVisual(false);
     updatePlaybackState('paused');
 });
 
 audio.addEventListener('ended', () => {
+    if (playReport) {
+        playReport.ended = true;
+    }
+    saveResumePosition(true);
     updatePlaybackState('none');
     nextTrack(true);
 });
 
 audio.addEventListener('timeupdate', () => {
+    trackListening();
+    if (!audio.paused) {
+        saveResumePosition();
+    }
     updateProgress();
     updatePositionState();
 });
 
-audio.addEventListener('loadedmetadata', updatePositionState);
+audio.addEventListener('loadedmetadata', () => {
+    restoreResumePosition();
+    updatePositionState();
+});
 
 audio.addEventListener('durationchange', updatePositionState);
 
 audio.addEventListener('ratechange', updatePositionState);
 
+const ADMIN_TOKEN_KEY = 'musrv.adminToken';
+
+// Admin calls carry the token in a header, which cross-site pages cannot
+// set, so a stray link or form can never trigger them.
+async function adminFetch(path, options = {}, retry = true) {
+    let token = localStorage.getItem(ADMIN_TOKEN_KEY) || '';
+    if (!token) {
+        token = prompt('Admin token (printed by musrv at startup):') || '';
+        if (!token) {
+            throw new Error('admin token required');
+        }
+        localStorage.setItem(ADMIN_TOKEN_KEY, token);
+    }
+    const headers = { ...(options.headers || {}), 'X-Admin-Token': token };
+    const response = await fetch(path, { ...options, headers });
+    if (response.status === 401) {
+        localStorage.removeItem(ADMIN_TOKEN_KEY);
+        if (retry) {
+            return adminFetch(path, options, false);
+        }
+        throw new Error('invalid admin token');
+    }
+    return response;
+}
+
 async function rescanLibrary() {
     const button = document.getElementById('rescan-btn');
     if (button) {
@@ -527,12 +836,15 @@ async function rescanLibrary() {
         button.textContent = 'rescanning...';
     }
     try {
-        const response = await fetch(`${API_BASE.replace('/api', '')}/admin/rescan`);
-        if (response.ok) {
-            await loadFolder(currentPath);
-        } else {
+        const response = await adminFetch('/admin/rescan', { method: 'POST' });
+        if (!response.ok) {
             throw new Error('rescan failed');
         }
+        const result = await response.json();
+        if (result.status === 'busy') {
+            alert('a scan is already running');
+        }
+        await loadFolder(currentPath);
     } catch (error) {
         alert('error rescanning library: ' + error.message);
     } finally {
@@ -0,0 +1,435 @@
+//! Edits the tags of audio files in place: metadata fixes from the admin
+//! API, and favorites, ratings and play counts mirrored into the files so
+//! other players can see them (only with `--allow-tag-writes`).
+
+use std::fs::{self, OpenOptions};
+use std::io::Seek;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use lofty::flac::FlacFile;
+use lofty::id3::v2::{Frame, FrameFlags, Id3v2Tag, Popularimeter};
+use lofty::mp4::{Atom, AtomData, AtomIdent, Ilst, Mp4File};
+use lofty::mpeg::MpegFile;
+use lofty::ogg::{OpusFile, SpeexFile, VorbisComments, VorbisFile};
+use lofty::{
+    Accessor, AudioFile, FileType, ItemKey, MimeType, ParseOptions, Picture, PictureType, Probe,
+    Tag, TagExt, TaggedFileExt,
+};
+
+/// The POPM "email" field identifying our ratings among other players'.
+const POPM_EMAIL: &str = "musrv";
+const FAVORITE_KEY: &str = "FAVORITE";
+const ITUNES_MEAN: &str = "com.apple.iTunes";
+
+/// What gets written into a track's tags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TagStats {
+    /// 1–5 stars.
+    pub rating: Option<u8>,
+    pub favorite: bool,
+    pub play_count: u64,
+}
+
+/// A metadata change. Fields left out keep their value; an empty string or
+/// a 0 removes the tag.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub struct TagEdit {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    #[serde(skip)]
+    pub cover: Option<CoverEdit>,
+}
+
+/// A new front cover, or `Remove` to drop the embedded one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CoverEdit {
+    Set { mime: String, data: Vec<u8> },
+    Remove,
+}
+
+impl TagEdit {
+    pub fn is_empty(&self) -> bool {
+        *self == TagEdit::default()
+    }
+}
+
+/// Updates the rating, favorite and play count tags of one file. The file
+/// keeps its modification time: a play is not a change to the music, and
+/// feeds and `modified` rules should not treat it as one.
+pub fn write_stats(path: &Path, stats: &TagStats) -> anyhow::Result<()> {
+    let file_type = FileType::from_path(path)
+        .with_context(|| format!("unknown file type: {}", path.display()))?;
+    let modified = fs::metadata(path)?.modified()?;
+    replace_atomically(path, |tmp| {
+        apply(tmp, file_type, stats)?;
+        OpenOptions::new()
+            .write(true)
+            .open(tmp)?
+            .set_modified(modified)?;
+        Ok(())
+    })
+}
+
+/// Applies a metadata change to one file, creating its primary tag if it
+/// has none yet.
+pub fn write_edit(path: &Path, edit: &TagEdit) -> anyhow::Result<()> {
+    let file_type = FileType::from_path(path)
+        .with_context(|| format!("unknown file type: {}", path.display()))?;
+    replace_atomically(path, |tmp| apply_edit(tmp, file_type, edit))
+}
+
+/// The file is copied, edited and renamed over the original so a failure
+/// never leaves a half-written track behind.
+fn replace_atomically(
+    path: &Path,
+    edit: impl FnOnce(&Path) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let tmp = temp_path(path);
+    fs::copy(path, &tmp).with_context(|| format!("failed to copy {}", path.display()))?;
+    let result = edit(&tmp).and_then(|()| fs::rename(&tmp, path).context("failed to replace file"));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// A hidden sibling, so a scan running meanwhile skips it.
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{file_name}.{}.tags.tmp", std::process::id()))
+}
+
+fn apply(path: &Path, file_type: FileType, stats: &TagStats) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let options = ParseOptions::new().read_properties(false);
+    match file_type {
+        FileType::Mpeg => {
+            let mut audio = MpegFile::read_from(&mut file, options)?;
+            if audio.id3v2().is_none() {
+                audio.set_id3v2(Id3v2Tag::default());
+            }
+            let tag = audio.id3v2_mut().expect("tag was just set");
+            set_id3v2(tag, stats)?;
+            file.rewind()?;
+            tag.save_to(&mut file)?;
+        }
+        FileType::Flac => {
+            let mut audio = FlacFile::read_from(&mut file, options)?;
+            if audio.vorbis_comments().is_none() {
+                audio.set_vorbis_comments(VorbisComments::default());
+            }
+            let tag = audio.vorbis_comments_mut().expect("tag was just set");
+            set_vorbis(tag, stats);
+            file.rewind()?;
+            tag.save_to(&mut file)?;
+        }
+        FileType::Vorbis => {
+            let mut audio = VorbisFile::read_from(&mut file, options)?;
+            set_vorbis(audio.vorbis_comments_mut(), stats);
+            file.rewind()?;
+            audio.vorbis_comments().save_to(&mut file)?;
+        }
+        FileType::Opus => {
+            let mut audio = OpusFile::read_from(&mut file, options)?;
+            set_vorbis(audio.vorbis_comments_mut(), stats);
+            file.rewind()?;
+            audio.vorbis_comments().save_to(&mut file)?;
+        }
+        FileType::Speex => {
+            let mut audio = SpeexFile::read_from(&mut file, options)?;
+            set_vorbis(audio.vorbis_comments_mut(), stats);
+            file.rewind()?;
+            audio.vorbis_comments().save_to(&mut file)?;
+        }
+        FileType::Mp4 => {
+            let mut audio = Mp4File::read_from(&mut file, options)?;
+            if audio.ilst().is_none() {
+                audio.set_ilst(Ilst::default());
+            }
+            let tag = audio.ilst_mut().expect("tag was just set");
+            set_mp4(tag, stats);
+            file.rewind()?;
+            tag.save_to(&mut file)?;
+        }
+        other => bail!("writing tags to {other:?} files is not supported"),
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+fn apply_edit(path: &Path, file_type: FileType, edit: &TagEdit) -> anyhow::Result<()> {
+    // The temporary copy's name says nothing about its format.
+    let mut tagged = Probe::open(path)?.set_file_type(file_type).read()?;
+    let tag_type = tagged.primary_tag_type();
+    if tagged.primary_tag().is_none() {
+        tagged.insert_tag(Tag::new(tag_type));
+    }
+    let tag = tagged.primary_tag_mut().expect("tag was just inserted");
+    set_text(tag, ItemKey::TrackTitle, &edit.title);
+    set_text(tag, ItemKey::TrackArtist, &edit.artist);
+    set_text(tag, ItemKey::AlbumTitle, &edit.album);
+    set_text(tag, ItemKey::AlbumArtist, &edit.album_artist);
+    set_text(tag, ItemKey::Genre, &edit.genre);
+    match edit.year {
+        Some(0) => tag.remove_year(),
+        Some(year) => tag.set_year(year),
+        None => {}
+    }
+    match edit.track_number {
+        Some(0) => tag.remove_track(),
+        Some(n) => tag.set_track(n),
+        None => {}
+    }
+    match edit.track_total {
+        Some(0) => tag.remove_track_total(),
+        Some(n) => tag.set_track_total(n),
+        None => {}
+    }
+    match edit.disc_number {
+        Some(0) => tag.remove_disk(),
+        Some(n) => tag.set_disk(n),
+        None => {}
+    }
+    match edit.disc_total {
+        Some(0) => tag.remove_disk_total(),
+        Some(n) => tag.set_disk_total(n),
+        None => {}
+    }
+    match &edit.cover {
+        Some(CoverEdit::Set { mime, data }) => {
+            tag.remove_picture_type(PictureType::CoverFront);
+            tag.push_picture(Picture::new_unchecked(
+                PictureType::CoverFront,
+                Some(MimeType::from_str(mime)),
+                None,
+                data.clone(),
+            ));
+        }
+        Some(CoverEdit::Remove) => tag.remove_picture_type(PictureType::CoverFront),
+        None => {}
+    }
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    tag.save_to(&mut file)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn set_text(tag: &mut Tag, key: ItemKey, value: &Option<String>) {
+    match value.as_deref().map(str::trim) {
+        Some("") => tag.remove_key(&key),
+        Some(text) => {
+            tag.insert_text(key, text.to_string());
+        }
+        None => {}
+    }
+}
+
+/// Stars on the 0–255 POPM scale, matching Windows Media Player and most
+/// players that follow it.
+fn popm_rating(stars: Option<u8>) -> u8 {
+    match stars {
+        Some(1) => 1,
+        Some(2) => 64,
+        Some(3) => 128,
+        Some(4) => 196,
+        Some(5) => 255,
+        _ => 0,
+    }
+}
+
+fn set_id3v2(tag: &mut Id3v2Tag, stats: &TagStats) -> anyhow::Result<()> {
+    let popm = Popularimeter {
+        email: POPM_EMAIL.to_string(),
+        rating: popm_rating(stats.rating),
+        counter: stats.play_count,
+    };
+    // Frames compare equal by email, so this replaces only our own POPM.
+    tag.insert(Frame::new("POPM", popm, FrameFlags::default())?);
+    if stats.favorite {
+        tag.insert_user_text(FAVORITE_KEY.to_string(), "1".to_string());
+    } else {
+        tag.remove_user_text(FAVORITE_KEY);
+    }
+    Ok(())
+}
+
+fn set_vorbis(tag: &mut VorbisComments, stats: &TagStats) {
+    match stats.rating {
+        Some(stars) => {
+            tag.insert("RATING".to_string(), (u32::from(stars) * 20).to_string());
+            tag.insert(
+                "FMPS_RATING".to_string(),
+                format!("{:.1}", f64::from(stars) / 5.0),
+            );
+        }
+        None => {
+            let _ = tag.remove("RATING");
+            let _ = tag.remove("FMPS_RATING");
+        }
+    }
+    if stats.play_count > 0 {
+        tag.insert("FMPS_PLAYCOUNT".to_string(), stats.play_count.to_string());
+    }
+    if stats.favorite {
+        tag.insert(FAVORITE_KEY.to_string(), "1".to_string());
+    } else {
+        let _ = tag.remove(FAVORITE_KEY);
+    }
+}
+
+fn freeform(name: &'static str) -> AtomIdent<'static> {
+    AtomIdent::Freeform {
+        mean: ITUNES_MEAN.into(),
+        name: name.into(),
+    }
+}
+
+fn set_mp4(tag: &mut Ilst, stats: &TagStats) {
+    let rate = AtomIdent::Fourcc(*b"rate");
+    match stats.rating {
+        Some(stars) => tag.replace_atom(Atom::new(
+            rate,
+            AtomData::UTF8((u32::from(stars) * 20).to_string()),
+        )),
+        None => {
+            let _ = tag.remove(&rate);
+        }
+    }
+    if stats.play_count > 0 {
+        tag.replace_atom(Atom::new(
+            freeform("FMPS_Playcount"),
+            AtomData::UTF8(stats.play_count.to_string()),
+        ));
+    }
+    if stats.favorite {
+        tag.replace_atom(Atom::new(
+            freeform(FAVORITE_KEY),
+            AtomData::UTF8("1".to_string()),
+        ));
+    } else {
+        let _ = tag.remove(&freeform(FAVORITE_KEY));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::minimal_flac;
+
+    #[test]
+    fn writes_vorbis_ratings_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        fs::write(&path, minimal_flac()).unwrap();
+        let stats = TagStats {
+            rating: Some(4),
+            favorite: true,
+            play_count: 7,
+        };
+        let yesterday = std::time::SystemTime::now() - std::time::Duration::from_secs(86_400);
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(yesterday)
+            .unwrap();
+        // Every value stored under `key`, so a duplicate would show up.
+        let values = |key: &str| -> Vec<String> {
+            let tagged = lofty::read_from_path(&path).unwrap();
+            let key = lofty::ItemKey::from_key(lofty::TagType::VorbisComments, key);
+            tagged
+                .primary_tag()
+                .unwrap()
+                .get_strings(&key)
+                .map(str::to_string)
+                .collect()
+        };
+        write_stats(&path, &stats).unwrap();
+        assert_eq!(values("RATING"), ["80"]);
+        assert_eq!(values("FMPS_RATING"), ["0.8"]);
+        assert_eq!(values("FMPS_PLAYCOUNT"), ["7"]);
+        assert_eq!(values("FAVORITE"), ["1"]);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), yesterday);
+
+        let lower = TagStats {
+            rating: Some(2),
+            favorite: true,
+            play_count: 8,
+        };
+        write_stats(&path, &lower).unwrap();
+        assert_eq!(values("RATING"), ["40"]);
+        assert_eq!(values("FMPS_RATING"), ["0.4"]);
+        assert_eq!(values("FMPS_PLAYCOUNT"), ["8"]);
+
+        write_stats(&path, &TagStats::default()).unwrap();
+        assert!(values("RATING").is_empty());
+        assert!(values("FMPS_RATING").is_empty());
+        assert!(values("FAVORITE").is_empty());
+        // A zero count leaves the last one written in place.
+        assert_eq!(values("FMPS_PLAYCOUNT"), ["8"]);
+        let leftovers: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(leftovers.len(), 1);
+    }
+
+    #[test]
+    fn edits_metadata_and_cover() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        fs::write(&path, minimal_flac()).unwrap();
+        let edit = TagEdit {
+            title: Some("Intro".into()),
+            album: Some(" Debut ".into()),
+            track_number: Some(3),
+            year: Some(1999),
+            cover: Some(CoverEdit::Set {
+                mime: "image/png".into(),
+                data: b"\x89PNG\r\n\x1a\n".to_vec(),
+            }),
+            ..TagEdit::default()
+        };
+        write_edit(&path, &edit).unwrap();
+
+        let tagged = lofty::read_from_path(&path).unwrap();
+        let tag = tagged.primary_tag().unwrap();
+        assert_eq!(tag.title().as_deref(), Some("Intro"));
+        assert_eq!(tag.album().as_deref(), Some("Debut"));
+        assert_eq!(tag.track(), Some(3));
+        assert_eq!(tag.year(), Some(1999));
+        assert_eq!(tag.pictures().len(), 1);
+
+        let clear = TagEdit {
+            album: Some(String::new()),
+            track_number: Some(0),
+            cover: Some(CoverEdit::Remove),
+            ..TagEdit::default()
+        };
+        write_edit(&path, &clear).unwrap();
+        let tagged = lofty::read_from_path(&path).unwrap();
+        let tag = tagged.primary_tag().unwrap();
+        assert_eq!(tag.title().as_deref(), Some("Intro"));
+        assert_eq!(tag.album(), None);
+        assert_eq!(tag.track(), None);
+        assert!(tag.pictures().is_empty());
+    }
+
+    #[test]
+    fn rejects_unsupported_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.wv");
+        fs::write(&path, b"").unwrap();
+        assert!(write_stats(&path, &TagStats::default()).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
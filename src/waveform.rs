@@ -0,0 +1,157 @@
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::decode;
+use crate::dupes;
+use crate::path_utils;
+
+/// Points kept in the on-disk cache; requests for fewer are merged from it.
+pub const CACHE_POINTS: usize = 4096;
+/// Directory in the data directory holding cached waveforms.
+pub const WAVEFORM_DIR: &str = "waveforms";
+/// Resolution of the first pass, before merging down to the cached size.
+const BUCKETS_PER_SEC: u32 = 100;
+
+/// Lowest and highest sample over each slice of a track, across channels,
+/// in the range -1..1.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Waveform {
+    pub duration: f64,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+impl Waveform {
+    /// Merges neighbouring points until there are at most `points`.
+    pub fn downsample(&self, points: usize) -> Waveform {
+        let n = self.min.len();
+        if points >= n {
+            return self.clone();
+        }
+        let mut min = vec![f32::MAX; points];
+        let mut max = vec![f32::MIN; points];
+        for i in 0..n {
+            let j = i * points / n;
+            min[j] = min[j].min(self.min[i]);
+            max[j] = max[j].max(self.max[i]);
+        }
+        Waveform {
+            duration: self.duration,
+            min,
+            max,
+        }
+    }
+
+    /// Pairs of signed bytes, minimum then maximum, with ±127 at full scale.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let scale = |v: f32| (v.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8;
+        self.min
+            .iter()
+            .zip(&self.max)
+            .flat_map(|(&lo, &hi)| [scale(lo), scale(hi)])
+            .collect()
+    }
+}
+
+/// Decodes a file, or the `(start, end)` seconds of it that a CUE sheet
+/// assigns to one track, and measures its peaks.
+pub fn compute(path: &Path, span: Option<(f64, Option<f64>)>) -> anyhow::Result<Waveform> {
+    let (start, end) = span.unwrap_or((0.0, None));
+    let mut buckets: Vec<(f32, f32)> = Vec::new();
+    let mut rate = 0;
+    let mut bucket_len = 0;
+    let (mut lo, mut hi, mut fill) = (0f32, 0f32, 0);
+    let mut frame: u64 = 0;
+    let mut counted: u64 = 0;
+    decode::decode(path, |spec, samples| {
+        if rate == 0 {
+            rate = spec.rate;
+            bucket_len = (rate / BUCKETS_PER_SEC).max(1);
+        }
+        let first = (start * f64::from(rate)) as u64;
+        let last = end.map(|e| (e * f64::from(rate)) as u64);
+        for frame_samples in samples.chunks_exact(spec.channels.max(1)) {
+            let index = frame;
+            frame += 1;
+            if last.is_some_and(|last| index >= last) {
+                return ControlFlow::Break(());
+            }
+            if index < first {
+                continue;
+            }
+            for &s in frame_samples {
+                lo = lo.min(s);
+                hi = hi.max(s);
+            }
+            counted += 1;
+            fill += 1;
+            if fill == bucket_len {
+                buckets.push((lo, hi));
+                (lo, hi, fill) = (0.0, 0.0, 0);
+            }
+        }
+        ControlFlow::Continue(())
+    })?;
+    if fill > 0 {
+        buckets.push((lo, hi));
+    }
+    anyhow::ensure!(!buckets.is_empty(), "no audio decoded");
+    let waveform = Waveform {
+        duration: counted as f64 / f64::from(rate),
+        min: buckets.iter().map(|b| b.0.max(-1.0)).collect(),
+        max: buckets.iter().map(|b| b.1.min(1.0)).collect(),
+    };
+    Ok(waveform.downsample(CACHE_POINTS))
+}
+
+/// Like [`compute`], but kept in `dir` under a hash of the file's audio,
+/// so a moved, renamed or retagged file is not decoded again while one
+/// with new audio is. The file is still read once per call to hash it.
+pub fn cached(
+    dir: &Path,
+    path: &Path,
+    span: Option<(f64, Option<f64>)>,
+) -> anyhow::Result<Waveform> {
+    let hash = dupes::audio_hash(path).context("failed to read track")?;
+    let mut key = hash.to_hex().to_string();
+    if let Some((start, end)) = span {
+        key.push_str(&format!("-{start}"));
+        if let Some(end) = end {
+            key.push_str(&format!("-{end}"));
+        }
+    }
+    let file = dir.join(format!("{key}.json"));
+    if let Ok(data) = fs::read(&file)
+        && let Ok(waveform) = serde_json::from_slice(&data)
+    {
+        return Ok(waveform);
+    }
+    let waveform = compute(path, span)?;
+    if let Err(err) = path_utils::write_atomic(&file, &serde_json::to_vec(&waveform)?) {
+        tracing::warn!(?err, path = %file.display(), "failed to cache waveform");
+    }
+    Ok(waveform)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsamples_and_packs_peaks() {
+        let wave = Waveform {
+            duration: 4.0,
+            min: vec![-0.5, -1.0, -0.25, 0.0],
+            max: vec![0.5, 0.25, 1.0, 0.0],
+        };
+        let half = wave.downsample(2);
+        assert_eq!(half.min, vec![-1.0, -0.25]);
+        assert_eq!(half.max, vec![0.5, 1.0]);
+        assert_eq!(wave.downsample(10), wave);
+        assert_eq!(half.to_bytes(), vec![0x81, 64, 224, 127]);
+    }
+}
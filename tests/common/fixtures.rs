@@ -0,0 +1,90 @@
+//! Small audio files built in memory for tests. Shared by the integration
+//! tests and, through a `#[path]` module, by the unit tests in `src/`.
+#![allow(dead_code)]
+
+/// A FLAC stream with only STREAMINFO and PADDING blocks: enough for
+/// lofty to read and write its tags.
+pub fn minimal_flac() -> Vec<u8> {
+    let mut data = b"fLaC".to_vec();
+    data.extend([0x00, 0, 0, 34]);
+    data.extend(4096u16.to_be_bytes());
+    data.extend(4096u16.to_be_bytes());
+    data.extend([0; 6]);
+    // 44100 Hz, 2 channels, 16 bits, 0 samples.
+    let packed: u64 = (44_100 << 44) | (1 << 41) | (15 << 36);
+    data.extend(packed.to_be_bytes());
+    data.extend([0; 16]);
+    data.extend([0x81, 0, 0, 16]);
+    data.extend([0; 16]);
+    data
+}
+
+/// A silent 16-bit stereo WAV file.
+pub fn wav(rate: u32, seconds: u32) -> Vec<u8> {
+    let data_len = rate * seconds * 4;
+    let mut out = Vec::new();
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&rate.to_le_bytes());
+    out.extend_from_slice(&(rate * 4).to_le_bytes());
+    out.extend_from_slice(&4u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    out.resize(out.len() + data_len as usize, 0);
+    out
+}
+
+/// A stereo 16-bit WAV holding a 1 kHz sine at the given peak level.
+pub fn sine_wav(rate: u32, seconds: u32, amplitude: f64) -> Vec<u8> {
+    let mut out = wav(rate, seconds);
+    let data = out.len() - (rate * seconds * 4) as usize;
+    for n in 0..(rate * seconds) as usize {
+        let t = n as f64 / f64::from(rate);
+        let s = (amplitude * 32767.0 * (2.0 * std::f64::consts::PI * 1000.0 * t).sin()) as i16;
+        for c in 0..2 {
+            let at = data + n * 4 + c * 2;
+            out[at..at + 2].copy_from_slice(&s.to_le_bytes());
+        }
+    }
+    out
+}
+
+/// An MP4 atom (box) around `body`.
+pub fn atom(kind: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// An MP4 audiobook with one sound track of `seconds` and Nero chapter
+/// marks at the given starting seconds. There are no samples, but lofty
+/// reads its duration.
+pub fn m4b(seconds: u32, chapters: &[(u32, &str)]) -> Vec<u8> {
+    let full = |words: &[u32]| -> Vec<u8> { words.iter().flat_map(|w| w.to_be_bytes()).collect() };
+    // Version and flags, creation and modification times, timescale, duration.
+    let mdhd = atom(b"mdhd", &full(&[0, 0, 0, 1000, seconds * 1000, 0]));
+    let mut hdlr = vec![0; 8];
+    hdlr.extend_from_slice(b"soun");
+    hdlr.extend_from_slice(&[0; 13]);
+    let trak = atom(
+        b"trak",
+        &atom(b"mdia", &[mdhd, atom(b"hdlr", &hdlr)].concat()),
+    );
+    let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, chapters.len() as u8];
+    for &(start, title) in chapters {
+        // 100 ns units.
+        chpl.extend_from_slice(&(u64::from(start) * 10_000_000).to_be_bytes());
+        chpl.push(title.len() as u8);
+        chpl.extend_from_slice(title.as_bytes());
+    }
+    let udta = atom(b"udta", &atom(b"chpl", &chpl));
+    let mut out = atom(b"ftyp", b"M4B \0\0\0\0");
+    out.extend(atom(b"moov", &[trak, udta].concat()));
+    out
+}
@@ -1,33 +1,41 @@
-use std::sync::{Arc, atomic::AtomicBool};
-
 use axum::{
     body,
     body::Body,
-    http::{Request, StatusCode},
+    http::{Request, StatusCode, header},
 };
 use tower::util::ServiceExt;
 
+mod common;
+
+use common::fixtures::{m4b, minimal_flac, sine_wav, wav};
+
 fn write_file(path: &std::path::Path) {
     std::fs::create_dir_all(path.parent().unwrap()).unwrap();
     std::fs::write(path, b"").unwrap();
 }
 
+fn tempdir() -> tempfile::TempDir {
+    tempfile::Builder::new().prefix("musrv").tempdir().unwrap()
+}
+
 #[tokio::test]
 async fn library_json_and_playlists() {
-    let tmp = tempfile::tempdir().unwrap();
+    let tmp = tempdir();
     let root = tmp.path().to_path_buf();
     write_file(&root.join("Album1/song1.mp3"));
     write_file(&root.join("Album1/song2.flac"));
     write_file(&root.join("loose.mp3"));
 
     let lib = musrv::library::Library::scan(root.clone());
-    let state = musrv::server::AppState {
-        lib: Arc::new(arc_swap::ArcSwap::from(Arc::new(lib))),
-        base: "http://127.0.0.1:9999/".to_string(),
-        root: root.clone(),
-        scan_ready: Arc::new(AtomicBool::new(true)),
-        scan_in_progress: Arc::new(AtomicBool::new(false)),
-    };
+    let state = musrv::server::AppState::new(
+        "http://127.0.0.1:9999/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            lib,
+            true,
+        )],
+    );
     let app = musrv::server::build_router(state);
 
     let res = app
@@ -59,6 +67,25 @@ async fn library_json_and_playlists() {
         .unwrap();
     assert!(res2.status().is_success());
 
+    let xspf = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder.xspf?path=Album1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        xspf.headers()[header::CONTENT_TYPE],
+        "application/xspf+xml; charset=utf-8"
+    );
+    let bytes = body::to_bytes(xspf.into_body(), 1024 * 1024).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.contains("<title>Album1</title>"));
+    assert!(text.contains("<location>http://127.0.0.1:9999/Album1/song1.mp3</location>"));
+
     let res3 = app
         .clone()
         .oneshot(
@@ -71,3 +98,1900 @@ async fn library_json_and_playlists() {
         .unwrap();
     assert_eq!(res3.status(), StatusCode::NOT_FOUND);
 }
+
+#[tokio::test]
+async fn folder_m3u8_export_options() {
+    use serde_json::json;
+
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("A/B")).unwrap();
+    std::fs::write(root.join("A/B/song one.flac"), minimal_flac()).unwrap();
+    std::fs::write(root.join("A/two.flac"), minimal_flac()).unwrap();
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount])
+        .with_admin_token("secret");
+    let app = musrv::server::build_router(state);
+    let edit = json!({
+        "title": "Opener",
+        "artist": "Band",
+        "album": "Debut",
+        "cover": "data:image/png;base64,iVBORw0KGgo=",
+    });
+    let (status, _) = send_json_as(
+        &app,
+        Some("secret"),
+        "PATCH",
+        "/api/tracks/A/B/song%20one.flac",
+        Some(edit),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let get = |uri: &'static str| {
+        let app = app.clone();
+        async move {
+            let res = app
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let bytes = body::to_bytes(res.into_body(), 64 * 1024).await.unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        }
+    };
+
+    let m3u8 = get("/api/folder.m3u8?path=A").await;
+    assert!(m3u8.starts_with("#EXTM3U\r\n#PLAYLIST:A\r\n"));
+    assert!(m3u8.contains(
+        "#EXTINF:0,Band - Opener\r\n#EXTALB:Debut\r\n#EXTART:Band\r\n#EXTIMG:http://h/api/artwork/"
+    ));
+    assert!(m3u8.contains("\r\nhttp://h/A/B/song%20one.flac\r\n"));
+    assert!(m3u8.contains("\r\nhttp://h/A/two.flac\r\n"));
+
+    let relative = get("/api/folder.m3u8?path=A&relative=1").await;
+    assert!(relative.contains("#EXTALB:Debut\r\n"));
+    assert!(relative.contains("\r\nB/song one.flac\r\n"));
+    assert!(relative.contains("\r\ntwo.flac\r\n"));
+    assert!(!relative.contains("http://h/A/"));
+
+    let xspf = get("/api/folder.xspf?path=A&relative=1").await;
+    assert!(xspf.contains("<location>B/song%20one.flac</location>"));
+
+    // Players that reject other directives get `#EXTINF` lines only.
+    let plain = get("/api/folder.m3u8?path=A&extended=0").await;
+    assert!(plain.starts_with("#EXTM3U\r\n#EXTINF:"));
+    assert!(plain.contains("#EXTINF:0,Band - Opener\r\nhttp://h/A/B/song%20one.flac\r\n"));
+    for tag in ["#PLAYLIST", "#EXTALB", "#EXTART", "#EXTIMG"] {
+        assert!(!plain.contains(tag));
+    }
+}
+
+#[tokio::test]
+async fn named_mounts_are_listed_and_served() {
+    let music = tempdir();
+    let books = tempdir();
+    write_file(&music.path().join("Album/song.mp3"));
+    std::fs::create_dir_all(books.path().join("Novel")).unwrap();
+    std::fs::write(books.path().join("Novel/part1.mp3"), b"abc").unwrap();
+
+    let mounts = [("music", music.path()), ("books", books.path())]
+        .into_iter()
+        .map(|(name, dir)| {
+            let root = std::fs::canonicalize(dir).unwrap();
+            let lib = musrv::library::Library::scan(root.clone());
+            musrv::server::state::Mount::new(name, root, lib, true)
+        })
+        .collect();
+    let state = musrv::server::AppState::new("http://h/".to_string(), mounts);
+    let app = musrv::server::build_router(state);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let names: Vec<&str> = v["albums"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|a| a["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["music", "books"]);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder?path=books")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["albums"][0]["path"], "books/Novel");
+    assert_eq!(v["tracks"][0]["relative_path"], "books/Novel/part1.mp3");
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/books/Novel/part1.mp3")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    assert_eq!(&bytes[..], b"abc");
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/music/Novel/part1.mp3")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn scan_publishes_events() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    write_file(&root.join("Album/song.mp3"));
+
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            musrv::library::Library::empty(root.clone()),
+            false,
+        )],
+    );
+    let app = musrv::server::build_router(state.clone());
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/events")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        res.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+
+    let mut rx = state.events.subscribe();
+    assert!(state.schedule_scan(false));
+    let mut names = Vec::new();
+    loop {
+        let event = rx.recv().await.unwrap();
+        names.push(event.name());
+        if let musrv::server::events::ServerEvent::LibraryChanged { revision } = event {
+            assert_eq!(revision, 2);
+            break;
+        }
+    }
+    assert_eq!(names.first(), Some(&"scan_started"));
+    assert!(names.contains(&"scan_progress"));
+    assert!(names.contains(&"scan_finished"));
+    assert!(!state.is_scanning(""));
+}
+
+#[tokio::test]
+async fn scan_status_reports_unparseable_files() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    write_file(&root.join("Album/broken.mp3"));
+
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            musrv::library::Library::empty(root.clone()),
+            false,
+        )],
+    );
+    let mut rx = state.events.subscribe();
+    assert!(state.schedule_scan(false));
+    while !matches!(
+        rx.recv().await.unwrap(),
+        musrv::server::events::ServerEvent::LibraryChanged { .. }
+    ) {}
+
+    let app = musrv::server::build_router(state.clone().with_admin_token("secret"));
+    let (status, v) = send_json_as(&app, Some("secret"), "GET", "/admin/scan/status", None).await;
+    assert!(status.is_success());
+    assert_eq!(v["mounts"][0]["scanning"], false);
+    let report = &v["mounts"][0]["last_scan"];
+    assert_eq!(report["tracks"], 1);
+    assert_eq!(report["issues"][0]["path"], "Album/broken.mp3");
+    assert!(report["error"].is_null());
+    assert!(root.join(".musrv/scan-report.json").exists());
+
+    // Folder scans count as scanning from the moment they are queued.
+    let mount = state.mounts[0].clone();
+    assert!(state.schedule_folder_scan(&mount, "Album".to_string()));
+    let (_, v) = send_json_as(&app, Some("secret"), "GET", "/admin/scan/status", None).await;
+    assert_eq!(v["mounts"][0]["scanning"], true);
+    while !matches!(
+        rx.recv().await.unwrap(),
+        musrv::server::events::ServerEvent::LibraryChanged { .. }
+    ) {}
+    let (_, v) = send_json_as(&app, Some("secret"), "GET", "/admin/scan/status", None).await;
+    assert_eq!(v["mounts"][0]["scanning"], false);
+    assert_eq!(v["mounts"][0]["last_scan"]["scope"], "Album");
+}
+
+#[tokio::test]
+async fn rescan_single_folder() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    write_file(&root.join("Old/a.mp3"));
+    let lib = musrv::library::Library::scan(root.clone());
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            lib,
+            true,
+        )],
+    )
+    .with_admin_token("secret");
+    let app = musrv::server::build_router(state.clone());
+
+    write_file(&root.join("New/b.mp3"));
+    let mut rx = state.events.subscribe();
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/admin/rescan?path=New")
+                .header("x-admin-token", "secret")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "queued");
+    assert_eq!(v["path"], "New");
+    while !matches!(
+        rx.recv().await.unwrap(),
+        musrv::server::events::ServerEvent::LibraryChanged { .. }
+    ) {}
+    let paths: Vec<String> = state
+        .collect_tracks("")
+        .into_iter()
+        .map(|t| t.path)
+        .collect();
+    assert_eq!(paths, vec!["New/b.mp3", "Old/a.mp3"]);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/admin/rescan?path=Missing")
+                .header("x-admin-token", "secret")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn admin_routes_require_post_and_token() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            musrv::library::Library::empty(root),
+            true,
+        )],
+    )
+    .with_admin_token("secret");
+    let app = musrv::server::build_router(state);
+
+    let send = |method: &str, token: Option<&str>, site: Option<&str>| {
+        let mut req = Request::builder().method(method).uri("/admin/rescan");
+        if let Some(token) = token {
+            req = req.header("x-admin-token", token);
+        }
+        if let Some(site) = site {
+            req = req.header("sec-fetch-site", site);
+        }
+        app.clone().oneshot(req.body(Body::empty()).unwrap())
+    };
+
+    let res = send("POST", None, None).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = send("POST", Some("wrong"), None).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = send("GET", Some("secret"), None).await.unwrap();
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let res = send("POST", Some("secret"), Some("cross-site"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = send("POST", Some("secret"), Some("same-origin"))
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "scheduled");
+}
+
+async fn send_json(
+    app: &axum::Router,
+    method: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    send_json_as(app, None, method, uri, body).await
+}
+
+/// Like [`send_json`], optionally with an admin token.
+async fn send_json_as(
+    app: &axum::Router,
+    token: Option<&str>,
+    method: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    let mut req = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        req = req.header("x-admin-token", token);
+    }
+    let body = match body {
+        Some(v) => {
+            req = req.header("content-type", "application/json");
+            Body::from(v.to_string())
+        }
+        None => Body::empty(),
+    };
+    let res = app.clone().oneshot(req.body(body).unwrap()).await.unwrap();
+    let status = res.status();
+    let bytes = body::to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    let v = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+    (status, v)
+}
+
+#[tokio::test]
+async fn saved_playlists_crud() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    write_file(&root.join("A/one.mp3"));
+    write_file(&root.join("B/two.mp3"));
+    write_file(&root.join("A/100%25.mp3"));
+    let lib = musrv::library::Library::scan(root.clone());
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            lib,
+            true,
+        )],
+    );
+    let app = musrv::server::build_router(state);
+
+    let (status, created) = send_json(
+        &app,
+        "POST",
+        "/api/playlists",
+        Some(serde_json::json!({"name": "Mix", "tracks": ["A/one.mp3", "gone.mp3"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(created["tracks"].as_array().unwrap().len(), 1);
+    assert_eq!(created["missing"][0], "gone.mp3");
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        &format!("/api/playlists/{id}/tracks"),
+        Some(serde_json::json!({"tracks": ["B/two.mp3"], "position": 0})),
+    )
+    .await;
+    assert!(status.is_success());
+    let (_, moved) = send_json(
+        &app,
+        "POST",
+        &format!("/api/playlists/{id}/move"),
+        Some(serde_json::json!({"from": 0, "to": 1})),
+    )
+    .await;
+    assert_eq!(moved["tracks"][0]["relative_path"], "A/one.mp3");
+    assert_eq!(moved["tracks"][1]["relative_path"], "B/two.mp3");
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/playlists/{id}.m3u8"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.starts_with("#EXTM3U"));
+    let one = text.find("http://h/A/one.mp3").unwrap();
+    let two = text.find("http://h/B/two.mp3").unwrap();
+    assert!(one < two);
+    assert!(!text.contains("gone.mp3"));
+
+    let (_, list) = send_json(&app, "GET", "/api/playlists", None).await;
+    assert_eq!(list[0]["id"], "favorites");
+    assert_eq!(list[1]["track_count"], 2);
+    assert_eq!(list[1]["missing_count"], 1);
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/api/playlists",
+        Some(serde_json::json!({"name": "Bad", "tracks": ["../etc/passwd"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    // Paths in the body are taken as they are, not percent-decoded again.
+    let (status, odd) = send_json(
+        &app,
+        "POST",
+        "/api/playlists",
+        Some(serde_json::json!({"name": "Odd", "tracks": ["A/100%25.mp3"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(odd["tracks"][0]["relative_path"], "A/100%25.mp3");
+    let (status, _) = send_json(&app, "DELETE", &format!("/api/playlists/{id}"), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send_json(&app, "GET", &format!("/api/playlists/{id}"), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn playlist_files_are_imported_read_only() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    write_file(&root.join("A/one.mp3"));
+    write_file(&root.join("B/two.mp3"));
+    std::fs::write(
+        root.join("A/mix.m3u"),
+        "#EXTM3U\n#PLAYLIST:Mix\none.mp3\n..\\B\\two.mp3\nD:\\Old\\B\\two.mp3\nnope.mp3\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("list.pls"),
+        format!("[playlist]\nFile1={}\n", root.join("A/one.mp3").display()),
+    )
+    .unwrap();
+    let lib = musrv::library::Library::scan(root.clone());
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            lib,
+            true,
+        )],
+    )
+    .with_playlist_remaps(vec![musrv::playlist::PathRemap {
+        from: "D:\\Old".into(),
+        to: "".into(),
+    }]);
+    let app = musrv::server::build_router(state);
+
+    let (_, list) = send_json(&app, "GET", "/api/playlists", None).await;
+    let list = list.as_array().unwrap();
+    assert_eq!(list.len(), 3);
+    let mix = list.iter().find(|p| p["name"] == "Mix").unwrap();
+    assert_eq!(mix["read_only"], true);
+    assert_eq!(mix["source"], "A/mix.m3u");
+    assert_eq!(mix["track_count"], 3);
+    assert_eq!(mix["missing_count"], 1);
+    let pls = list.iter().find(|p| p["name"] == "list").unwrap();
+    assert_eq!(pls["track_count"], 1);
+
+    let id = mix["id"].as_str().unwrap();
+    let (_, detail) = send_json(&app, "GET", &format!("/api/playlists/{id}"), None).await;
+    assert_eq!(detail["missing"][0], "nope.mp3");
+    assert_eq!(detail["tracks"][1]["relative_path"], "B/two.mp3");
+    let (status, _) = send_json(&app, "DELETE", &format!("/api/playlists/{id}"), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn smart_playlists_follow_rules() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    write_file(&root.join("A/one.flac"));
+    write_file(&root.join("A/two.mp3"));
+    let lib = musrv::library::Library::scan(root.clone());
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            lib,
+            true,
+        )],
+    );
+    let app = musrv::server::build_router(state);
+
+    let (status, created) = send_json(
+        &app,
+        "POST",
+        "/api/smart-playlists",
+        Some(serde_json::json!({
+            "name": "New FLAC",
+            "rules": [
+                {"field": "format", "op": "eq", "value": "flac"},
+                {"field": "modified", "op": "within_days", "value": 30}
+            ]
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["tracks"].as_array().unwrap().len(), 1);
+    assert_eq!(created["tracks"][0]["relative_path"], "A/one.flac");
+    let id = created["id"].as_str().unwrap();
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/smart-playlists/{id}.m3u8"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.contains("http://h/A/one.flac"));
+    assert!(!text.contains("two.mp3"));
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/api/smart-playlists",
+        Some(serde_json::json!({
+            "name": "Bad",
+            "rules": [{"field": "year", "op": "contains", "value": "x"}]
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    // Names are held to the same limit as saved playlists.
+    for def in [
+        serde_json::json!({"name": "n".repeat(201)}),
+        serde_json::json!({
+            "name": "Long",
+            "rules": [{"field": "title", "op": "contains", "value": "x".repeat(1025)}]
+        }),
+    ] {
+        let (status, _) = send_json(&app, "POST", "/api/smart-playlists", Some(def)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    let (_, list) = send_json(&app, "GET", "/api/smart-playlists", None).await;
+    assert_eq!(list.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn folder_downloads_as_zip() {
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("Album/CD1")).unwrap();
+    std::fs::write(root.join("Album/CD1/one.mp3"), b"first track").unwrap();
+    std::fs::write(root.join("Album/two.mp3"), b"second").unwrap();
+    std::fs::write(root.join("other.mp3"), b"elsewhere").unwrap();
+
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount]);
+    let app = musrv::server::build_router(state);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder.zip?path=Album")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "application/zip");
+    let len: usize = res.headers()[header::CONTENT_LENGTH]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    assert_eq!(bytes.len(), len);
+    let find = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+    assert!(find(b"Album/CD1/one.mp3first track"));
+    assert!(find(b"Album/two.mp3second"));
+    assert!(find(b"Album/Album.m3u8"));
+    assert!(find(b"CD1/one.mp3\r\n"));
+    assert!(!find(b"elsewhere"));
+
+    let missing = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder.zip?path=Nope")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn scrobbles_build_history_and_top_tracks() {
+    use serde_json::json;
+
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    write_file(&root.join("A/one.mp3"));
+    write_file(&root.join("A/two.mp3"));
+    write_file(&root.join("100%25 Hits/a.mp3"));
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount]);
+    let app = musrv::server::build_router(state);
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/api/now-playing",
+        Some(json!({"path": "A/one.mp3", "client": "phone"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, playing) = send_json(&app, "GET", "/api/now-playing", None).await;
+    assert_eq!(playing[0]["client"], "phone");
+    assert_eq!(playing[0]["path"], "A/one.mp3");
+
+    for (path, at) in [
+        ("A/one.mp3", 1000),
+        ("A/two.mp3", 2000),
+        ("A/one.mp3", 3000),
+    ] {
+        let (status, event) = send_json(
+            &app,
+            "POST",
+            "/api/scrobble",
+            Some(json!({"path": path, "timestamp": at, "client": "phone"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(event["track"]["relative_path"], path);
+    }
+    let (_, playing) = send_json(&app, "GET", "/api/now-playing", None).await;
+    assert_eq!(playing, json!([]));
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/api/scrobble",
+        Some(json!({"path": "A/missing.mp3"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/api/scrobble",
+        Some(json!({"path": "../etc/passwd"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, history) = send_json(&app, "GET", "/api/history?limit=2", None).await;
+    let ats: Vec<u64> = history["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["at"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ats, vec![3000, 2000]);
+
+    let (_, top) = send_json(&app, "GET", "/api/history/top-tracks", None).await;
+    assert_eq!(top["items"][0]["path"], "A/one.mp3");
+    assert_eq!(top["items"][0]["plays"], 2);
+    let (_, top) = send_json(
+        &app,
+        "GET",
+        "/api/history/top-tracks?since=1500&until=2500",
+        None,
+    )
+    .await;
+    assert_eq!(top["items"].as_array().unwrap().len(), 1);
+    assert_eq!(top["items"][0]["path"], "A/two.mp3");
+
+    // Body paths are taken as they are, not percent-decoded again.
+    for uri in ["/api/now-playing", "/api/scrobble"] {
+        let (status, _) = send_json(
+            &app,
+            "POST",
+            uri,
+            Some(json!({"path": "100%25 Hits/a.mp3", "timestamp": 100})),
+        )
+        .await;
+        assert!(status.is_success());
+    }
+
+    assert!(root.join(".musrv/history.jsonl").exists());
+}
+
+#[tokio::test]
+async fn ratings_and_favorites_playlist() {
+    use serde_json::json;
+
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    write_file(&root.join("A/one.mp3"));
+    write_file(&root.join("A/two.mp3"));
+    write_file(&root.join("B/100%25.mp3"));
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount]);
+    let app = musrv::server::build_router(state);
+
+    let (status, track) = send_json(
+        &app,
+        "PUT",
+        "/api/ratings/track",
+        Some(json!({"path": "A/two.mp3", "favorite": true, "rating": 5})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(track["favorite"], true);
+    assert_eq!(track["rating"], 5);
+    let (status, _) = send_json(
+        &app,
+        "PUT",
+        "/api/ratings/track",
+        Some(json!({"path": "A/one.mp3", "rating": 9})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    // Untagged tracks have no album key to rate by.
+    let (status, _) = send_json(
+        &app,
+        "PUT",
+        "/api/ratings/album",
+        Some(json!({"path": "A/one.mp3", "rating": 3})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, album) = send_json(
+        &app,
+        "PUT",
+        "/api/ratings/album",
+        Some(json!({"key": "band/record", "rating": 3})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(album["key"], "band/record");
+    // Body paths are taken as they are, not percent-decoded again.
+    let (status, track) = send_json(
+        &app,
+        "PUT",
+        "/api/ratings/track",
+        Some(json!({"path": "B/100%25.mp3", "rating": 1})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(track["relative_path"], "B/100%25.mp3");
+    let (status, _) = send_json(
+        &app,
+        "PUT",
+        "/api/ratings/album",
+        Some(json!({"path": "B/100%25.mp3", "rating": 3})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, folder) = send_json(&app, "GET", "/api/folder?path=A", None).await;
+    let tracks = folder["tracks"].as_array().unwrap();
+    let two = tracks.iter().find(|t| t["name"] == "two.mp3").unwrap();
+    assert_eq!(two["favorite"], true);
+    let one = tracks.iter().find(|t| t["name"] == "one.mp3").unwrap();
+    assert_eq!(one["favorite"], false);
+
+    let (_, ratings) = send_json(&app, "GET", "/api/ratings", None).await;
+    assert_eq!(ratings["tracks"][0]["key"], "A/two.mp3");
+    assert_eq!(ratings["albums"][0]["rating"], 3);
+
+    let (_, favorites) = send_json(&app, "GET", "/api/playlists/favorites", None).await;
+    assert_eq!(favorites["read_only"], true);
+    assert_eq!(favorites["tracks"][0]["relative_path"], "A/two.mp3");
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/playlists/favorites.m3u8")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.contains("http://h/A/two.mp3"));
+    assert!(!text.contains("one.mp3"));
+    let (status, _) = send_json(&app, "DELETE", "/api/playlists/favorites", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn tag_writes_mirror_ratings_into_files() {
+    use serde_json::json;
+
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("A")).unwrap();
+    std::fs::write(root.join("A/song.flac"), minimal_flac()).unwrap();
+    write_file(&root.join("A/empty.mp3"));
+    let last_week = std::time::SystemTime::now() - std::time::Duration::from_secs(7 * 86_400);
+    std::fs::File::options()
+        .write(true)
+        .open(root.join("A/song.flac"))
+        .unwrap()
+        .set_modified(last_week)
+        .unwrap();
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state =
+        musrv::server::AppState::new("http://h/".to_string(), vec![mount]).with_tag_writes(true);
+    let app = musrv::server::build_router(state.clone());
+
+    let (status, _) = send_json(
+        &app,
+        "PUT",
+        "/api/ratings/track",
+        Some(json!({"path": "A/song.flac", "favorite": true, "rating": 4})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/api/scrobble",
+        Some(json!({"path": "A/song.flac"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Writes wait in the background for requests to settle.
+    let song = root.join("A/song.flac");
+    let data = std::fs::read(&song).unwrap();
+    assert!(!String::from_utf8_lossy(&data).contains("RATING=80"));
+    state.flush_tag_writes().await;
+    let data = std::fs::read(&song).unwrap();
+    let text = String::from_utf8_lossy(&data);
+    assert!(text.contains("RATING=80"));
+    assert!(text.contains("FMPS_PLAYCOUNT=1"));
+    assert!(text.contains("FAVORITE=1"));
+    let size = state.find_track("A/song.flac").unwrap().track.size;
+    assert_eq!(size, Some(data.len() as u64));
+    // Plays and ratings leave the file's time alone, so feeds don't move.
+    let modified = std::fs::metadata(&song).unwrap().modified().unwrap();
+    assert_eq!(modified, last_week);
+    // The cache on disk follows, so the next start doesn't re-read the file.
+    let cached = musrv::library::Library::load_cached(&root).unwrap();
+    let song_entry = cached.track("A/song.flac").unwrap();
+    assert_eq!(song_entry.size, Some(data.len() as u64));
+
+    // Files that cannot be tagged still get rated in the store.
+    let (status, track) = send_json(
+        &app,
+        "PUT",
+        "/api/ratings/track",
+        Some(json!({"path": "A/empty.mp3", "rating": 2})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(track["rating"], 2);
+    state.flush_tag_writes().await;
+    let leftovers = std::fs::read_dir(root.join("A")).unwrap().count();
+    assert_eq!(leftovers, 2);
+}
+
+#[tokio::test]
+async fn admin_refuses_tag_edits_on_cue_tracks() {
+    use serde_json::json;
+
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("Live")).unwrap();
+    std::fs::write(root.join("Live/show.wav"), wav(1000, 6)).unwrap();
+    std::fs::write(
+        root.join("Live/show.cue"),
+        "FILE \"show.wav\" WAVE\r\n  TRACK 01 AUDIO\r\n    INDEX 01 00:00:00\r\n  TRACK 02 AUDIO\r\n    INDEX 01 00:03:00\r\n",
+    )
+    .unwrap();
+    let rip = std::fs::read(root.join("Live/show.wav")).unwrap();
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount])
+        .with_admin_token("secret");
+    let app = musrv::server::build_router(state);
+    let admin = Some("secret");
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("PATCH")
+                .uri("/api/tracks/Live/show.01.wav")
+                .header("x-admin-token", "secret")
+                .header("content-type", "application/json")
+                .body(Body::from(json!({"title": "Opener"}).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    assert!(String::from_utf8_lossy(&bytes).contains("CUE sheet"));
+    let (status, _) = send_json_as(
+        &app,
+        admin,
+        "PATCH",
+        "/api/albums",
+        Some(json!({"folder": "Live", "album": "Live Show"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(std::fs::read(root.join("Live/show.wav")).unwrap(), rip);
+}
+
+#[tokio::test]
+async fn admin_edits_track_and_album_tags() {
+    use serde_json::json;
+
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("A")).unwrap();
+    std::fs::write(root.join("A/one.flac"), minimal_flac()).unwrap();
+    std::fs::write(root.join("A/two.flac"), minimal_flac()).unwrap();
+    write_file(&root.join("A/broken.mp3"));
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount])
+        .with_admin_token("secret");
+    let app = musrv::server::build_router(state);
+    let admin = Some("secret");
+
+    let edit = json!({"title": "Opening", "track_number": 1, "artist": "Band"});
+    let (status, _) = send_json(&app, "PATCH", "/api/tracks/A/one.flac", Some(edit.clone())).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, track) =
+        send_json_as(&app, admin, "PATCH", "/api/tracks/A/one.flac", Some(edit)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(track["title"], "Opening");
+    assert_eq!(track["track_number"], 1);
+    let (status, _) = send_json_as(
+        &app,
+        admin,
+        "PATCH",
+        "/api/tracks/A/missing.flac",
+        Some(json!({"title": "x"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send_json_as(
+        &app,
+        admin,
+        "PATCH",
+        "/api/tracks/A/one.flac",
+        Some(json!({"cover": "data:text/plain;base64,TWFu"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send_json_as(
+        &app,
+        admin,
+        "PATCH",
+        "/api/albums",
+        Some(json!({"folder": "A", "title": "Same"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let album = json!({
+        "folder": "A",
+        "album": "Debut",
+        "album_artist": "Band",
+        "cover": "data:image/png;base64,iVBORw0KGgo=",
+    });
+    let (status, resp) = send_json_as(&app, admin, "PATCH", "/api/albums", Some(album)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(resp["tracks"].as_array().unwrap().len(), 2);
+    assert_eq!(resp["failed"][0]["path"], "A/broken.mp3");
+    assert_eq!(resp["tracks"][0]["album_key"], "band/debut");
+    assert!(resp["tracks"][0]["artwork_url"].is_string());
+
+    // The library reflects the edits without a rescan.
+    let (_, folder) = send_json(&app, "GET", "/api/folder?path=A", None).await;
+    let titles: Vec<_> = folder["tracks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["album"].clone())
+        .collect();
+    assert_eq!(titles.iter().filter(|a| *a == "Debut").count(), 2);
+    let (status, resp) = send_json_as(
+        &app,
+        admin,
+        "PATCH",
+        "/api/albums",
+        Some(json!({"key": "band/debut", "genre": "Rock"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(resp["tracks"][1]["genre"], "Rock");
+}
+
+#[tokio::test]
+async fn admin_upload_streams_into_library() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    write_file(&root.join("Old/a.mp3"));
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount])
+        .with_admin_token("secret")
+        .with_max_upload_bytes(16);
+    let app = musrv::server::build_router(state.clone());
+    let upload = |uri: &str, token: &str, data: &'static [u8]| {
+        Request::builder()
+            .method("PUT")
+            .uri(uri)
+            .header("x-admin-token", token)
+            .body(Body::from(data))
+            .unwrap()
+    };
+
+    let mut rx = state.events.subscribe();
+    let res = app
+        .clone()
+        .oneshot(upload(
+            "/admin/upload/New%20Album/b.mp3",
+            "secret",
+            b"audio",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["path"], "New Album/b.mp3");
+    assert_eq!(v["size"], 5);
+    assert_eq!(v["status"], "queued");
+    assert_eq!(
+        std::fs::read(root.join("New Album/b.mp3")).unwrap(),
+        b"audio"
+    );
+    while !matches!(
+        rx.recv().await.unwrap(),
+        musrv::server::events::ServerEvent::LibraryChanged { .. }
+    ) {}
+    assert!(state.find_track("New Album/b.mp3").is_some());
+
+    let cases: [(&str, &str, &'static [u8], StatusCode); 7] = [
+        (
+            "/admin/upload/x.mp3",
+            "wrong",
+            b"a",
+            StatusCode::UNAUTHORIZED,
+        ),
+        (
+            "/admin/upload/New%20Album/b.mp3",
+            "secret",
+            b"a",
+            StatusCode::CONFLICT,
+        ),
+        (
+            "/admin/upload/..%2Fescape.mp3",
+            "secret",
+            b"a",
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/admin/upload/A/.hidden.mp3",
+            "secret",
+            b"a",
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/admin/upload/A/notes.txt",
+            "secret",
+            b"a",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ),
+        (
+            "/admin/upload/A/big.mp3",
+            "secret",
+            &[0; 17],
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ),
+        (
+            "/admin/upload/A/empty.mp3",
+            "secret",
+            b"",
+            StatusCode::BAD_REQUEST,
+        ),
+    ];
+    for (uri, token, data, expected) in cases {
+        let res = app.clone().oneshot(upload(uri, token, data)).await.unwrap();
+        assert_eq!(res.status(), expected, "{uri}");
+    }
+    assert!(!root.join("escape.mp3").exists());
+    // Failed uploads leave no files or temporaries behind.
+    let leftovers: Vec<_> = std::fs::read_dir(root.join("A"))
+        .map(|d| d.filter_map(|e| e.ok()).collect())
+        .unwrap_or_default();
+    assert!(leftovers.is_empty());
+
+    let res = app
+        .oneshot(upload(
+            "/admin/upload/New%20Album/b.mp3?overwrite=1",
+            "secret",
+            b"newer",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(
+        std::fs::read(root.join("New Album/b.mp3")).unwrap(),
+        b"newer"
+    );
+}
+
+#[tokio::test]
+async fn admin_upload_during_scan_is_indexed() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    write_file(&root.join("Old/a.mp3"));
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount])
+        .with_admin_token("secret");
+    let app = musrv::server::build_router(state.clone());
+    let upload = |uri: &str| {
+        Request::builder()
+            .method("PUT")
+            .uri(uri)
+            .header("x-admin-token", "secret")
+            .body(Body::from("audio"))
+            .unwrap()
+    };
+
+    // An upload to the root while a full scan runs is scanned after it.
+    let mut rx = state.events.subscribe();
+    assert!(state.schedule_scan(false));
+    assert!(
+        state.mounts[0]
+            .scan_in_progress
+            .load(std::sync::atomic::Ordering::SeqCst)
+    );
+    let res = app
+        .clone()
+        .oneshot(upload("/admin/upload/c.mp3"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "queued");
+    tokio::time::timeout(std::time::Duration::from_secs(10), async {
+        while state.find_track("c.mp3").is_none() {
+            rx.recv().await.unwrap();
+        }
+    })
+    .await
+    .expect("upload was never scanned");
+
+    // A symlink inside the root must not let an upload create folders
+    // outside it.
+    #[cfg(unix)]
+    {
+        let outside = tempdir();
+        std::os::unix::fs::symlink(outside.path(), root.join("link")).unwrap();
+        let res = app
+            .oneshot(upload("/admin/upload/link/New/d.mp3"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(!outside.path().join("New").exists());
+    }
+}
+
+#[tokio::test]
+async fn lyrics_from_sidecars_and_tags() {
+    use lofty::{Accessor, ItemKey, TagExt, TaggedFileExt};
+
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    write_file(&root.join("A/synced.mp3"));
+    std::fs::write(
+        root.join("A/synced.lrc"),
+        "[00:01.50]Hello\n[00:03.00]World\n",
+    )
+    .unwrap();
+    write_file(&root.join("A/plain.mp3"));
+    std::fs::write(root.join("A/plain.txt"), "Just words\n").unwrap();
+    // An LRC file without timestamps is plain text, whatever its extension.
+    write_file(&root.join("A/untimed.mp3"));
+    std::fs::write(root.join("A/untimed.lrc"), "[ar:Band]\nNo times here\n").unwrap();
+    write_file(&root.join("A/none.mp3"));
+    std::fs::write(root.join("A/tagged.flac"), minimal_flac()).unwrap();
+    let tag_type = lofty::read_from_path(root.join("A/tagged.flac"))
+        .unwrap()
+        .primary_tag_type();
+    let mut tag = lofty::Tag::new(tag_type);
+    tag.set_title("Tagged".into());
+    tag.insert_text(ItemKey::Lyrics, "[00:10.00]Embedded line".into());
+    tag.save_to_path(root.join("A/tagged.flac")).unwrap();
+
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount]);
+    let app = musrv::server::build_router(state);
+
+    let (status, v) = send_json(&app, "GET", "/api/lyrics/A/synced.mp3", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["source"], "file");
+    assert_eq!(v["lines"][0]["time"], 1.5);
+    assert_eq!(v["lines"][1]["text"], "World");
+
+    let (status, v) = send_json(&app, "GET", "/api/lyrics/A/tagged.flac", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["source"], "embedded");
+    assert_eq!(v["lines"][0]["text"], "Embedded line");
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/lyrics/A/plain.mp3")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        res.headers()[header::CONTENT_TYPE],
+        "text/plain; charset=utf-8"
+    );
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    assert_eq!(&bytes[..], b"Just words");
+
+    let (status, _) = send_json(&app, "GET", "/api/lyrics/A/none.mp3", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, folder) = send_json(&app, "GET", "/api/folder?path=A", None).await;
+    let kinds: Vec<_> = folder["tracks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| (t["name"].as_str().unwrap(), t["lyrics"].clone()))
+        .collect();
+    assert!(kinds.contains(&("synced.mp3", "synced".into())));
+    assert!(kinds.contains(&("plain.mp3", "plain".into())));
+    assert!(kinds.contains(&("untimed.mp3", "plain".into())));
+    assert!(kinds.contains(&("tagged.flac", "synced".into())));
+    assert!(kinds.contains(&("none.mp3", serde_json::Value::Null)));
+}
+
+#[tokio::test]
+async fn cue_sheets_split_rips_into_tracks() {
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("Live")).unwrap();
+    std::fs::write(root.join("Live/show.wav"), wav(1000, 10)).unwrap();
+    std::fs::write(
+        root.join("Live/show.cue"),
+        "PERFORMER \"The Band\"\r\nTITLE \"Live Show\"\r\nFILE \"show.wav\" WAVE\r\n  TRACK 01 AUDIO\r\n    TITLE \"Opener\"\r\n    INDEX 01 00:00:00\r\n  TRACK 02 AUDIO\r\n    TITLE \"Middle\"\r\n    PERFORMER \"Guest\"\r\n    INDEX 01 00:03:00\r\n  TRACK 03 AUDIO\r\n    TITLE \"Closer\"\r\n    INDEX 01 00:06:00\r\n",
+    )
+    .unwrap();
+
+    std::fs::write(
+        root.join("Live/lost.cue"),
+        "FILE \"Rips/lost.flac\" WAVE\r\n  TRACK 01 AUDIO\r\n    INDEX 01 00:00:00\r\n  TRACK 02 AUDIO\r\n    INDEX 01 00:03:00\r\n",
+    )
+    .unwrap();
+
+    let (lib, report) = musrv::library::Library::scan_with_progress(root.clone(), &mut |_| {});
+    let issue = report
+        .issues
+        .iter()
+        .find(|i| i.path == "Live/lost.cue")
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(issue.kind).unwrap(),
+        "cue_missing_source"
+    );
+    assert!(issue.message.contains("Live/Rips/lost.flac"));
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount]);
+    let app = musrv::server::build_router(state);
+
+    let (status, folder) = send_json(&app, "GET", "/api/folder?path=Live", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let tracks = folder["tracks"].as_array().unwrap();
+    let names: Vec<_> = tracks.iter().map(|t| t["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["show.01.wav", "show.02.wav", "show.03.wav"]);
+    assert_eq!(tracks[1]["title"], "Middle");
+    assert_eq!(tracks[1]["artist"], "Guest");
+    assert_eq!(tracks[1]["album"], "Live Show");
+    assert_eq!(tracks[1]["duration"], 3.0);
+    assert_eq!(tracks[2]["duration"], 4.0);
+    assert_eq!(tracks[1]["cue"]["file"], "Live/show.wav");
+    assert_eq!(tracks[1]["cue"]["start"], 3.0);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder.m3u8?path=Live")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 64 * 1024).await.unwrap();
+    let m3u8 = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(m3u8.contains("#EXTINF:3,Guest - Middle\r\n"));
+    assert!(m3u8.contains("\r\nhttp://h/Live/show.02.wav\r\n"));
+
+    // Enclosures give the size of the cut stream, in the rip's format.
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder.rss?path=Live")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 64 * 1024).await.unwrap();
+    let rss = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(rss.contains(
+        "<enclosure url=\"http://h/Live/show.02.wav\" length=\"12044\" type=\"audio/wav\" />"
+    ));
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/Live/show.02.wav")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "audio/wav");
+    let bytes = body::to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    assert_eq!(bytes.len(), 44 + 3000 * 4);
+    assert_eq!(&bytes[..4], b"RIFF");
+    assert_eq!(&bytes[40..44], &(3000u32 * 4).to_le_bytes());
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/Live/show.02.wav")
+                .header(header::RANGE, "bytes=40-47")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 40-47/12044");
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    assert_eq!(&bytes[..4], &(3000u32 * 4).to_le_bytes());
+    assert_eq!(&bytes[4..], &[0, 0, 0, 0]);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder.zip?path=Live")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    let find = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+    assert!(find(b"Live/show.wavRIFF"));
+    assert!(find(b"Live/show.cuePERFORMER"));
+    assert!(find(
+        b"#EXTVLCOPT:start-time=3.000\r\n#EXTVLCOPT:stop-time=6.000\r\nshow.wav\r\n"
+    ));
+}
+
+#[tokio::test]
+async fn resume_positions_for_long_form_folders() {
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    write_file(&root.join("Books/Novel/part1.mp3"));
+    write_file(&root.join("Music/song.mp3"));
+    write_file(&root.join("Books/100%25/part1.mp3"));
+
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount])
+        .with_long_form(vec!["Books/".to_string()]);
+    let app = musrv::server::build_router(state);
+
+    let (_, books) = send_json(&app, "GET", "/api/folder?path=Books/Novel", None).await;
+    assert_eq!(books["long_form"], true);
+    assert_eq!(books["tracks"][0]["long_form"], true);
+    let (_, music) = send_json(&app, "GET", "/api/folder?path=Music", None).await;
+    assert_eq!(music["long_form"], false);
+    assert_eq!(music["tracks"][0]["long_form"], false);
+
+    let save = |position: f64, client: &str| serde_json::json!({"path": "Books/Novel/part1.mp3", "position": position, "client": client});
+    let (status, v) = send_json(&app, "PUT", "/api/resume", Some(save(42.5, "phone"))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["position"], 42.5);
+    send_json(&app, "PUT", "/api/resume", Some(save(7.0, "laptop"))).await;
+    let (status, _) = send_json(&app, "PUT", "/api/resume", Some(save(-1.0, "phone"))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let uri = "/api/resume/track?path=Books/Novel/part1.mp3&client=phone";
+    let (status, v) = send_json(&app, "GET", uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["position"], 42.5);
+    let (_, v) = send_json(&app, "GET", "/api/resume?client=laptop", None).await;
+    assert_eq!(v["entries"][0]["position"], 7.0);
+    assert_eq!(
+        v["entries"][0]["track"]["relative_path"],
+        "Books/Novel/part1.mp3"
+    );
+    let (_, v) = send_json(&app, "GET", "/api/resume", None).await;
+    assert_eq!(v["entries"].as_array().unwrap().len(), 0);
+
+    // Starting over clears the position.
+    let (status, _) = send_json(&app, "PUT", "/api/resume", Some(save(0.0, "phone"))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send_json(&app, "GET", uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let uri = "/api/resume/track?path=Books/Novel/part1.mp3&client=laptop";
+    let (status, _) = send_json(&app, "DELETE", uri, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send_json(&app, "GET", uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Body paths are taken as they are, not percent-decoded again.
+    let save = serde_json::json!({"path": "Books/100%25/part1.mp3", "position": 3.0});
+    let (status, _) = send_json(&app, "PUT", "/api/resume", Some(save)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, v) = send_json(&app, "GET", "/api/resume", None).await;
+    assert_eq!(
+        v["entries"][0]["track"]["relative_path"],
+        "Books/100%25/part1.mp3"
+    );
+}
+
+#[tokio::test]
+async fn audiobooks_are_scanned_with_chapters() {
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("Books")).unwrap();
+    std::fs::write(
+        root.join("Books/novel.m4b"),
+        m4b(600, &[(0, "Opening"), (240, "Middle")]),
+    )
+    .unwrap();
+
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount])
+        .with_long_form(vec!["Books/".to_string()]);
+    let app = musrv::server::build_router(state);
+
+    let (status, v) = send_json(&app, "GET", "/api/folder?path=Books", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let book = &v["tracks"][0];
+    assert_eq!(book["relative_path"], "Books/novel.m4b");
+    assert_eq!(book["long_form"], true);
+    assert_eq!(book["duration"], 600.0);
+    let chapters = book["chapters"].as_array().unwrap();
+    assert_eq!(chapters.len(), 2);
+    assert_eq!(chapters[0]["title"], "Opening");
+    assert_eq!(chapters[0]["end"], 240.0);
+    assert_eq!(chapters[1]["title"], "Middle");
+    assert_eq!(chapters[1]["start"], 240.0);
+    assert_eq!(chapters[1]["end"], 600.0);
+}
+
+#[tokio::test]
+async fn folder_podcast_feed() {
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("Show")).unwrap();
+    std::fs::write(root.join("Show/ep1.mp3"), b"0123456789").unwrap();
+    std::fs::write(root.join("Show/ep2.flac"), minimal_flac()).unwrap();
+
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount]);
+    let app = musrv::server::build_router(state);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/folder.rss?path=Show")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()[header::CONTENT_TYPE],
+        "application/rss+xml; charset=utf-8"
+    );
+    let bytes = body::to_bytes(res.into_body(), 64 * 1024).await.unwrap();
+    let rss = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(rss.contains("xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\""));
+    assert!(rss.contains("<title>Show</title>"));
+    assert!(
+        rss.contains(
+            "<enclosure url=\"http://h/Show/ep1.mp3\" length=\"10\" type=\"audio/mpeg\" />"
+        )
+    );
+    let flac_len = minimal_flac().len();
+    assert!(rss.contains(&format!(
+        "<enclosure url=\"http://h/Show/ep2.flac\" length=\"{flac_len}\" type=\"audio/flac\" />"
+    )));
+    assert_eq!(rss.matches("<pubDate>").count(), 2);
+    assert!(rss.contains(" GMT</pubDate>"));
+}
+
+#[tokio::test]
+async fn recently_added_groups_and_feed() {
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    write_file(&root.join("Old/a.mp3"));
+    write_file(&root.join("New/b.mp3"));
+    write_file(&root.join("New/c.mp3"));
+    let old = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_577_836_800);
+    std::fs::File::options()
+        .write(true)
+        .open(root.join("Old/a.mp3"))
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount]);
+    let app = musrv::server::build_router(state);
+
+    let (status, v) = send_json(&app, "GET", "/api/recent?days=30&group=folder", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["days"], 30);
+    let groups = v["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0]["key"], "folder:New");
+    assert_eq!(groups[0]["title"], "New");
+    assert_eq!(groups[0]["tracks"].as_array().unwrap().len(), 2);
+    assert!(groups[0]["tracks"][0]["first_seen"].as_u64().is_some());
+
+    let (_, v) = send_json(&app, "GET", "/api/recent?days=3650", None).await;
+    assert_eq!(v["groups"].as_array().unwrap().len(), 2);
+    assert_eq!(v["groups"][1]["added_at"], 1_577_836_800);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/recent.atom")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()[header::CONTENT_TYPE],
+        "application/atom+xml; charset=utf-8"
+    );
+    let bytes = body::to_bytes(res.into_body(), 64 * 1024).await.unwrap();
+    let atom = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(atom.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+    assert_eq!(atom.matches("<entry>").count(), 1);
+    assert!(atom.contains("href=\"http://h/api/folder.m3u8?path=New\""));
+}
+
+#[tokio::test]
+async fn random_tracks_and_album_shuffle() {
+    use serde_json::json;
+
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    for album in ["A", "B", "C"] {
+        for n in 1..=3 {
+            write_file(&root.join(format!("{album}/{n}.mp3")));
+        }
+    }
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount]);
+    let app = musrv::server::build_router(state);
+
+    let (status, v) = send_json(&app, "GET", "/api/random?count=4", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["mode"], "tracks");
+    let tracks = v["tracks"].as_array().unwrap();
+    assert_eq!(tracks.len(), 4);
+    let paths: std::collections::HashSet<_> =
+        tracks.iter().map(|t| t["relative_path"].as_str()).collect();
+    assert_eq!(paths.len(), 4);
+
+    let (_, v) = send_json(&app, "GET", "/api/random?count=50&path=B", None).await;
+    let tracks = v["tracks"].as_array().unwrap();
+    assert_eq!(tracks.len(), 3);
+    assert!(
+        tracks
+            .iter()
+            .all(|t| t["relative_path"].as_str().unwrap().starts_with("B/"))
+    );
+
+    // Albums come back whole and in track order.
+    let (_, v) = send_json(&app, "GET", "/api/random?mode=albums&count=2", None).await;
+    assert_eq!(v["mode"], "albums");
+    let paths: Vec<String> = v["tracks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["relative_path"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(paths.len(), 6);
+    for album in paths.chunks(3) {
+        let folder = &album[0][..1];
+        let expected: Vec<String> = (1..=3).map(|n| format!("{folder}/{n}.mp3")).collect();
+        assert_eq!(album, expected.as_slice());
+    }
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/api/scrobble",
+        Some(json!({"path": "A/1.mp3"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, v) = send_json(
+        &app,
+        "GET",
+        "/api/random?count=50&exclude_days=1&weight=plays&spread_artists=1",
+        None,
+    )
+    .await;
+    let tracks = v["tracks"].as_array().unwrap();
+    assert_eq!(tracks.len(), 8);
+    assert!(tracks.iter().all(|t| t["relative_path"] != "A/1.mp3"));
+    let (_, v) = send_json(&app, "GET", "/api/random?mode=albums&exclude_days=1", None).await;
+    assert_eq!(v["tracks"].as_array().unwrap().len(), 6);
+
+    let (status, _) = send_json(&app, "GET", "/api/random?weight=loud", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let res = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/random.m3u8?count=2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let bytes = body::to_bytes(res.into_body(), 64 * 1024).await.unwrap();
+    let m3u = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(m3u.starts_with("#EXTM3U"));
+    assert_eq!(m3u.matches("http://h/").count(), 2);
+}
+
+#[tokio::test]
+async fn admin_reports_duplicates() {
+    let tmp = tempdir();
+    let root = tmp.path().to_path_buf();
+    std::fs::create_dir_all(root.join("Old")).unwrap();
+    std::fs::create_dir_all(root.join("New")).unwrap();
+    std::fs::write(root.join("Old/song.flac"), minimal_flac()).unwrap();
+    std::fs::write(root.join("New/song.flac"), minimal_flac()).unwrap();
+    write_file(&root.join("New/other.mp3"));
+
+    let lib = musrv::library::Library::scan(root.clone());
+    let mount = musrv::server::state::Mount::new("", root.clone(), lib, true);
+    let state = musrv::server::AppState::new("http://h/".to_string(), vec![mount])
+        .with_admin_token("secret");
+    let app = musrv::server::build_router(state);
+
+    let (status, _) = send_json(&app, "GET", "/admin/duplicates", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, v) = send_json_as(&app, Some("secret"), "GET", "/admin/duplicates", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let groups = v["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0]["kind"], "exact");
+    let tracks = groups[0]["tracks"].as_array().unwrap();
+    assert_eq!(tracks[0]["path"], "New/song.flac");
+    assert_eq!(tracks[1]["path"], "Old/song.flac");
+    assert_eq!(tracks[0]["format"], "flac");
+    assert_eq!(tracks[0]["size"], minimal_flac().len());
+
+    let (status, _) = send_json_as(
+        &app,
+        Some("secret"),
+        "GET",
+        "/admin/duplicates?tolerance=-1",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn loudness_analysis_exposes_replay_gain() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    std::fs::create_dir_all(root.join("Album")).unwrap();
+    std::fs::write(root.join("Album/tone.wav"), sine_wav(48_000, 2, 0.1)).unwrap();
+    write_file(&root.join("Album/broken.mp3"));
+
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            musrv::library::Library::empty(root.clone()),
+            false,
+        )],
+    )
+    .with_loudness_analysis(true);
+    let app = musrv::server::build_router(state.clone());
+    let mut rx = state.events.subscribe();
+    assert!(state.schedule_scan(false));
+    // The scan and then the analysis each publish a new revision.
+    loop {
+        if let musrv::server::events::ServerEvent::LibraryChanged { revision } =
+            rx.recv().await.unwrap()
+            && revision == 3
+        {
+            break;
+        }
+    }
+
+    let (status, v) = send_json(&app, "GET", "/api/folder?path=Album", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let tracks = v["tracks"].as_array().unwrap();
+    let tone = tracks.iter().find(|t| t["name"] == "tone.wav").unwrap();
+    let gain = &tone["replay_gain"];
+    assert_eq!(gain["source"], "analysis");
+    let loudness = gain["loudness"].as_f64().unwrap();
+    assert!((loudness + 20.0).abs() < 0.1, "{loudness}");
+    let track_gain = gain["track_gain"].as_f64().unwrap();
+    assert!((track_gain - 2.0).abs() < 0.1, "{track_gain}");
+    assert!((gain["track_peak"].as_f64().unwrap() - 0.1).abs() < 0.01);
+    let broken = tracks.iter().find(|t| t["name"] == "broken.mp3").unwrap();
+    assert!(broken.get("replay_gain").is_none());
+
+    // Measurements are kept in the cache.
+    let cached = musrv::library::Library::load_cached(&root).unwrap();
+    assert!(cached.tracks().iter().any(|t| t.loudness.is_some()));
+}
+
+#[tokio::test]
+async fn waveform_peaks_are_cached() {
+    let tmp = tempdir();
+    let root = std::fs::canonicalize(tmp.path()).unwrap();
+    std::fs::create_dir_all(root.join("Album")).unwrap();
+    std::fs::write(root.join("Album/tone.wav"), sine_wav(48_000, 2, 0.1)).unwrap();
+    write_file(&root.join("Album/broken.mp3"));
+
+    let lib = musrv::library::Library::scan(root.clone());
+    let state = musrv::server::AppState::new(
+        "http://h/".to_string(),
+        vec![musrv::server::state::Mount::new(
+            "",
+            root.clone(),
+            lib,
+            false,
+        )],
+    );
+    let waveforms = state.data_dir.join(musrv::waveform::WAVEFORM_DIR);
+    let app = musrv::server::build_router(state);
+
+    let (status, v) = send_json(&app, "GET", "/api/waveform/Album/tone.wav?points=50", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["points"], 50);
+    assert!((v["duration"].as_f64().unwrap() - 2.0).abs() < 0.01);
+    let max = v["max"].as_array().unwrap();
+    let min = v["min"].as_array().unwrap();
+    assert_eq!(max.len(), 50);
+    assert!(max.iter().all(|p| (p.as_f64().unwrap() - 0.1).abs() < 0.01));
+    assert!(min.iter().all(|p| (p.as_f64().unwrap() + 0.1).abs() < 0.01));
+    assert_eq!(std::fs::read_dir(&waveforms).unwrap().count(), 1);
+
+    // New tags leave the audio and so the cache entry as they were.
+    let mut retagged = sine_wav(48_000, 2, 0.1);
+    retagged.extend_from_slice(b"LIST\x04\0\0\0INFO");
+    let riff_len = (retagged.len() - 8) as u32;
+    retagged[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    std::fs::write(root.join("Album/tone.wav"), retagged).unwrap();
+    let (status, _) = send_json(&app, "GET", "/api/waveform/Album/tone.wav", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(std::fs::read_dir(&waveforms).unwrap().count(), 1);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/waveform/Album/tone.wav?points=20&format=bin")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()[header::CONTENT_TYPE],
+        "application/octet-stream"
+    );
+    let bytes = body::to_bytes(res.into_body(), 1024).await.unwrap();
+    assert_eq!(bytes.len(), 40);
+    assert_eq!(bytes[1] as i8, 13);
+
+    let (status, _) = send_json(&app, "GET", "/api/waveform/Album/tone.wav?format=png", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send_json(&app, "GET", "/api/waveform/Album/broken.mp3", None).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = send_json(&app, "GET", "/api/waveform/Album/missing.wav", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}